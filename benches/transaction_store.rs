@@ -0,0 +1,140 @@
+//! Compares the compact transaction store with the hash map ones - the previous `HashMap<u32, TransactionDetails>`
+//! and the current `HashMapTransactionStore` of records.
+//! Run with "cargo bench --bench transaction_store"
+
+use base_transaction::transaction_store::{CompactTransactionStore, TransactionRecord};
+use base_transaction::{TransactionDetails, TransactionType};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::mem::size_of;
+use std::time::Instant;
+
+const DENSE_TRANSACTIONS: u32 = 1_000_000;
+/// Each sparse id takes its own page of the compact store - fewer of them to keep the memory reasonable
+const SPARSE_TRANSACTIONS: u32 = 10_000;
+
+fn transaction(tx: u32) -> TransactionDetails {
+    TransactionDetails {
+        transaction_type: TransactionType::Deposit,
+        client: (tx % u16::MAX as u32) as u16,
+        tx,
+        amount: Some(tx as f32),
+        ..Default::default()
+    }
+}
+
+/// Pseudo-random ids spread over the whole u32 range - linear congruential generator
+fn sparse_ids(count: u32) -> Vec<u32> {
+    let mut state: u32 = 12345;
+    (0..count)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            state
+        })
+        .collect()
+}
+
+/// Approximate memory of hashbrown map - entries and control bytes
+fn hash_map_memory<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+fn bench_hash_map(ids: &[u32]) {
+    let start = Instant::now();
+    let mut store: HashMap<u32, TransactionDetails> = HashMap::new();
+    for tx in ids {
+        store.insert(*tx, transaction(*tx));
+    }
+    let insert = start.elapsed();
+
+    let start = Instant::now();
+    for tx in ids {
+        black_box(store.get(tx).map(|d| d.amount));
+    }
+    let lookup = start.elapsed();
+
+    report(
+        "HashMap<u32, TransactionDetails>",
+        ids.len(),
+        insert,
+        lookup,
+        hash_map_memory(&store),
+    );
+}
+
+fn bench_record_hash_map(ids: &[u32]) {
+    let start = Instant::now();
+    let mut store: HashMap<u32, TransactionRecord> = HashMap::new();
+    for tx in ids {
+        store.insert(*tx, TransactionRecord::new(&transaction(*tx), *tx as u64));
+    }
+    let insert = start.elapsed();
+
+    let start = Instant::now();
+    for tx in ids {
+        black_box(store.get(tx).map(|d| d.amount));
+    }
+    let lookup = start.elapsed();
+
+    report(
+        "HashMapTransactionStore",
+        ids.len(),
+        insert,
+        lookup,
+        hash_map_memory(&store),
+    );
+}
+
+fn bench_compact_store(ids: &[u32]) {
+    let start = Instant::now();
+    let mut store = CompactTransactionStore::default();
+    for tx in ids {
+        store.insert(*tx, TransactionRecord::new(&transaction(*tx), *tx as u64));
+    }
+    let insert = start.elapsed();
+
+    let start = Instant::now();
+    for tx in ids {
+        black_box(store.get(*tx).map(|d| d.amount));
+    }
+    let lookup = start.elapsed();
+
+    report(
+        "CompactTransactionStore",
+        ids.len(),
+        insert,
+        lookup,
+        store.memory_usage(),
+    );
+}
+
+fn report(
+    name: &str,
+    transactions: usize,
+    insert: std::time::Duration,
+    lookup: std::time::Duration,
+    memory: usize,
+) {
+    println!(
+        "{:<34} insert: {:>8.2?}  lookup: {:>8.2?}  memory: {:>6} MiB ({:.1} bytes/tx)",
+        name,
+        insert,
+        lookup,
+        memory / 1024 / 1024,
+        memory as f64 / transactions as f64
+    );
+}
+
+fn main() {
+    let dense: Vec<u32> = (0..DENSE_TRANSACTIONS).collect();
+    println!("{} transactions with dense ids", DENSE_TRANSACTIONS);
+    bench_hash_map(&dense);
+    bench_record_hash_map(&dense);
+    bench_compact_store(&dense);
+
+    let sparse = sparse_ids(SPARSE_TRANSACTIONS);
+    println!("{} transactions with sparse ids", SPARSE_TRANSACTIONS);
+    bench_hash_map(&sparse);
+    bench_record_hash_map(&sparse);
+    bench_compact_store(&sparse);
+}
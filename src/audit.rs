@@ -0,0 +1,346 @@
+use crate::rejection::Rejection;
+use crate::{ClientState, TransactionDetails};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Previous hash of the first record
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Record of the audit log - one JSON line per processed action
+#[derive(Serialize, Debug)]
+struct AuditRecord<'a> {
+    /// Sequence number of the record - starts from 1
+    seq: u64,
+    /// Processing row of the action
+    row: u64,
+    details: &'a TransactionDetails,
+    /// applied or rejection reason
+    outcome: &'static str,
+    /// Client state after the action - none if the client is not present
+    state: Option<&'a ClientState>,
+    /// Hash of the previous record
+    prev_hash: &'a str,
+    /// Hash of this record - sha256 of the record without the hash field
+    hash: String,
+}
+
+/// Last record of the log kept in the sidecar file - to detect truncated tail of the log
+#[derive(Serialize, Deserialize, Debug)]
+struct AuditHead {
+    /// Sequence number of the last record
+    seq: u64,
+    /// Hash of the last record
+    hash: String,
+}
+
+/// Sidecar file of the log head - next to the log, i.e. audit.jsonl.head
+fn head_path(path: &Path) -> PathBuf {
+    let mut head = path.as_os_str().to_owned();
+    head.push(".head");
+    PathBuf::from(head)
+}
+
+/// Hash of the JSON record - computed on the record without hash field, with sorted keys
+fn record_hash(record: &serde_json::Value) -> Result<String, anyhow::Error> {
+    let mut record = record.clone();
+    record
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Audit record is not an object"))?
+        .remove("hash");
+
+    Ok(hex::encode(Sha256::digest(record.to_string().as_bytes())))
+}
+
+/// Append-only audit log with hash chain - each record holds the hash of the previous one
+pub struct AuditLog {
+    writer: Box<dyn Write>,
+    /// Sidecar file of the log head - none if the log isn't a file
+    head_path: Option<PathBuf>,
+    /// Sequence number of the last record
+    seq: u64,
+    /// Hash of the last record
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Create audit log writing to the provided writer - chain starts from the genesis hash
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer,
+            head_path: None,
+            seq: 0,
+            last_hash: GENESIS_HASH.to_string(),
+        }
+    }
+
+    /// Open audit log file - new records continue the chain of the existing ones
+    /// Existing records are verified against the head file, which is updated after each record
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let summary = verify_audit_file(path)?;
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Box::new(BufWriter::new(file)),
+            head_path: Some(head_path(path)),
+            seq: summary.records,
+            last_hash: summary.last_hash,
+        })
+    }
+
+    /// Append record of the processed action
+    pub fn append(
+        &mut self,
+        row: u64,
+        details: &TransactionDetails,
+        result: &Result<(), Rejection>,
+        state: Option<&ClientState>,
+    ) -> Result<(), anyhow::Error> {
+        let mut record = AuditRecord {
+            seq: self.seq + 1,
+            row,
+            details,
+            outcome: match result {
+                Ok(()) => "applied",
+                Err(reason) => reason.as_str(),
+            },
+            state,
+            prev_hash: &self.last_hash,
+            hash: String::new(),
+        };
+        // Hash is computed on the parsed record - the same way as on verification
+        record.hash = record_hash(&serde_json::from_str(&serde_json::to_string(&record)?)?)?;
+
+        let line = serde_json::to_string(&record)?;
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+
+        self.seq = record.seq;
+        self.last_hash = record.hash;
+        self.write_head()
+    }
+
+    /// Replace the head file with the last record - written aside and renamed, so it's never partial
+    fn write_head(&self) -> Result<(), anyhow::Error> {
+        let path = match self.head_path.as_ref() {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        let head = AuditHead {
+            seq: self.seq,
+            hash: self.last_hash.clone(),
+        };
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(&head)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Result of the audit log verification
+#[derive(Debug)]
+pub struct AuditSummary {
+    /// Number of verified records
+    pub records: u64,
+    /// Hash of the last record - to compare with externally kept one
+    pub last_hash: String,
+}
+
+/// Verify hash chain of the audit log
+/// Fails on the first edited, dropped or reordered record with its line number
+/// Truncated tail can't be detected - see verify_audit_file
+pub fn verify_audit<R: BufRead>(reader: R) -> Result<AuditSummary, anyhow::Error> {
+    verify_chain(reader, None)
+}
+
+/// Verify hash chain of the audit log file and compare it with the head file
+/// Fails if the log is truncated - the record of the head is missing or changed
+/// Log without the head file is verified by its chain only, missing log - as the empty one
+pub fn verify_audit_file<P: AsRef<Path>>(path: P) -> Result<AuditSummary, anyhow::Error> {
+    let path = path.as_ref();
+    let head_path = head_path(path);
+    let head: Option<AuditHead> = match head_path.exists() {
+        true => Some(
+            serde_json::from_str(&std::fs::read_to_string(&head_path)?)
+                .map_err(|e| anyhow!("Invalid head file {}: {}", head_path.display(), e))?,
+        ),
+        false => None,
+    };
+
+    match path.exists() {
+        true => verify_chain(BufReader::new(File::open(path)?), head.as_ref()),
+        false => verify_chain(std::io::empty(), head.as_ref()),
+    }
+}
+
+/// Verify hash chain of the log - the record of the head should be present with the same hash
+fn verify_chain<R: BufRead>(
+    reader: R,
+    head: Option<&AuditHead>,
+) -> Result<AuditSummary, anyhow::Error> {
+    let mut summary = AuditSummary {
+        records: 0,
+        last_hash: GENESIS_HASH.to_string(),
+    };
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line_number = idx + 1;
+        let line = line?;
+        let record: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("Line {}: invalid record: {}", line_number, e))?;
+
+        let seq = record["seq"].as_u64();
+        if seq != Some(summary.records + 1) {
+            return Err(anyhow!(
+                "Line {}: sequence {:?} is expected to be {} - record is dropped or reordered",
+                line_number,
+                seq,
+                summary.records + 1
+            ));
+        } else if record["prev_hash"].as_str() != Some(summary.last_hash.as_str()) {
+            return Err(anyhow!(
+                "Line {}: previous hash mismatch - previous record is changed or dropped",
+                line_number
+            ));
+        }
+
+        let hash = record_hash(&record)?;
+        if record["hash"].as_str() != Some(hash.as_str()) {
+            return Err(anyhow!(
+                "Line {}: hash mismatch - record is changed",
+                line_number
+            ));
+        }
+
+        summary.records += 1;
+        summary.last_hash = hash;
+        if head
+            .filter(|d| d.seq == summary.records && d.hash != summary.last_hash)
+            .is_some()
+        {
+            return Err(anyhow!(
+                "Line {}: hash differs from the head file - log is rewritten",
+                line_number
+            ));
+        }
+    }
+
+    match head.filter(|d| d.seq > summary.records) {
+        Some(head) => Err(anyhow!(
+            "Log is truncated - {} records, the head file refers to record {}",
+            summary.records,
+            head.seq
+        )),
+        None => Ok(summary),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audit::{head_path, verify_audit, verify_audit_file, AuditLog, GENESIS_HASH};
+    use crate::rejection::Rejection;
+    use crate::{ClientState, TransactionDetails, TransactionType};
+    use std::io::BufReader;
+
+    fn write_log(path: &std::path::Path, first_tx: u32) {
+        let mut log = AuditLog::open(path).unwrap();
+        for tx in first_tx..first_tx + 3 {
+            let details = TransactionDetails {
+                transaction_type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(1.5),
+                ..Default::default()
+            };
+            let state = ClientState {
+                client: 1,
+                available: 1.5 * tx as f32,
+                total: 1.5 * tx as f32,
+                ..Default::default()
+            };
+            let result = match tx % 2 {
+                0 => Err(Rejection::DuplicateTx),
+                _ => Ok(()),
+            };
+            log.append(tx as u64, &details, &result, Some(&state))
+                .unwrap();
+        }
+    }
+
+    fn verify(content: &str) -> Result<u64, String> {
+        verify_audit(BufReader::new(content.as_bytes()))
+            .map(|d| d.records)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    pub fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        write_log(&path, 1);
+        // Reopened log continues the chain
+        write_log(&path, 4);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].contains(GENESIS_HASH));
+        assert!(lines[1].contains("\"outcome\":\"duplicate_tx\""));
+        assert_eq!(verify(&content), Ok(6));
+
+        // Edited amount
+        let edited = content.replacen("\"amount\":1.5", "\"amount\":2.5", 1);
+        assert!(verify(&edited)
+            .unwrap_err()
+            .contains("Line 1: hash mismatch"));
+
+        // Dropped line
+        let dropped = [lines[0], lines[2], lines[3]].join("\n");
+        assert!(verify(&dropped).unwrap_err().contains("Line 2: sequence"));
+
+        // Dropped line with fixed sequence - the chain is still broken
+        let fixed_seq = [lines[0], &lines[2].replace("\"seq\":3", "\"seq\":2")].join("\n");
+        assert!(verify(&fixed_seq)
+            .unwrap_err()
+            .contains("Line 2: previous hash mismatch"));
+
+        assert!(verify("not json").is_err());
+        assert_eq!(verify(""), Ok(0));
+    }
+
+    #[test]
+    pub fn test_truncated_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        write_log(&path, 1);
+        assert_eq!(verify_audit_file(&path).unwrap().records, 3);
+        assert!(head_path(&path).exists());
+
+        // Dropped tail keeps the chain valid - only the head file reveals it
+        let content = std::fs::read_to_string(&path).unwrap();
+        let truncated: Vec<&str> = content.lines().take(2).collect();
+        std::fs::write(&path, truncated.join("\n") + "\n").unwrap();
+        assert_eq!(verify(&std::fs::read_to_string(&path).unwrap()), Ok(2));
+        let error = verify_audit_file(&path).unwrap_err().to_string();
+        assert!(error.contains("Log is truncated - 2 records"), "{}", error);
+        assert!(
+            AuditLog::open(&path).is_err(),
+            "Truncated log isn't continued"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(verify_audit_file(&path).is_err());
+
+        // Log without the head file is verified by its chain only
+        std::fs::remove_file(head_path(&path)).unwrap();
+        assert_eq!(verify_audit_file(&path).unwrap().records, 0);
+        write_log(&path, 1);
+        assert_eq!(verify_audit_file(&path).unwrap().records, 3);
+    }
+}
@@ -1,8 +1,33 @@
 use serde::Deserialize;
 use serde::Serialize;
+use std::fmt;
+
+/// Status of the client account
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Locked by chargeback or by admin - actions are checked by the lock policy, can be unlocked by admin
+    Locked,
+    /// Closed by admin - final status, funds movements and admin actions are rejected
+    /// Disputes, reversals and reviews of its earlier transactions are still processed
+    Closed,
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Locked => "locked",
+            AccountStatus::Closed => "closed",
+        })
+    }
+}
 
 /// Current client state
 #[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(from = "ClientStateRow", into = "ClientStateRow")]
 pub struct ClientState {
     /// Client id.
     pub client: u16,
@@ -10,8 +35,104 @@ pub struct ClientState {
     pub available: f32,
     /// The total funds that are held for dispute.
     pub held: f32,
-    /// The total funds that are available or held.
+    /// The total funds that are available, held or pending.
     pub total: f32,
-    /// Whether the account is locked.
-    pub locked: bool,
+    /// Status of the account.
+    pub status: AccountStatus,
+    /// The total fees collected from the client.
+    pub fees: f32,
+    /// The total funds of the transactions held until their review.
+    pub pending: f32,
+}
+
+impl ClientState {
+    /// Whether the account is locked or closed
+    pub fn is_locked(&self) -> bool {
+        self.status != AccountStatus::Active
+    }
+}
+
+/// Csv/JSON representation of the client state
+/// Locked column is kept for backward compatibility - status is derived from it if not provided
+#[derive(Deserialize, Serialize)]
+struct ClientStateRow {
+    client: u16,
+    available: f32,
+    held: f32,
+    total: f32,
+    locked: bool,
+    #[serde(default)]
+    fees: f32,
+    #[serde(default)]
+    status: Option<AccountStatus>,
+    #[serde(default)]
+    pending: f32,
+}
+
+impl From<ClientStateRow> for ClientState {
+    fn from(row: ClientStateRow) -> Self {
+        Self {
+            client: row.client,
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            status: row.status.unwrap_or(match row.locked {
+                true => AccountStatus::Locked,
+                false => AccountStatus::Active,
+            }),
+            fees: row.fees,
+            pending: row.pending,
+        }
+    }
+}
+
+impl From<ClientState> for ClientStateRow {
+    fn from(state: ClientState) -> Self {
+        Self {
+            client: state.client,
+            available: state.available,
+            held: state.held,
+            total: state.total,
+            locked: state.is_locked(),
+            fees: state.fees,
+            status: Some(state.status),
+            pending: state.pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{AccountStatus, ClientState};
+
+    #[test]
+    pub fn test_status_columns() {
+        // Files without status column - status is derived from the locked one
+        let data = "client,available,held,total,locked
+1,1.0,0.0,1.0,false
+2,1.0,0.0,1.0,true
+";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let states: Vec<ClientState> = rdr.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(states[0].status, AccountStatus::Active);
+        assert_eq!(states[1].status, AccountStatus::Locked);
+
+        // Status column takes precedence
+        let data = "client,available,held,total,locked,fees,status
+1,1.0,0.0,1.0,true,0.0,closed
+";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let states: Vec<ClientState> = rdr.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(states[0].status, AccountStatus::Closed);
+        assert!(states[0].is_locked());
+
+        // Locked column is still written - closed account is reported as locked
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&states[0]).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked,fees,status,pending\n1,1.0,0.0,1.0,true,0.0,closed,0.0\n"
+        );
+    }
 }
@@ -1,142 +1,542 @@
-use crate::ClientState;
-use std::collections::HashMap;
+use crate::client_state_store::{ClientStateStore, InMemoryClientStateStore};
+use crate::rejection::Rejection;
+use crate::{AccountStatus, ClientState, TransactionType};
 
 /// Interface to manage clients states
 pub struct ClientsStatesMgr {
-    clients_states: HashMap<u16, ClientState>,
+    clients_states: Box<dyn ClientStateStore>,
+    /// Failure of the state store - the action is rejected with store_failure
+    store_error: Option<anyhow::Error>,
 }
 
-/// Note: Now we allow double actions on locked account - to skip uncomment get_client_details
+/// Note: Actions on locked account are checked by the processor based on the lock policy
 impl ClientsStatesMgr {
-    /// Create state manager
+    /// Create state manager with in-memory store
     pub fn new() -> Self {
+        Self::with_store(Box::<InMemoryClientStateStore>::default())
+    }
+
+    /// Create state manager with the provided store of clients states
+    pub fn with_store(store: Box<dyn ClientStateStore>) -> Self {
         Self {
-            clients_states: Default::default(),
+            clients_states: store,
+            store_error: None,
         }
     }
 
     /// Get current states of all clients
-    pub fn get_states(&self) -> Vec<ClientState> {
-        self.clients_states.values().cloned().collect()
+    pub fn get_states(&self) -> Result<Vec<ClientState>, anyhow::Error> {
+        self.clients_states.iterate()
+    }
+
+    /// Get current state of the client
+    pub fn get_state(&self, client_id: u16) -> Result<Option<ClientState>, anyhow::Error> {
+        self.clients_states.get(client_id)
+    }
+
+    /// Take the error of the failed store - if the action is rejected with store_failure
+    pub fn take_store_error(&mut self) -> Option<anyhow::Error> {
+        self.store_error.take()
+    }
+
+    /// Restore the state of the client - none removes the client, i.e. created by rolled back action
+    pub fn restore_state(
+        &mut self,
+        client_id: u16,
+        state: Option<ClientState>,
+    ) -> Result<(), anyhow::Error> {
+        match state {
+            Some(state) => self.clients_states.upsert(state),
+            None => self.clients_states.remove(client_id),
+        }
+    }
+
+    /// Persist pending changes of the store
+    pub fn flush(&mut self) -> Result<(), anyhow::Error> {
+        self.clients_states.flush()
     }
 
     /// Apply deposit - i.e. increase available funds
-    /// returns state of the operation - now it always true/success
-    pub fn apply_deposit(&mut self, client_id: u16, amount: f32) -> bool {
-        let data = self
+    /// Now it always succeeds - new client is created if not present
+    pub fn apply_deposit(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        let mut data = self
             .clients_states
-            .entry(client_id)
-            .or_insert_with(|| ClientState {
+            .get(client_id)
+            .map_err(|e| self.store_failure(e))?
+            .unwrap_or(ClientState {
                 client: client_id,
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: false,
+                ..Default::default()
             });
 
         data.available += amount;
         data.total += amount;
 
-        true
+        self.save(data)
+    }
+
+    /// Apply deposit with its fee in one update - the fee is charged from the deposited amount
+    /// Held deposit is credited to the pending bucket until its review
+    /// Now it always succeeds - new client is created if not present
+    pub fn apply_charged_deposit(
+        &mut self,
+        client_id: u16,
+        amount: f32,
+        fee: f32,
+        held: bool,
+    ) -> Result<(), Rejection> {
+        let mut data = self
+            .clients_states
+            .get(client_id)
+            .map_err(|e| self.store_failure(e))?
+            .unwrap_or(ClientState {
+                client: client_id,
+                ..Default::default()
+            });
+
+        match held {
+            true => data.pending += amount - fee,
+            false => data.available += amount - fee,
+        }
+        data.total += amount - fee;
+        data.fees += fee;
+
+        self.save(data)
+    }
+
+    /// Apply withdrawal with its fee in one update
+    /// Held withdrawal is moved to the pending bucket - it's taken out of the account when approved
+    /// Fails if client is not present or available less than the amount and fee
+    pub fn apply_charged_withdrawal(
+        &mut self,
+        client_id: u16,
+        amount: f32,
+        fee: f32,
+        held: bool,
+    ) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.available < amount + fee {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.available -= amount + fee;
+            match held {
+                true => data.pending += amount,
+                false => data.total -= amount,
+            }
+            data.total -= fee;
+            data.fees += fee;
+
+            Ok(())
+        })
     }
 
     /// Apply withdrawal on clients account - decrease funds
-    /// returns state of the operation - false if can't apply withdrawal
-    pub fn apply_withdrawal(&mut self, client_id: u16, amount: f32) -> bool {
-        let data = self
-            .get_client_details(client_id)
+    /// Fails if client is not present or available less than the amount
+    pub fn apply_withdrawal(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
             // available amount shouldn't be less!
-            .filter(|d| d.available >= amount);
+            if data.available < amount {
+                return Err(Rejection::InsufficientFunds);
+            }
 
-        if data.is_none() {
-            return false;
-        }
+            data.available -= amount;
+            data.total -= amount;
 
-        let data = data.unwrap();
+            Ok(())
+        })
+    }
 
-        data.available -= amount;
-        data.total -= amount;
+    /// Move funds from the source client to the destination one and charge the fee from the source
+    /// Both clients are updated or none
+    /// Held transfer is credited to pending funds of the destination - until it's approved
+    /// Fails if any client is not present or available funds of the source are less than the amount and fee
+    pub fn apply_transfer(
+        &mut self,
+        from: u16,
+        to: u16,
+        amount: f32,
+        fee: f32,
+        held: bool,
+    ) -> Result<(), Rejection> {
+        if from == to {
+            return Err(Rejection::InvalidDestination);
+        }
+        let before = self.get_client_details(from)?;
+        let mut source = before.clone();
+        let mut destination = self.get_client_details(to)?;
+        if source.available < amount + fee {
+            return Err(Rejection::InsufficientFunds);
+        }
+
+        source.available -= amount + fee;
+        source.total -= amount + fee;
+        source.fees += fee;
+        match held {
+            true => destination.pending += amount,
+            false => destination.available += amount,
+        }
+        destination.total += amount;
 
-        true
+        self.save_both(source, destination, before)
     }
 
-    /// Apply dispute on client state
-    /// Returns state - false if client is not present of available less than the amount
-    pub fn apply_dispute(&mut self, client_id: u16, amount: f32) -> bool {
-        let data = self
-            .get_client_details(client_id)
-            .filter(|d| d.available >= amount);
-        if data.is_none() {
-            return false;
+    /// Undo the declined transfer - funds are moved back and the fee is refunded to the source
+    /// Held transfer is taken from pending funds of the destination, otherwise from available ones
+    /// Fails if any client is not present or the destination funds are not enough to take it back
+    pub fn apply_transfer_decline(
+        &mut self,
+        from: u16,
+        to: u16,
+        amount: f32,
+        fee: f32,
+        held: bool,
+    ) -> Result<(), Rejection> {
+        let before = self.get_client_details(from)?;
+        let mut source = before.clone();
+        let mut destination = self.get_client_details(to)?;
+        let funds = match held {
+            true => &mut destination.pending,
+            false => &mut destination.available,
+        };
+        if *funds < amount || source.fees < fee {
+            return Err(Rejection::InsufficientFunds);
         }
 
-        let data = data.unwrap();
+        *funds -= amount;
+        destination.total -= amount;
+        source.available += amount + fee;
+        source.total += amount + fee;
+        source.fees -= fee;
+
+        self.save_both(source, destination, before)
+    }
 
-        data.available -= amount;
-        data.held += amount;
+    /// Apply dispute on client state
+    /// Fails if client is not present or available less than the amount
+    pub fn apply_dispute(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.available < amount {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.available -= amount;
+            data.held += amount;
+
+            Ok(())
+        })
+    }
 
-        true
+    /// Apply dispute on client state even if available funds are less than the amount
+    /// Available funds become negative. Fails if client is not present
+    pub fn apply_overdrawn_dispute(
+        &mut self,
+        client_id: u16,
+        amount: f32,
+    ) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            data.available -= amount;
+            data.held += amount;
+
+            Ok(())
+        })
     }
 
     /// Apply resolve on client state
-    /// Returns state - false if client is not present of held less than the amount
-    pub fn apply_resolve(&mut self, client_id: u16, amount: f32) -> bool {
-        let data = self
-            .get_client_details(client_id)
-            .filter(|d| d.held >= amount);
-        if data.is_none() {
-            return false;
+    /// Fails if client is not present or held less than the amount
+    pub fn apply_resolve(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.held < amount {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.available += amount;
+            data.held -= amount;
+
+            Ok(())
+        })
+    }
+
+    /// Apply chargeback on client's state and mark the account as locked
+    /// Fails if client is not present or held less than the amount
+    pub fn apply_chargeback(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.held < amount {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.total -= amount;
+            data.held -= amount;
+            // Closed account stays closed
+            if data.status == AccountStatus::Active {
+                data.status = AccountStatus::Locked;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Apply chargeback of the transfer - held funds of the destination are returned to the source
+    /// The destination account is marked as locked
+    /// Fails if any client is not present or held funds of the destination are less than the amount
+    pub fn apply_transfer_chargeback(
+        &mut self,
+        from: u16,
+        to: u16,
+        amount: f32,
+    ) -> Result<(), Rejection> {
+        if from == to {
+            return Err(Rejection::InvalidDestination);
+        }
+        let mut source = self.get_client_details(from)?;
+        let before = self.get_client_details(to)?;
+        let mut destination = before.clone();
+        if destination.held < amount {
+            return Err(Rejection::InsufficientFunds);
         }
 
-        let data = data.unwrap();
+        destination.total -= amount;
+        destination.held -= amount;
+        if destination.status == AccountStatus::Active {
+            destination.status = AccountStatus::Locked;
+        }
+        source.available += amount;
+        source.total += amount;
 
-        data.available += amount;
-        data.held -= amount;
+        self.save_both(destination, source, before)
+    }
 
-        true
+    /// Reverse deposit/withdrawal - i.e. erroneous one, the account isn't locked
+    /// Deposit is taken back from available funds, withdrawal is returned to them
+    /// Fee charged for the transaction is refunded
+    /// Fails if client is not present or available funds are not enough to take the deposit back
+    pub fn apply_reversal(
+        &mut self,
+        client_id: u16,
+        transaction_type: TransactionType,
+        amount: f32,
+        fee: f32,
+    ) -> Result<(), Rejection> {
+        let delta = match transaction_type {
+            TransactionType::Deposit => -amount,
+            TransactionType::Withdrawal => amount,
+            _ => return Err(Rejection::UnknownType),
+        };
+
+        self.update_client(client_id, |data| {
+            if data.available + fee + delta < 0. || data.fees < fee {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.available += delta + fee;
+            data.total += delta + fee;
+            data.fees -= fee;
+
+            Ok(())
+        })
     }
 
-    /// Apply chargeback on client's state and mark the account as locked
-    /// State of the operation - failed if client is not present or held less than the amount
-    pub fn apply_chargeback(&mut self, client_id: u16, amount: f32) -> bool {
-        let data = self
-            .get_client_details(client_id)
-            .filter(|d| d.held >= amount);
-        if data.is_none() {
-            return false;
-        }
+    /// Change status of the account - i.e. by admin action
+    /// Fails if client is not present, already has the status or is closed
+    pub fn set_status(&mut self, client_id: u16, status: AccountStatus) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.status == AccountStatus::Closed {
+                return Err(Rejection::AccountClosed);
+            } else if data.status == status {
+                return Err(Rejection::StatusUnchanged);
+            }
+
+            data.status = status;
+
+            Ok(())
+        })
+    }
+
+    /// Release pending funds to available ones - i.e. approved deposit
+    /// Fails if client is not present or pending less than the amount
+    pub fn apply_release(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.pending < amount {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.pending -= amount;
+            data.available += amount;
+
+            Ok(())
+        })
+    }
 
-        let data = data.unwrap();
+    /// Take pending funds out of the account - i.e. approved withdrawal
+    /// Fails if client is not present or pending less than the amount
+    pub fn apply_settle(&mut self, client_id: u16, amount: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.pending < amount {
+                return Err(Rejection::InsufficientFunds);
+            }
 
-        data.total -= amount;
-        data.held -= amount;
-        data.locked = true;
+            data.pending -= amount;
+            data.total -= amount;
 
-        true
+            Ok(())
+        })
     }
 
-    fn get_client_details(&mut self, client_id: u16) -> Option<&mut ClientState> {
-        self.clients_states.get_mut(&client_id)
-        // Enable if we need to eliminate actions on locked client account!
-        //.filter(|d| !d.locked)
+    /// Undo the declined deposit/withdrawal with its fee - held funds are released first
+    /// Held withdrawal isn't taken out of the account - only its fee is refunded
+    /// Fails if client is not present, pending funds are less than the held ones
+    /// or available funds are not enough to take the deposit back
+    pub fn apply_decline(
+        &mut self,
+        client_id: u16,
+        transaction_type: TransactionType,
+        amount: f32,
+        fee: f32,
+        held: bool,
+    ) -> Result<(), Rejection> {
+        let (held_amount, delta) = match (transaction_type, held) {
+            (TransactionType::Deposit, true) => (amount - fee, -amount),
+            (TransactionType::Deposit, false) => (0., -amount),
+            (TransactionType::Withdrawal, true) => (amount, 0.),
+            (TransactionType::Withdrawal, false) => (0., amount),
+            _ => return Err(Rejection::UnknownType),
+        };
+
+        self.update_client(client_id, |data| {
+            let change = held_amount + delta + fee;
+            if data.pending < held_amount
+                || data.fees < fee
+                || (change < 0. && data.available + change < 0.)
+            {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.pending -= held_amount;
+            data.available += change;
+            data.total += delta + fee;
+            data.fees -= fee;
+
+            Ok(())
+        })
+    }
+
+    /// Charge fee from client's available funds
+    /// Fails if client is not present or available less than the fee
+    pub fn apply_fee(&mut self, client_id: u16, fee: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.available < fee {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.available -= fee;
+            data.total -= fee;
+            data.fees += fee;
+
+            Ok(())
+        })
+    }
+
+    /// Refund previously charged fee - i.e. in case of chargeback
+    /// Fails if client is not present or collected fees less than the fee
+    pub fn refund_fee(&mut self, client_id: u16, fee: f32) -> Result<(), Rejection> {
+        self.update_client(client_id, |data| {
+            if data.fees < fee {
+                return Err(Rejection::InsufficientFunds);
+            }
+
+            data.available += fee;
+            data.total += fee;
+            data.fees -= fee;
+
+            Ok(())
+        })
+    }
+
+    /// Apply the update on existing client state and save it - nothing is saved if update fails
+    fn update_client<F>(&mut self, client_id: u16, update: F) -> Result<(), Rejection>
+    where
+        F: FnOnce(&mut ClientState) -> Result<(), Rejection>,
+    {
+        let mut data = self.get_client_details(client_id)?;
+        update(&mut data)?;
+        self.save(data)
+    }
+
+    fn get_client_details(&mut self, client_id: u16) -> Result<ClientState, Rejection> {
+        self.clients_states
+            .get(client_id)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::UnknownClient)
+    }
+
+    fn save(&mut self, data: ClientState) -> Result<(), Rejection> {
+        self.clients_states
+            .upsert(data)
+            .map_err(|e| self.store_failure(e))
+    }
+
+    /// Save states of both clients of the transfer - both are saved or none
+    /// The first client is restored to its before-image if the second one can't be saved
+    fn save_both(
+        &mut self,
+        first: ClientState,
+        second: ClientState,
+        first_before: ClientState,
+    ) -> Result<(), Rejection> {
+        self.save(first)?;
+        if let Err(reason) = self.save(second) {
+            // Error of the second save is reported - the restore one is only logged
+            let client = first_before.client;
+            if let Err(e) = self.clients_states.upsert(first_before) {
+                log::error!(client = client; "client state can't be restored - {}", e);
+            }
+            return Err(reason);
+        }
+
+        Ok(())
+    }
+
+    /// Keep the store error to report it - the action is rejected
+    fn store_failure(&mut self, error: anyhow::Error) -> Rejection {
+        self.store_error = Some(error);
+        Rejection::StoreFailure
+    }
+}
+
+impl Default for ClientsStatesMgr {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::ClientsStatesMgr;
+    use crate::client_state_store::{ClientStateStore, InMemoryClientStateStore};
+    use crate::rejection::Rejection;
+    use crate::{ClientState, ClientsStatesMgr};
     use float_cmp::approx_eq;
 
+    /// Current state of the client - it should be present
+    fn state(mgr: &ClientsStatesMgr, client_id: u16) -> ClientState {
+        mgr.get_state(client_id).unwrap().unwrap()
+    }
+
+    /// Change state of the client directly in the store
+    fn update_state<F: FnOnce(&mut ClientState)>(mgr: &mut ClientsStatesMgr, client_id: u16, f: F) {
+        let mut c = state(mgr, client_id);
+        f(&mut c);
+        mgr.clients_states.upsert(c).unwrap();
+    }
+
     #[test]
     pub fn test_deposits() {
         let mut mgr = ClientsStatesMgr::new();
 
-        assert!(mgr.apply_deposit(2, 13.));
-        let c = mgr.clients_states.get(&2);
+        assert!(mgr.apply_deposit(2, 13.).is_ok());
+        let c = mgr.get_state(2).unwrap();
         assert!(c.is_some(), "New client should be added!");
         let c = c.unwrap();
         assert_eq!(c.client, 2);
-        assert!(!c.locked, "New added client shouldn't be locked");
+        assert!(!c.is_locked(), "New added client shouldn't be locked");
         assert!(approx_eq!(f32, c.total, 13., ulps = 4));
         assert!(approx_eq!(f32, c.available, 13., ulps = 4));
         assert!(
@@ -144,11 +544,15 @@ mod test {
             "In case of deposit held shouldn't be updated!"
         );
 
-        assert!(mgr.apply_deposit(2, 15.));
-        let c = mgr.clients_states.get(&2).unwrap();
-        assert_eq!(mgr.clients_states.len(), 1, "Old client should be updated!");
+        assert!(mgr.apply_deposit(2, 15.).is_ok());
+        let c = mgr.get_state(2).unwrap().unwrap();
+        assert_eq!(
+            mgr.get_states().unwrap().len(),
+            1,
+            "Old client should be updated!"
+        );
         assert_eq!(c.client, 2);
-        assert!(!c.locked);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 28., ulps = 4));
         assert!(approx_eq!(f32, c.available, 28., ulps = 4));
         assert!(
@@ -156,11 +560,15 @@ mod test {
             "In case of deposit held shouldn't be updated!"
         );
 
-        assert!(mgr.apply_deposit(3, 17.));
-        assert_eq!(mgr.clients_states.len(), 2, "New client should be added!");
-        let c3 = mgr.clients_states.get(&3).unwrap();
+        assert!(mgr.apply_deposit(3, 17.).is_ok());
+        assert_eq!(
+            mgr.get_states().unwrap().len(),
+            2,
+            "New client should be added!"
+        );
+        let c3 = mgr.get_state(3).unwrap().unwrap();
         assert_eq!(c3.client, 3);
-        assert!(!c3.locked);
+        assert!(!c3.is_locked());
         assert!(approx_eq!(f32, c3.total, 17., ulps = 4));
         assert!(approx_eq!(f32, c3.available, 17., ulps = 4));
         assert!(
@@ -168,19 +576,21 @@ mod test {
             "In case of deposit held shouldn't be updated!"
         );
 
-        let c = mgr.clients_states.get_mut(&2).unwrap();
+        let c = state(&mgr, 2);
         assert_eq!(c.client, 2);
-        assert!(!c.locked);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 28., ulps = 4));
         assert!(approx_eq!(f32, c.available, 28., ulps = 4));
         assert!(approx_eq!(f32, c.held, 0., ulps = 4));
 
-        c.held = 11.;
-        c.total += 11.;
-        assert!(mgr.apply_deposit(2, 17.));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
+        update_state(&mut mgr, 2, |c| {
+            c.held = 11.;
+            c.total += 11.;
+        });
+        assert!(mgr.apply_deposit(2, 17.).is_ok());
+        let c = state(&mgr, 2);
         assert_eq!(c.client, 2);
-        assert!(!c.locked);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 56., ulps = 4));
         assert!(approx_eq!(f32, c.available, 45., ulps = 4));
         assert!(
@@ -189,7 +599,7 @@ mod test {
         );
 
         assert_eq!(
-            mgr.get_states().len(),
+            mgr.get_states().unwrap().len(),
             2,
             "Should return both client details!"
         );
@@ -198,48 +608,57 @@ mod test {
     #[test]
     pub fn test_withdraw() {
         let mut mgr = ClientsStatesMgr::new();
-        assert!(
-            !mgr.apply_withdrawal(2, 1.),
+        assert_eq!(
+            mgr.apply_withdrawal(2, 1.),
+            Err(Rejection::UnknownClient),
             "Should be failed as no client available!"
         );
-        assert!(mgr.clients_states.is_empty(), "Nth. should be added!");
-
-        assert!(mgr.apply_deposit(2, 11.));
         assert!(
-            !mgr.apply_withdrawal(2, 12.),
+            mgr.get_states().unwrap().is_empty(),
+            "Nth. should be added!"
+        );
+
+        assert!(mgr.apply_deposit(2, 11.).is_ok());
+        assert_eq!(
+            mgr.apply_withdrawal(2, 12.),
+            Err(Rejection::InsufficientFunds),
             "Should be failed as available amount is more!"
         );
         assert!(
-            mgr.apply_withdrawal(2, 9.),
+            mgr.apply_withdrawal(2, 9.).is_ok(),
             "Should be fine as available fund is higher "
         );
 
-        let c = mgr.clients_states.get_mut(&2).unwrap();
+        let c = state(&mgr, 2);
         assert_eq!(c.client, 2);
-        assert!(!c.locked);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 2., ulps = 4));
         assert!(approx_eq!(f32, c.available, 2., ulps = 4));
         assert!(
             approx_eq!(f32, c.held, 0., ulps = 4),
             "Held value shouldn't be changed!"
         );
-        assert!(!mgr.apply_withdrawal(3, 2.), "No client data!");
+        assert!(mgr.apply_withdrawal(3, 2.).is_err(), "No client data!");
 
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        c.held = 3.;
-        c.total += 3.;
+        update_state(&mut mgr, 2, |c| {
+            c.held = 3.;
+            c.total += 3.;
+        });
 
-        assert!(mgr.apply_withdrawal(2, 1.5));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
+        assert!(mgr.apply_withdrawal(2, 1.5).is_ok());
+        let c = state(&mgr, 2);
         assert_eq!(c.client, 2);
-        assert!(!c.locked);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 3.5, ulps = 4));
         assert!(approx_eq!(f32, c.available, 0.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 3., ulps = 4));
 
-        assert!(mgr.apply_withdrawal(2, 0.5), "Available == 0.5 -> ok");
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(!c.locked);
+        assert!(
+            mgr.apply_withdrawal(2, 0.5).is_ok(),
+            "Available == 0.5 -> ok"
+        );
+        let c = state(&mgr, 2);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 3.0, ulps = 4));
         assert!(approx_eq!(f32, c.available, 0.0, ulps = 4));
         assert!(approx_eq!(f32, c.held, 3., ulps = 4));
@@ -249,107 +668,242 @@ mod test {
     pub fn test_dispute() {
         let mut mgr = ClientsStatesMgr::new();
         assert!(
-            !mgr.apply_dispute(2, 1.),
+            mgr.apply_dispute(2, 1.).is_err(),
             "Should be failed as no client available!"
         );
 
-        mgr.apply_deposit(2, 11.5);
+        mgr.apply_deposit(2, 11.5).unwrap();
 
-        assert!(mgr.apply_dispute(2, 2.));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(!c.locked);
+        assert!(mgr.apply_dispute(2, 2.).is_ok());
+        let c = state(&mgr, 2);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 11.5, ulps = 4));
         assert!(approx_eq!(f32, c.available, 9.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 2., ulps = 4));
 
-        assert!(mgr.apply_dispute(2, 9.));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(!c.locked);
+        assert!(mgr.apply_dispute(2, 9.).is_ok());
+        let c = state(&mgr, 2);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 11.5, ulps = 4));
         assert!(approx_eq!(f32, c.available, 0.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 11., ulps = 4));
 
-        assert!(!mgr.apply_dispute(3, 1.), "There is no client 3!");
+        assert!(mgr.apply_dispute(3, 1.).is_err(), "There is no client 3!");
 
-        assert!(!mgr.apply_dispute(2, 1.), "No 1.0 available!");
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(!c.locked);
+        assert!(mgr.apply_dispute(2, 1.).is_err(), "No 1.0 available!");
+        let c = state(&mgr, 2);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 11.5, ulps = 4));
         assert!(approx_eq!(f32, c.available, 0.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 11., ulps = 4));
 
-        assert!(mgr.apply_dispute(2, 0.5));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(!c.locked);
+        assert!(mgr.apply_dispute(2, 0.5).is_ok());
+        let c = state(&mgr, 2);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 11.5, ulps = 4));
         assert!(approx_eq!(f32, c.available, 0., ulps = 4));
         assert!(approx_eq!(f32, c.held, 11.5, ulps = 4));
 
-        assert!(!mgr.apply_dispute(2, 0.1));
+        assert!(mgr.apply_dispute(2, 0.1).is_err());
     }
 
     #[test]
     pub fn test_resolve() {
         let mut mgr = ClientsStatesMgr::new();
         assert!(
-            !mgr.apply_resolve(2, 1.),
+            mgr.apply_resolve(2, 1.).is_err(),
             "Should be failed as no client available!"
         );
 
-        mgr.apply_deposit(2, 2.5);
+        mgr.apply_deposit(2, 2.5).unwrap();
         assert!(
-            !mgr.apply_resolve(2, 1.),
+            mgr.apply_resolve(2, 1.).is_err(),
             "Should be failed as held is 0 -> <2.5!"
         );
 
-        mgr.clients_states.get_mut(&2).unwrap().held = 3.5;
-        mgr.clients_states.get_mut(&2).unwrap().total = 6.;
-        assert!(mgr.apply_resolve(2, 1.));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(!c.locked);
+        update_state(&mut mgr, 2, |c| c.held = 3.5);
+        update_state(&mut mgr, 2, |c| c.total = 6.);
+        assert!(mgr.apply_resolve(2, 1.).is_ok());
+        let c = state(&mgr, 2);
+        assert!(!c.is_locked());
         assert!(approx_eq!(f32, c.total, 6., ulps = 4));
         assert!(approx_eq!(f32, c.available, 3.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 2.5, ulps = 4));
 
-        assert!(mgr.apply_resolve(2, 2.5));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
+        assert!(mgr.apply_resolve(2, 2.5).is_ok());
+        let c = state(&mgr, 2);
         assert!(approx_eq!(f32, c.total, 6., ulps = 4));
         assert!(approx_eq!(f32, c.available, 6., ulps = 4));
         assert!(approx_eq!(f32, c.held, 0., ulps = 4));
 
-        assert!(!mgr.apply_resolve(2, 0.5), "Held == 0");
+        assert!(mgr.apply_resolve(2, 0.5).is_err(), "Held == 0");
     }
 
     #[test]
     pub fn test_chargeback() {
         let mut mgr = ClientsStatesMgr::new();
         assert!(
-            !mgr.apply_chargeback(2, 1.),
+            mgr.apply_chargeback(2, 1.).is_err(),
             "Should be failed as no client available!"
         );
 
-        mgr.apply_deposit(2, 2.5);
+        mgr.apply_deposit(2, 2.5).unwrap();
         assert!(
-            !mgr.apply_chargeback(2, 1.),
+            mgr.apply_chargeback(2, 1.).is_err(),
             "Should be failed as held == 0!"
         );
-        mgr.clients_states.get_mut(&2).unwrap().held = 3.5;
-        mgr.clients_states.get_mut(&2).unwrap().total = 6.;
+        update_state(&mut mgr, 2, |c| c.held = 3.5);
+        update_state(&mut mgr, 2, |c| c.total = 6.);
 
-        assert!(mgr.apply_chargeback(2, 1.));
+        assert!(mgr.apply_chargeback(2, 1.).is_ok());
 
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(c.locked, "Should be marked as locked!");
+        let c = state(&mgr, 2);
+        assert!(c.is_locked(), "Should be marked as locked!");
         assert!(approx_eq!(f32, c.total, 5., ulps = 4));
         assert!(approx_eq!(f32, c.available, 2.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 2.5, ulps = 4));
 
-        assert!(mgr.apply_chargeback(2, 2.5));
-        let c = mgr.clients_states.get_mut(&2).unwrap();
-        assert!(c.locked, "Should remain as locked after chargeback!");
+        assert!(mgr.apply_chargeback(2, 2.5).is_ok());
+        let c = state(&mgr, 2);
+        assert!(c.is_locked(), "Should remain as locked after chargeback!");
         assert!(approx_eq!(f32, c.total, 2.5, ulps = 4));
         assert!(approx_eq!(f32, c.available, 2.5, ulps = 4));
         assert!(approx_eq!(f32, c.held, 0., ulps = 4));
-        assert!(!mgr.apply_chargeback(2, 2.5));
+        assert!(mgr.apply_chargeback(2, 2.5).is_err());
+    }
+
+    #[test]
+    pub fn test_fees() {
+        let mut mgr = ClientsStatesMgr::new();
+        assert!(
+            mgr.apply_fee(2, 1.).is_err(),
+            "Should be failed as no client available!"
+        );
+
+        mgr.apply_deposit(2, 10.).unwrap();
+        assert!(mgr.apply_fee(2, 1.5).is_ok());
+        let c = state(&mgr, 2);
+        assert!(approx_eq!(f32, c.total, 8.5, ulps = 4));
+        assert!(approx_eq!(f32, c.available, 8.5, ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1.5, ulps = 4));
+
+        assert!(
+            mgr.apply_fee(2, 9.).is_err(),
+            "Available is less than the fee!"
+        );
+        assert!(mgr.refund_fee(2, 2.).is_err(), "Collected fees are less!");
+
+        assert!(mgr.refund_fee(2, 1.5).is_ok());
+        let c = state(&mgr, 2);
+        assert!(approx_eq!(f32, c.total, 10., ulps = 4));
+        assert!(approx_eq!(f32, c.available, 10., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 0., ulps = 4));
+    }
+
+    /// Store which fails on write - to check store errors
+    struct FailingStore;
+
+    impl ClientStateStore for FailingStore {
+        fn get(&self, _client: u16) -> Result<Option<ClientState>, anyhow::Error> {
+            Ok(None)
+        }
+
+        fn upsert(&mut self, _state: ClientState) -> Result<(), anyhow::Error> {
+            Err(anyhow::anyhow!("Store is read-only"))
+        }
+
+        fn remove(&mut self, _client: u16) -> Result<(), anyhow::Error> {
+            Err(anyhow::anyhow!("Store is read-only"))
+        }
+
+        fn iterate(&self) -> Result<Vec<ClientState>, anyhow::Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Store which fails on write of the client - to check partial updates
+    struct FailingClientStore {
+        states: InMemoryClientStateStore,
+        client: u16,
+    }
+
+    impl ClientStateStore for FailingClientStore {
+        fn get(&self, client: u16) -> Result<Option<ClientState>, anyhow::Error> {
+            self.states.get(client)
+        }
+
+        fn upsert(&mut self, state: ClientState) -> Result<(), anyhow::Error> {
+            match state.client == self.client {
+                true => Err(anyhow::anyhow!("Client can't be written")),
+                false => self.states.upsert(state),
+            }
+        }
+
+        fn remove(&mut self, client: u16) -> Result<(), anyhow::Error> {
+            self.states.remove(client)
+        }
+
+        fn iterate(&self) -> Result<Vec<ClientState>, anyhow::Error> {
+            self.states.iterate()
+        }
+    }
+
+    #[test]
+    pub fn test_transfer() {
+        let mut states = InMemoryClientStateStore::default();
+        for client in [1, 2, 3] {
+            states
+                .upsert(ClientState {
+                    client,
+                    available: 10.,
+                    total: 10.,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let mut mgr =
+            ClientsStatesMgr::with_store(Box::new(FailingClientStore { states, client: 3 }));
+
+        assert_eq!(
+            mgr.apply_transfer(1, 2, 9.5, 1., false),
+            Err(Rejection::InsufficientFunds),
+            "Fee isn't covered!"
+        );
+        assert!(mgr.apply_transfer(1, 2, 4., 1., false).is_ok());
+        let c = state(&mgr, 1);
+        assert!(approx_eq!(f32, c.available, 5., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 5., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1., ulps = 4));
+        let c = state(&mgr, 2);
+        assert!(approx_eq!(f32, c.available, 14., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 14., ulps = 4));
+
+        // Destination can't be saved - the source is restored
+        assert_eq!(
+            mgr.apply_transfer(1, 3, 2., 1., false),
+            Err(Rejection::StoreFailure)
+        );
+        assert!(mgr.take_store_error().is_some());
+        let c = state(&mgr, 1);
+        assert!(approx_eq!(f32, c.available, 5., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1., ulps = 4));
+    }
+
+    #[test]
+    pub fn test_store_failure() {
+        let mut mgr = ClientsStatesMgr::with_store(Box::new(FailingStore));
+        assert!(mgr.take_store_error().is_none());
+
+        assert_eq!(mgr.apply_deposit(1, 1.), Err(Rejection::StoreFailure));
+        let error = mgr.take_store_error();
+        assert!(error.is_some(), "Store error should be kept!");
+        assert_eq!(error.unwrap().to_string(), "Store is read-only");
+        assert!(mgr.take_store_error().is_none());
+
+        assert_eq!(
+            mgr.apply_withdrawal(1, 1.),
+            Err(Rejection::UnknownClient),
+            "Nothing should be written!"
+        );
     }
 }
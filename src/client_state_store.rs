@@ -0,0 +1,179 @@
+use crate::csv_processor::load_client_states;
+use crate::ClientState;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Storage of clients states - i.e. in memory, file, database, etc.
+pub trait ClientStateStore {
+    /// Get state of the client - none if the client is not present
+    fn get(&self, client: u16) -> Result<Option<ClientState>, anyhow::Error>;
+    /// Insert new or replace existing state of the client
+    fn upsert(&mut self, state: ClientState) -> Result<(), anyhow::Error>;
+    /// Remove state of the client - i.e. on rollback
+    fn remove(&mut self, client: u16) -> Result<(), anyhow::Error>;
+    /// Get states of all clients
+    fn iterate(&self) -> Result<Vec<ClientState>, anyhow::Error>;
+
+    /// Persist pending changes - no-op for in-memory stores
+    fn flush(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// In-memory store - client id to state mapping
+#[derive(Default)]
+pub struct InMemoryClientStateStore {
+    clients_states: HashMap<u16, ClientState>,
+}
+
+impl ClientStateStore for InMemoryClientStateStore {
+    fn get(&self, client: u16) -> Result<Option<ClientState>, anyhow::Error> {
+        Ok(self.clients_states.get(&client).cloned())
+    }
+
+    fn upsert(&mut self, state: ClientState) -> Result<(), anyhow::Error> {
+        self.clients_states.insert(state.client, state);
+        Ok(())
+    }
+
+    fn remove(&mut self, client: u16) -> Result<(), anyhow::Error> {
+        self.clients_states.remove(&client);
+        Ok(())
+    }
+
+    fn iterate(&self) -> Result<Vec<ClientState>, anyhow::Error> {
+        Ok(self.clients_states.values().cloned().collect())
+    }
+}
+
+/// File-backed store - states are loaded from the csv file and written back on flush
+/// File has the same format as the output of the application
+pub struct FileClientStateStore {
+    /// Path of the csv file
+    path: PathBuf,
+    /// Loaded and updated states
+    states: InMemoryClientStateStore,
+    /// Whether there are not flushed changes
+    dirty: bool,
+}
+
+impl FileClientStateStore {
+    /// Open the store - states are loaded if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut states = InMemoryClientStateStore::default();
+
+        if path.exists() {
+            for state in load_client_states(&path)? {
+                states.upsert(state)?;
+            }
+        }
+
+        Ok(Self {
+            path,
+            states,
+            dirty: false,
+        })
+    }
+}
+
+impl ClientStateStore for FileClientStateStore {
+    fn get(&self, client: u16) -> Result<Option<ClientState>, anyhow::Error> {
+        self.states.get(client)
+    }
+
+    fn upsert(&mut self, state: ClientState) -> Result<(), anyhow::Error> {
+        self.dirty = true;
+        self.states.upsert(state)
+    }
+
+    fn remove(&mut self, client: u16) -> Result<(), anyhow::Error> {
+        self.dirty = true;
+        self.states.remove(client)
+    }
+
+    fn iterate(&self) -> Result<Vec<ClientState>, anyhow::Error> {
+        self.states.iterate()
+    }
+
+    /// Write all states sorted by client id - via temporary file to keep the old one on failure
+    fn flush(&mut self) -> Result<(), anyhow::Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut states = self.states.iterate()?;
+        states.sort_by_key(|d| d.client);
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut writer = csv::Writer::from_path(&tmp_path)?;
+        for state in states {
+            writer.serialize(state)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::client_state_store::{
+        ClientStateStore, FileClientStateStore, InMemoryClientStateStore,
+    };
+    use crate::{AccountStatus, ClientState};
+    use float_cmp::approx_eq;
+
+    fn check_store<S: ClientStateStore>(store: &mut S) {
+        assert!(store.get(1).unwrap().is_none());
+
+        let mut state = ClientState {
+            client: 1,
+            available: 2.5,
+            total: 2.5,
+            ..Default::default()
+        };
+        store.upsert(state.clone()).unwrap();
+        state.status = AccountStatus::Locked;
+        store.upsert(state).unwrap();
+        store
+            .upsert(ClientState {
+                client: 2,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let c = store.get(1).unwrap().unwrap();
+        assert!(c.is_locked(), "State should be replaced!");
+        assert!(approx_eq!(f32, c.total, 2.5, ulps = 4));
+        assert_eq!(store.iterate().unwrap().len(), 2);
+
+        store.remove(2).unwrap();
+        store.remove(3).unwrap();
+        assert!(store.get(2).unwrap().is_none());
+        assert_eq!(store.iterate().unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn test_in_memory_store() {
+        check_store(&mut InMemoryClientStateStore::default());
+    }
+
+    #[test]
+    pub fn test_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("states.csv");
+
+        let mut store = FileClientStateStore::open(&path).unwrap();
+        check_store(&mut store);
+        assert!(!path.exists(), "Nothing should be written before flush!");
+        store.flush().unwrap();
+
+        let store = FileClientStateStore::open(&path).unwrap();
+        assert_eq!(store.iterate().unwrap().len(), 1);
+        assert!(store.get(1).unwrap().unwrap().is_locked());
+    }
+}
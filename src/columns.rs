@@ -0,0 +1,105 @@
+use anyhow::anyhow;
+use csv::StringRecord;
+use std::collections::{BTreeMap, HashMap};
+
+/// Columns of the transaction details file
+pub const COLUMNS: [&str; 8] = [
+    "transaction_type",
+    "client",
+    "tx",
+    "amount",
+    "destination",
+    "timestamp",
+    "operator",
+    "reason",
+];
+
+/// Aliases accepted in all files - i.e. 'type' of the common spec
+const DEFAULT_ALIASES: [(&str, &str); 1] = [("type", "transaction_type")];
+
+/// Column name to its aliases
+pub type ColumnAliases = BTreeMap<String, Vec<String>>;
+
+/// Check that aliases are provided for known columns only
+pub fn check_aliases(aliases: &ColumnAliases) -> Result<(), anyhow::Error> {
+    match aliases.keys().find(|d| !COLUMNS.contains(&d.as_str())) {
+        Some(column) => Err(anyhow!(
+            "Unknown column {} - expected one of {}",
+            column,
+            COLUMNS.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Mapping of the file headers to the columns
+/// Headers are trimmed and case-insensitive, unknown ones are kept - i.e. extra columns are ignored
+#[derive(Clone, Debug)]
+pub struct ColumnMapping {
+    /// Lowercase alias to the column name
+    alias_to_column: HashMap<String, String>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+impl ColumnMapping {
+    /// Create mapping of default aliases and the provided ones - the later ones have priority
+    pub fn new(aliases: &[&ColumnAliases]) -> Self {
+        let mut alias_to_column: HashMap<String, String> = DEFAULT_ALIASES
+            .iter()
+            .map(|(alias, column)| (alias.to_string(), column.to_string()))
+            .collect();
+        for (column, column_aliases) in aliases.iter().flat_map(|d| d.iter()) {
+            for alias in column_aliases {
+                alias_to_column.insert(alias.trim().to_lowercase(), column.clone());
+            }
+        }
+
+        Self { alias_to_column }
+    }
+
+    /// Column of the header - the header itself in lowercase if it's not an alias
+    pub fn column_of(&self, header: &str) -> String {
+        let header = header.trim().to_lowercase();
+        self.alias_to_column.get(&header).cloned().unwrap_or(header)
+    }
+
+    /// Headers of the file mapped to the columns
+    pub fn map_headers(&self, headers: &StringRecord) -> StringRecord {
+        headers.iter().map(|d| self.column_of(d)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::columns::{check_aliases, ColumnAliases, ColumnMapping};
+    use csv::StringRecord;
+
+    #[test]
+    pub fn test_column_mapping() {
+        let mapping = ColumnMapping::default();
+        assert_eq!(mapping.column_of(" Type "), "transaction_type");
+        assert_eq!(mapping.column_of("CLIENT"), "client");
+        assert_eq!(mapping.column_of("memo"), "memo");
+
+        let common = ColumnAliases::from([("amount".to_string(), vec!["value".to_string()])]);
+        let partner = ColumnAliases::from([
+            ("client".to_string(), vec!["Customer_Id".to_string()]),
+            ("amount".to_string(), vec!["sum".to_string()]),
+        ]);
+        let mapping = ColumnMapping::new(&[&common, &partner]);
+        let headers = StringRecord::from(vec!["TX", "customer_id", "Value", "sum", "note"]);
+        assert_eq!(
+            mapping.map_headers(&headers),
+            StringRecord::from(vec!["tx", "client", "amount", "amount", "note"])
+        );
+
+        assert!(check_aliases(&partner).is_ok());
+        let unknown = ColumnAliases::from([("kind".to_string(), vec!["type".to_string()])]);
+        assert!(check_aliases(&unknown).is_err());
+    }
+}
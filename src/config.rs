@@ -0,0 +1,178 @@
+use crate::disputes::DisputesConfig;
+use crate::fees::FeeSchedule;
+use crate::interest::InterestConfig;
+use crate::limits::LimitsConfig;
+use crate::ordering::OrderingConfig;
+use crate::policy::{AccountsConfig, AmountsConfig, InputConfig};
+use crate::rules::RuleConfig;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Engine configuration - loaded from TOML file
+/// Unknown keys are rejected - i.e. misspelled ones
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Client tiers - tier name to list of clients
+    pub tiers: ClientTiers,
+    /// Fees applied on deposits and withdrawals
+    pub fees: FeeSchedule,
+    /// Limits of clients accounts
+    pub limits: LimitsConfig,
+    /// Ordering of actions based on timestamps
+    pub ordering: OrderingConfig,
+    /// Dispute window and auto-resolve
+    pub disputes: DisputesConfig,
+    /// Interest rates of the accrual
+    pub interest: InterestConfig,
+    /// Fraud rules checked before deposits and withdrawals
+    pub rules: Vec<RuleConfig>,
+    /// Actions on locked accounts
+    pub accounts: AccountsConfig,
+    /// Rounding and validation of amounts
+    pub amounts: AmountsConfig,
+    /// Handling of input rows which can't be parsed
+    pub input: InputConfig,
+}
+
+impl EngineConfig {
+    /// Load configuration from the provided TOML file
+    pub fn from_file(config_path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(config_path).map_err(|e| anyhow!(e))?;
+        Self::from_toml(&content)
+    }
+
+    /// Parse configuration from TOML content
+    pub fn from_toml(content: &str) -> Result<Self, anyhow::Error> {
+        let config: Self = toml::from_str(content).map_err(|e| anyhow!(e))?;
+        config.input.validate()?;
+        Ok(config)
+    }
+
+    /// Effective configuration in TOML format - defaults included
+    pub fn to_toml(&self) -> Result<String, anyhow::Error> {
+        toml::to_string(self).map_err(|e| anyhow!(e))
+    }
+}
+
+/// Clients grouping to tiers - i.e. to apply different fees and limits per tier
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(
+    from = "HashMap<String, Vec<u16>>",
+    into = "BTreeMap<String, Vec<u16>>"
+)]
+pub struct ClientTiers {
+    /// Client id to tier name mapping
+    client_to_tier: HashMap<u16, String>,
+}
+
+impl ClientTiers {
+    /// Get tier of the client, if any
+    pub fn tier_of(&self, client_id: u16) -> Option<&str> {
+        self.client_to_tier.get(&client_id).map(|d| d.as_str())
+    }
+}
+
+/// Tier name to clients list conversion
+impl From<HashMap<String, Vec<u16>>> for ClientTiers {
+    fn from(tiers: HashMap<String, Vec<u16>>) -> Self {
+        let client_to_tier = tiers
+            .into_iter()
+            .flat_map(|(tier, clients)| clients.into_iter().map(move |c| (c, tier.clone())))
+            .collect();
+
+        Self { client_to_tier }
+    }
+}
+
+/// Clients list per tier conversion - sorted to keep the output stable
+impl From<ClientTiers> for BTreeMap<String, Vec<u16>> {
+    fn from(tiers: ClientTiers) -> Self {
+        let mut tier_to_clients: BTreeMap<String, Vec<u16>> = BTreeMap::new();
+        for (client, tier) in tiers.client_to_tier {
+            tier_to_clients.entry(tier).or_default().push(client);
+        }
+        tier_to_clients.values_mut().for_each(|d| d.sort_unstable());
+
+        tier_to_clients
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+
+    #[test]
+    pub fn test_config() {
+        let config = EngineConfig::from_toml("").unwrap();
+        assert!(config.tiers.tier_of(1).is_none());
+
+        let config = EngineConfig::from_toml(
+            r#"
+            [tiers]
+            gold = [1, 2]
+            silver = [3]
+
+            [[fees]]
+            transaction_type = "withdrawal"
+            flat = 0.5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.tiers.tier_of(1), Some("gold"));
+        assert_eq!(config.tiers.tier_of(2), Some("gold"));
+        assert_eq!(config.tiers.tier_of(3), Some("silver"));
+        assert!(config.tiers.tier_of(4).is_none());
+
+        assert!(
+            EngineConfig::from_toml("[tiers]\ngold = [-1]").is_err(),
+            "Client id should be u16!"
+        );
+    }
+
+    #[test]
+    pub fn test_config_show() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [tiers]
+            gold = [3, 1]
+
+            [limits.clients.2]
+            max_withdrawal = 100
+
+            [[rules]]
+            rule = "velocity"
+            max_withdrawals = 2
+            window_rows = 10
+
+            [accounts]
+            locked = "reject_all"
+            "#,
+        )
+        .unwrap();
+
+        // Defaults are included, the output is loaded back as the same config
+        let content = config.to_toml().unwrap();
+        assert!(content.contains("gold = [1, 3]"));
+        assert!(content.contains("verdict = \"flag\""));
+        assert!(content.contains("parse_errors = \"fail\""));
+        assert_eq!(
+            EngineConfig::from_toml(&content)
+                .unwrap()
+                .to_toml()
+                .unwrap(),
+            content
+        );
+
+        // Unknown keys are rejected on any level
+        assert!(EngineConfig::from_toml("fee = 1").is_err());
+        assert!(EngineConfig::from_toml("[disputes]\nwindow = 1").is_err());
+        assert!(EngineConfig::from_toml("[limits.default]\nmax = 1").is_err());
+        assert!(
+            EngineConfig::from_toml("[[rules]]\nrule = \"velocity\"\nmax_withdrawals = 1\nwindow_rows = 1\nverdit = \"flag\"")
+                .is_err()
+        );
+    }
+}
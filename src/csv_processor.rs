@@ -1,4 +1,7 @@
+use crate::columns::ColumnMapping;
 use crate::transaction_details::TransactionDetails;
+use crate::transaction_mgr::Review;
+use crate::ClientState;
 use anyhow::anyhow;
 use serde::Serialize;
 
@@ -14,10 +17,20 @@ pub struct TransactionIOLoader {
     curr_idx: usize,
 }
 
+/// Row of the file which can't be parsed
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    /// Line of the row in the file - 0 if unknown
+    pub line: u64,
+    /// Parsing error details
+    pub message: String,
+}
+
 impl TransactionIOLoader {
     /// Create new transaction loader based on the provided transaction file
-    pub fn new(transaction_path: &str) -> Result<Self, anyhow::Error> {
-        let mut reader = csv::Reader::from_path(transaction_path).map_err(|e| anyhow!(e))?;
+    /// Headers are mapped to the columns. Fails on the first row which can't be parsed
+    pub fn new(transaction_path: &str, mapping: &ColumnMapping) -> Result<Self, anyhow::Error> {
+        let mut reader = open_reader(transaction_path, mapping)?;
 
         let mut transactions = Vec::new();
         for record in reader.deserialize() {
@@ -30,6 +43,80 @@ impl TransactionIOLoader {
             curr_idx: 0,
         })
     }
+
+    /// Create transaction loader of the admin file - all rows should be admin actions
+    /// Fails on the first row which can't be parsed or isn't an admin action
+    pub fn new_admin(
+        transaction_path: &str,
+        mapping: &ColumnMapping,
+    ) -> Result<Self, anyhow::Error> {
+        let loader = Self::new(transaction_path, mapping)?;
+        if let Some((idx, d)) = loader
+            .transaction_records
+            .iter()
+            .enumerate()
+            .find(|(_, d)| !d.transaction_type.is_admin())
+        {
+            let transaction_type: &str = d.transaction_type.into();
+            return Err(anyhow!(
+                "Admin file {}: record {} is not an admin action - {}",
+                transaction_path,
+                idx + 1,
+                transaction_type
+            ));
+        }
+
+        Ok(loader)
+    }
+
+    /// Create new transaction loader skipping rows which can't be parsed
+    /// Errors of the skipped rows are returned with the loader
+    pub fn new_lenient(
+        transaction_path: &str,
+        mapping: &ColumnMapping,
+    ) -> Result<(Self, Vec<ParseError>), anyhow::Error> {
+        let mut reader = open_reader(transaction_path, mapping)?;
+
+        let mut transactions = Vec::new();
+        let mut errors = Vec::new();
+        for record in reader.deserialize() {
+            match record {
+                Ok(record) => transactions.push(record),
+                Err(e) => errors.push(ParseError {
+                    line: e.position().map_or(0, |d| d.line()),
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        let loader = Self {
+            transaction_records: transactions,
+            curr_idx: 0,
+        };
+        Ok((loader, errors))
+    }
+}
+
+/// Open csv reader of the transaction file with headers mapped to the columns
+fn open_reader(
+    transaction_path: &str,
+    mapping: &ColumnMapping,
+) -> Result<csv::Reader<std::fs::File>, anyhow::Error> {
+    let mut reader = csv::Reader::from_path(transaction_path).map_err(|e| anyhow!(e))?;
+    let headers = mapping.map_headers(reader.headers()?);
+    reader.set_headers(headers);
+
+    Ok(reader)
+}
+
+/// Loader of already loaded transactions - i.e. empty one to run the accrual only
+impl From<Vec<TransactionDetails>> for TransactionIOLoader {
+    fn from(transaction_records: Vec<TransactionDetails>) -> Self {
+        Self {
+            transaction_records,
+            curr_idx: 0,
+        }
+    }
 }
 
 impl TransactionLoader for TransactionIOLoader {
@@ -45,6 +132,34 @@ impl TransactionLoader for TransactionIOLoader {
     }
 }
 
+/// Load clients states from the csv file - the same format as the output
+pub fn load_client_states<P: AsRef<std::path::Path>>(
+    path: P,
+) -> Result<Vec<ClientState>, anyhow::Error> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| anyhow!(e))?;
+
+    let mut states = Vec::new();
+    for record in reader.deserialize() {
+        let state: ClientState = record?;
+        states.push(state);
+    }
+
+    Ok(states)
+}
+
+/// Load open reviews from the csv file - the same format as written by generate_csv
+pub fn load_reviews<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<Review>, anyhow::Error> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| anyhow!(e))?;
+
+    let mut reviews = Vec::new();
+    for record in reader.deserialize() {
+        let review: Review = record?;
+        reviews.push(review);
+    }
+
+    Ok(reviews)
+}
+
 /// Generate csv content from provided data
 pub fn generate_csv<W>(clients_details: &Vec<W>) -> Result<String, anyhow::Error>
 where
@@ -0,0 +1,258 @@
+use crate::validation::LockChange;
+use crate::ClientState;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Change of the balance field of the client
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BalanceDelta {
+    pub client: u16,
+    /// Field name - available, held, total or fees
+    pub field: &'static str,
+    pub old: f32,
+    pub new: f32,
+}
+
+impl BalanceDelta {
+    /// Difference of the new and old values
+    pub fn delta(&self) -> f32 {
+        self.new - self.old
+    }
+}
+
+/// Differences of two clients states sets - i.e. today's output vs yesterday's one
+#[derive(Default, Debug)]
+pub struct StatesDiff {
+    /// Clients present only in the new set
+    pub added: Vec<u16>,
+    /// Clients present only in the old set
+    pub removed: Vec<u16>,
+    /// Balance changes more than the tolerance
+    pub deltas: Vec<BalanceDelta>,
+    /// Lock status changes
+    pub lock_changes: Vec<LockChange>,
+}
+
+/// Single change of the diff - row of the csv output
+#[derive(Serialize, Debug)]
+struct DiffRow {
+    client: u16,
+    change: &'static str,
+    field: &'static str,
+    old: String,
+    new: String,
+}
+
+/// Balance fields of the state to compare
+fn balance_fields(state: &ClientState) -> [(&'static str, f32); 4] {
+    [
+        ("available", state.available),
+        ("held", state.held),
+        ("total", state.total),
+        ("fees", state.fees),
+    ]
+}
+
+impl StatesDiff {
+    /// Compare two sets of clients states
+    /// Balance changes not more than the tolerance are ignored
+    pub fn new(old: &[ClientState], new: &[ClientState], tolerance: f32) -> Self {
+        let old: BTreeMap<u16, &ClientState> = old.iter().map(|d| (d.client, d)).collect();
+        let new: BTreeMap<u16, &ClientState> = new.iter().map(|d| (d.client, d)).collect();
+
+        let mut diff = Self {
+            added: new
+                .keys()
+                .filter(|d| !old.contains_key(d))
+                .copied()
+                .collect(),
+            removed: old
+                .keys()
+                .filter(|d| !new.contains_key(d))
+                .copied()
+                .collect(),
+            ..Default::default()
+        };
+
+        for (client, new_state) in &new {
+            let old_state = match old.get(client) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            let fields = balance_fields(old_state)
+                .into_iter()
+                .zip(balance_fields(new_state));
+            for ((field, old_value), (_, new_value)) in fields {
+                if (new_value - old_value).abs() > tolerance {
+                    diff.deltas.push(BalanceDelta {
+                        client: *client,
+                        field,
+                        old: old_value,
+                        new: new_value,
+                    });
+                }
+            }
+
+            if old_state.is_locked() != new_state.is_locked() {
+                diff.lock_changes.push(LockChange {
+                    client: *client,
+                    locked: new_state.is_locked(),
+                });
+            }
+        }
+
+        diff
+    }
+
+    /// Whether there are no differences
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.deltas.is_empty()
+            && self.lock_changes.is_empty()
+    }
+
+    /// Generate csv content of the diff - one row per change
+    pub fn to_csv(&self) -> Result<String, anyhow::Error> {
+        let mut rows = Vec::new();
+        for client in &self.added {
+            rows.push(DiffRow {
+                client: *client,
+                change: "added",
+                field: "",
+                old: String::new(),
+                new: String::new(),
+            });
+        }
+        for client in &self.removed {
+            rows.push(DiffRow {
+                client: *client,
+                change: "removed",
+                field: "",
+                old: String::new(),
+                new: String::new(),
+            });
+        }
+        for d in &self.deltas {
+            rows.push(DiffRow {
+                client: d.client,
+                change: "balance",
+                field: d.field,
+                old: d.old.to_string(),
+                new: d.new.to_string(),
+            });
+        }
+        for d in &self.lock_changes {
+            rows.push(DiffRow {
+                client: d.client,
+                change: "lock",
+                field: "locked",
+                old: (!d.locked).to_string(),
+                new: d.locked.to_string(),
+            });
+        }
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        if rows.is_empty() {
+            writer.write_record(["client", "change", "field", "old", "new"])?;
+        }
+        for row in rows {
+            writer.serialize(row)?;
+        }
+
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
+
+impl fmt::Display for StatesDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+
+        for client in &self.added {
+            writeln!(f, "client {}: added", client)?;
+        }
+        for client in &self.removed {
+            writeln!(f, "client {}: removed", client)?;
+        }
+        for d in &self.deltas {
+            writeln!(
+                f,
+                "client {}: {} {} -> {} ({:+})",
+                d.client,
+                d.field,
+                d.old,
+                d.new,
+                d.delta()
+            )?;
+        }
+        for d in &self.lock_changes {
+            let status = if d.locked { "locked" } else { "unlocked" };
+            writeln!(f, "client {}: {}", d.client, status)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diff::StatesDiff;
+    use crate::{AccountStatus, ClientState};
+    use float_cmp::approx_eq;
+
+    fn state(client: u16, available: f32, locked: bool) -> ClientState {
+        ClientState {
+            client,
+            available,
+            total: available,
+            status: match locked {
+                true => AccountStatus::Locked,
+                false => AccountStatus::Active,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_diff() {
+        let old = [
+            state(1, 10., false),
+            state(2, 5., false),
+            state(3, 1., false),
+        ];
+        let new = [
+            state(1, 12., false),
+            state(3, 1.00001, true),
+            state(4, 1., false),
+        ];
+
+        let diff = StatesDiff::new(&old, &new, 0.0001);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added, vec![4]);
+        assert_eq!(diff.removed, vec![2]);
+        assert_eq!(diff.deltas.len(), 2, "Available and total of client 1");
+        assert!(diff.deltas.iter().all(|d| d.client == 1));
+        assert!(approx_eq!(f32, diff.deltas[0].delta(), 2., ulps = 4));
+        assert_eq!(diff.lock_changes.len(), 1);
+        assert_eq!(diff.lock_changes[0].client, 3);
+
+        let text = diff.to_string();
+        assert!(text.contains("client 1: available 10 -> 12 (+2)"));
+        assert!(text.contains("client 3: locked"));
+
+        let csv = diff.to_csv().unwrap();
+        assert!(csv.starts_with("client,change,field,old,new\n"));
+        assert!(csv.contains("4,added,,,\n"));
+        assert!(csv.contains("1,balance,total,10,12\n"));
+        assert!(csv.contains("3,lock,locked,false,true\n"));
+
+        let diff = StatesDiff::new(&old, &old, 0.);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no differences\n");
+        assert_eq!(diff.to_csv().unwrap(), "client,change,field,old,new\n");
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Dispute state of the transaction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DisputeState {
+    /// Transaction is never disputed
+    NotDisputed,
+    /// Dispute is open
+    Open,
+    /// Dispute is resolved - transaction can be disputed again
+    Resolved,
+    /// Transaction is charged back - final state
+    ChargedBack,
+    /// Transaction is reversed - final state
+    Reversed,
+}
+
+/// Disputes configuration. Not provided values are not checked
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputesConfig {
+    /// Dispute is rejected if the transaction is older than the number of rows
+    pub window_rows: Option<u64>,
+    /// Dispute is rejected if the transaction is older than the millis - based on timestamps
+    pub window_millis: Option<i64>,
+    /// Open dispute is resolved automatically after the number of rows
+    pub auto_resolve_rows: Option<u64>,
+    /// Open dispute is resolved automatically after the millis - based on timestamps
+    pub auto_resolve_millis: Option<i64>,
+    /// Dispute of the amount more than available funds
+    pub insufficient_funds: InsufficientFundsPolicy,
+}
+
+/// Dispute of the amount more than available funds - i.e. deposit already withdrawn
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InsufficientFundsPolicy {
+    /// Dispute is rejected with insufficient_funds
+    #[default]
+    Reject,
+    /// Disputed amount is held anyway - available funds become negative
+    Hold,
+}
+
+impl DisputesConfig {
+    /// Whether the transaction can't be disputed anymore
+    pub fn is_expired(
+        &self,
+        (tx_row, tx_timestamp): (u64, Option<i64>),
+        (row, timestamp): (u64, Option<i64>),
+    ) -> bool {
+        is_after(
+            self.window_rows,
+            self.window_millis,
+            (tx_row, tx_timestamp),
+            (row, timestamp),
+        )
+    }
+
+    /// Whether the open dispute should be resolved automatically
+    pub fn is_overdue(
+        &self,
+        (dispute_row, dispute_timestamp): (u64, Option<i64>),
+        (row, timestamp): (u64, Option<i64>),
+    ) -> bool {
+        is_after(
+            self.auto_resolve_rows,
+            self.auto_resolve_millis,
+            (dispute_row, dispute_timestamp),
+            (row, timestamp),
+        )
+    }
+}
+
+/// Whether the current position is after the start one more than rows/millis
+/// Millis are checked only if both positions have timestamps
+fn is_after(
+    rows: Option<u64>,
+    millis: Option<i64>,
+    (start_row, start_timestamp): (u64, Option<i64>),
+    (row, timestamp): (u64, Option<i64>),
+) -> bool {
+    let rows_after = rows
+        .filter(|d| row.saturating_sub(start_row) > *d)
+        .is_some();
+    let millis_after = match (millis, start_timestamp, timestamp) {
+        (Some(window), Some(start), Some(time)) => time.saturating_sub(start) > window,
+        _ => false,
+    };
+
+    rows_after || millis_after
+}
+
+/// Open dispute waiting for the auto-resolve deadline
+#[derive(Clone, Copy, Debug)]
+pub struct OpenDispute {
+    pub tx: u32,
+    pub client: u16,
+    pub row: u64,
+    pub timestamp: Option<i64>,
+}
+
+/// Open disputes in the opening order - to resolve automatically
+#[derive(Default, Clone)]
+pub struct DisputeDeadlines {
+    disputes: VecDeque<OpenDispute>,
+    /// Transaction id to the row of its open dispute
+    open_rows: HashMap<u32, u64>,
+}
+
+impl DisputeDeadlines {
+    /// Keep track on opened dispute
+    pub fn push(&mut self, dispute: OpenDispute) {
+        self.open_rows.insert(dispute.tx, dispute.row);
+        self.disputes.push_back(dispute);
+    }
+
+    /// Stop tracking the dispute - i.e. it's resolved or charged back
+    pub fn close(&mut self, tx: u32) {
+        self.open_rows.remove(&tx);
+    }
+
+    /// Take the earliest open dispute if it's overdue on the provided position
+    /// Disputes are checked in the opening order, closed ones are dropped
+    pub fn pop_overdue(
+        &mut self,
+        config: &DisputesConfig,
+        position: (u64, Option<i64>),
+    ) -> Option<OpenDispute> {
+        while let Some(dispute) = self.disputes.front().copied() {
+            if self.open_rows.get(&dispute.tx) != Some(&dispute.row) {
+                self.disputes.pop_front();
+                continue;
+            } else if !config.is_overdue((dispute.row, dispute.timestamp), position) {
+                return None;
+            }
+
+            self.disputes.pop_front();
+            self.open_rows.remove(&dispute.tx);
+            return Some(dispute);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+    use crate::disputes::{DisputeDeadlines, OpenDispute};
+
+    #[test]
+    pub fn test_disputes_config() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [disputes]
+            window_rows = 10
+            window_millis = 1000
+            auto_resolve_rows = 5
+            "#,
+        )
+        .unwrap();
+        let disputes = &config.disputes;
+
+        assert!(!disputes.is_expired((1, None), (11, None)));
+        assert!(disputes.is_expired((1, None), (12, None)));
+        assert!(!disputes.is_expired((1, Some(1000)), (2, Some(2000))));
+        assert!(disputes.is_expired((1, Some(1000)), (2, Some(2001))));
+        assert!(
+            !disputes.is_expired((1, None), (2, Some(5000))),
+            "Transaction has no timestamp"
+        );
+
+        assert!(!disputes.is_overdue((1, Some(1000)), (6, Some(10000))));
+        assert!(disputes.is_overdue((1, None), (7, None)));
+        assert!(
+            !disputes.is_expired((1, Some(i64::MAX)), (2, Some(i64::MIN))),
+            "Far apart timestamps don't overflow"
+        );
+
+        let mut deadlines = DisputeDeadlines::default();
+        deadlines.push(OpenDispute {
+            tx: 1,
+            client: 1,
+            row: 1,
+            timestamp: None,
+        });
+        deadlines.push(OpenDispute {
+            tx: 2,
+            client: 1,
+            row: 3,
+            timestamp: None,
+        });
+        assert!(deadlines.pop_overdue(disputes, (6, None)).is_none());
+        assert_eq!(deadlines.pop_overdue(disputes, (7, None)).unwrap().tx, 1);
+        assert!(deadlines.pop_overdue(disputes, (7, None)).is_none());
+        assert_eq!(deadlines.pop_overdue(disputes, (9, None)).unwrap().tx, 2);
+
+        // Closed dispute is dropped, re-opened one has own deadline
+        deadlines.push(OpenDispute {
+            tx: 1,
+            client: 1,
+            row: 10,
+            timestamp: None,
+        });
+        deadlines.close(1);
+        deadlines.push(OpenDispute {
+            tx: 1,
+            client: 1,
+            row: 12,
+            timestamp: None,
+        });
+        assert!(deadlines.pop_overdue(disputes, (16, None)).is_none());
+        assert_eq!(deadlines.pop_overdue(disputes, (18, None)).unwrap().row, 12);
+    }
+}
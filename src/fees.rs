@@ -0,0 +1,104 @@
+use crate::transaction_details::TransactionType;
+use serde::{Deserialize, Serialize};
+
+/// Fee rule of the transaction type
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FeeRule {
+    /// Transaction type the fee is applied on - deposit or withdrawal
+    pub transaction_type: TransactionType,
+    /// Client tier the rule is limited to. If not provided - applied on all clients
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// Flat fee per transaction
+    #[serde(default)]
+    pub flat: f32,
+    /// Percentage of the transaction amount, i.e. 1.5 -> 1.5%
+    #[serde(default)]
+    pub percent: f32,
+}
+
+/// Fee schedule - list of fee rules
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct FeeSchedule {
+    rules: Vec<FeeRule>,
+}
+
+impl FeeSchedule {
+    /// Calculate fee of the transaction
+    /// Tier specific rule has priority over the generic one. Returns 0 if there is no rule
+    pub fn fee_for(
+        &self,
+        transaction_type: TransactionType,
+        tier: Option<&str>,
+        amount: f32,
+    ) -> f32 {
+        let rules = self
+            .rules
+            .iter()
+            .filter(|d| d.transaction_type == transaction_type);
+
+        let rule = rules
+            .clone()
+            .find(|d| tier.is_some() && d.tier.as_deref() == tier)
+            .or_else(|| rules.clone().find(|d| d.tier.is_none()));
+
+        match rule {
+            Some(rule) => rule.flat + amount * rule.percent / 100.,
+            None => 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+    use crate::TransactionType;
+    use float_cmp::approx_eq;
+
+    #[test]
+    pub fn test_fee_schedule() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[fees]]
+            transaction_type = "withdrawal"
+            flat = 0.5
+            percent = 1.0
+
+            [[fees]]
+            transaction_type = "withdrawal"
+            tier = "gold"
+            percent = 0.5
+
+            [[fees]]
+            transaction_type = "deposit"
+            tier = "silver"
+            flat = 1.0
+            "#,
+        )
+        .unwrap();
+        let fees = &config.fees;
+
+        let fee = fees.fee_for(TransactionType::Withdrawal, None, 100.);
+        assert!(approx_eq!(f32, fee, 1.5, ulps = 4));
+        let fee = fees.fee_for(TransactionType::Withdrawal, Some("silver"), 100.);
+        assert!(
+            approx_eq!(f32, fee, 1.5, ulps = 4),
+            "No silver rule - generic should be applied!"
+        );
+        let fee = fees.fee_for(TransactionType::Withdrawal, Some("gold"), 100.);
+        assert!(approx_eq!(f32, fee, 0.5, ulps = 4));
+
+        let fee = fees.fee_for(TransactionType::Deposit, None, 100.);
+        assert!(
+            approx_eq!(f32, fee, 0., ulps = 4),
+            "No generic deposit rule!"
+        );
+        let fee = fees.fee_for(TransactionType::Deposit, Some("silver"), 100.);
+        assert!(approx_eq!(f32, fee, 1., ulps = 4));
+
+        let fee = fees.fee_for(TransactionType::Dispute, Some("silver"), 100.);
+        assert!(approx_eq!(f32, fee, 0., ulps = 4));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::rejection::Rejection;
+use crate::{TransactionDetails, TransactionType};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Processed action of the client - applied or rejected
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    /// Processing row of the action
+    pub row: u64,
+    pub tx: u32,
+    pub transaction_type: TransactionType,
+    pub amount: Option<f32>,
+    /// Rejection reason - none if the action is applied
+    pub rejection: Option<Rejection>,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transaction_type: &str = self.transaction_type.into();
+        write!(f, "row {} tx {} {}", self.row, self.tx, transaction_type)?;
+        if let Some(amount) = self.amount {
+            write!(f, " {}", amount)?;
+        }
+
+        match self.rejection {
+            Some(reason) => write!(f, " rejected: {}", reason),
+            None => write!(f, " applied"),
+        }
+    }
+}
+
+/// Processed actions per client in the processing order
+#[derive(Default, Debug)]
+pub struct ClientHistory {
+    client_to_entries: HashMap<u16, Vec<HistoryEntry>>,
+}
+
+impl ClientHistory {
+    /// Keep track on the processed action
+    pub fn record(
+        &mut self,
+        action_details: &TransactionDetails,
+        row: u64,
+        result: &Result<(), Rejection>,
+    ) {
+        self.client_to_entries
+            .entry(action_details.client)
+            .or_default()
+            .push(HistoryEntry {
+                row,
+                tx: action_details.tx,
+                transaction_type: action_details.transaction_type,
+                amount: action_details.amount,
+                rejection: result.err(),
+            });
+    }
+
+    /// Processed actions of the client
+    pub fn of(&self, client_id: u16) -> &[HistoryEntry] {
+        self.client_to_entries
+            .get(&client_id)
+            .map_or(&[], |d| d.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::history::ClientHistory;
+    use crate::rejection::Rejection;
+    use crate::{TransactionDetails, TransactionType};
+
+    #[test]
+    pub fn test_client_history() {
+        let mut history = ClientHistory::default();
+        let mut tx = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(2.5),
+            ..Default::default()
+        };
+        history.record(&tx, 1, &Ok(()));
+        tx.tx = 2;
+        tx.transaction_type = TransactionType::Withdrawal;
+        history.record(&tx, 2, &Err(Rejection::InsufficientFunds));
+
+        assert!(history.of(2).is_empty());
+        let entries = history.of(1);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].to_string(), "row 1 tx 1 deposit 2.5 applied");
+        assert_eq!(
+            entries[1].to_string(),
+            "row 2 tx 2 withdrawal 2.5 rejected: insufficient_funds"
+        );
+    }
+}
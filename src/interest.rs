@@ -0,0 +1,98 @@
+use crate::config::ClientTiers;
+use serde::{Deserialize, Serialize};
+
+/// First tx id of the reserved range by default - ids of generated accruals
+pub const DEFAULT_FIRST_ACCRUAL_TX: u32 = 4_000_000_000;
+
+/// Interest rate of the accrual
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InterestRate {
+    /// Client tier the rate is limited to. If not provided - applied on all clients
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// Percentage of the available funds per accrual, i.e. 0.5 -> 0.5%
+    pub percent: f32,
+}
+
+/// Interest configuration - rate table and the reserved tx id range
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct InterestConfig {
+    /// Rates per tier - clients without rate are not credited
+    pub rates: Vec<InterestRate>,
+    /// First tx id of the reserved range - input transactions with ids from it are rejected
+    pub first_tx: u32,
+}
+
+impl Default for InterestConfig {
+    fn default() -> Self {
+        Self {
+            rates: Vec::new(),
+            first_tx: DEFAULT_FIRST_ACCRUAL_TX,
+        }
+    }
+}
+
+impl InterestConfig {
+    /// Interest rate of the client in percent
+    /// Tier specific rate has priority over the generic one. Returns 0 if there is no rate
+    pub fn rate_of(&self, client_id: u16, tiers: &ClientTiers) -> f32 {
+        let tier = tiers.tier_of(client_id);
+        self.rates
+            .iter()
+            .find(|d| tier.is_some() && d.tier.as_deref() == tier)
+            .or_else(|| self.rates.iter().find(|d| d.tier.is_none()))
+            .map_or(0., |d| d.percent)
+    }
+
+    /// Whether the tx id is in the range reserved for generated accruals
+    pub fn is_reserved(&self, tx: u32) -> bool {
+        tx >= self.first_tx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+    use float_cmp::approx_eq;
+
+    #[test]
+    pub fn test_interest_config() {
+        let config = EngineConfig::from_toml("").unwrap();
+        assert!(approx_eq!(
+            f32,
+            config.interest.rate_of(1, &config.tiers),
+            0.,
+            ulps = 4
+        ));
+        assert!(config.interest.is_reserved(u32::MAX));
+        assert!(!config.interest.is_reserved(1));
+
+        let config = EngineConfig::from_toml(
+            r#"
+            [tiers]
+            gold = [1]
+            silver = [2]
+
+            [interest]
+            first_tx = 1000
+
+            [[interest.rates]]
+            percent = 0.5
+
+            [[interest.rates]]
+            tier = "gold"
+            percent = 1.5
+            "#,
+        )
+        .unwrap();
+
+        let rate = |client| config.interest.rate_of(client, &config.tiers);
+        assert!(approx_eq!(f32, rate(1), 1.5, ulps = 4));
+        assert!(approx_eq!(f32, rate(2), 0.5, ulps = 4));
+        assert!(approx_eq!(f32, rate(3), 0.5, ulps = 4));
+        assert!(config.interest.is_reserved(1000));
+        assert!(!config.interest.is_reserved(999));
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Manifest entry of the processed file
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BatchEntry {
+    /// Path of the file as it was provided
+    pub file: String,
+    /// sha256 of the file content - identity of the batch
+    pub hash: String,
+    /// Number of processed rows
+    pub rows: u64,
+    /// Processing time in RFC 3339
+    pub processed_at: String,
+}
+
+/// Batch prepared to be finished - written before clients states are persisted
+/// Recovered on open: the batch is finished if the state file is changed since, dropped otherwise
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct PreparedBatch {
+    entry: BatchEntry,
+    /// tx ids seen by the batch
+    seen: Vec<u32>,
+    /// State file the batch is persisted to - none if states are not persisted
+    state_file: Option<PathBuf>,
+    /// sha256 of the state file before it's persisted - none if there is no file
+    state_hash: Option<String>,
+}
+
+/// Ledger of the ingested batches - kept in the directory across runs
+/// Holds the manifest of processed files and the index of seen deposit/withdrawal/transfer tx ids
+/// Seen tx ids of the batch are kept in memory and written only when the batch is finished,
+/// so the marks of rolled back or interrupted batches are not persisted
+pub struct Ledger {
+    /// JSON lines file of processed batches
+    manifest_path: PathBuf,
+    /// Csv file of seen tx ids - tx,batch lines, batch is the position in the manifest
+    seen_path: PathBuf,
+    /// JSON file of the prepared batch - exists only while the batch is being finished
+    prepared_path: PathBuf,
+    /// Processed batches in the processing order
+    batches: Vec<BatchEntry>,
+    /// tx id to position of the batch it's seen in first
+    seen: HashMap<u32, usize>,
+    /// Batch in progress - file and hash
+    current: Option<(String, String)>,
+    /// tx ids marked as seen by the batch in progress - written on finish
+    pending: Vec<u32>,
+    /// Batch prepared to be finished
+    prepared: Option<PreparedBatch>,
+}
+
+/// sha256 of the file content in hex
+pub fn file_hash<P: AsRef<Path>>(path: P) -> Result<String, anyhow::Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// sha256 of the file content - none if the file doesn't exist
+fn existing_file_hash(path: &Path) -> Result<Option<String>, anyhow::Error> {
+    match path.exists() {
+        true => file_hash(path).map(Some),
+        false => Ok(None),
+    }
+}
+
+/// Current time in RFC 3339
+fn now_rfc3339() -> Result<String, anyhow::Error> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    chrono::DateTime::from_timestamp_millis(millis)
+        .map(|d| d.to_rfc3339())
+        .ok_or_else(|| anyhow!("Invalid current time"))
+}
+
+impl Ledger {
+    /// Open (or create) the ledger in the directory
+    /// Batch interrupted while it was being finished is recovered
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, anyhow::Error> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let manifest_path = dir.join("manifest.jsonl");
+        let mut batches = Vec::new();
+        if manifest_path.exists() {
+            for (idx, line) in BufReader::new(File::open(&manifest_path)?)
+                .lines()
+                .enumerate()
+            {
+                let entry: BatchEntry = serde_json::from_str(&line?)
+                    .map_err(|e| anyhow!("Manifest line {}: {}", idx + 1, e))?;
+                batches.push(entry);
+            }
+        }
+
+        let seen_path = dir.join("seen_tx.csv");
+        let mut seen = HashMap::new();
+        if seen_path.exists() {
+            for (idx, line) in BufReader::new(File::open(&seen_path)?).lines().enumerate() {
+                let line = line?;
+                let (tx, batch) = line
+                    .split_once(',')
+                    .and_then(|(tx, batch)| Some((tx.parse().ok()?, batch.parse().ok()?)))
+                    .ok_or_else(|| anyhow!("Seen tx line {}: invalid entry {}", idx + 1, line))?;
+                seen.entry(tx).or_insert(batch);
+            }
+        }
+
+        let mut ledger = Self {
+            manifest_path,
+            seen_path,
+            prepared_path: dir.join("prepared.json"),
+            batches,
+            seen,
+            current: None,
+            pending: Vec::new(),
+            prepared: None,
+        };
+        if ledger.prepared_path.exists() {
+            let prepared: PreparedBatch =
+                serde_json::from_reader(File::open(&ledger.prepared_path)?)
+                    .map_err(|e| anyhow!("Prepared batch: {}", e))?;
+            let persisted = match prepared.state_file.as_deref() {
+                Some(path) => existing_file_hash(path)? != prepared.state_hash,
+                None => false,
+            };
+
+            // States of the batch are persisted - the batch is finished, otherwise it's processed again
+            match persisted {
+                true => {
+                    ledger.prepared = Some(prepared);
+                    ledger.finish_batch()?;
+                }
+                false => std::fs::remove_file(&ledger.prepared_path)?,
+            }
+        }
+
+        Ok(ledger)
+    }
+
+    /// Start the batch of the file
+    /// Returns the manifest entry if the same content is already processed - the file should be skipped
+    pub fn start_batch(&mut self, file: &str) -> Result<Option<BatchEntry>, anyhow::Error> {
+        let hash = file_hash(file)?;
+        if let Some(entry) = self.batches.iter().find(|d| d.hash == hash) {
+            return Ok(Some(entry.clone()));
+        }
+
+        self.current = Some((file.to_string(), hash));
+        self.pending.clear();
+        Ok(None)
+    }
+
+    /// Hash of the batch in progress
+    pub fn batch(&self) -> Option<&str> {
+        self.current.as_ref().map(|(_, hash)| hash.as_str())
+    }
+
+    /// Hash of the batch the tx id is seen in - the batch in progress included
+    pub fn seen_in(&self, tx: u32) -> Option<&str> {
+        let batch = *self.seen.get(&tx)?;
+        match self.batches.get(batch) {
+            Some(d) => Some(d.hash.as_str()),
+            None => self.batch(),
+        }
+    }
+
+    /// Mark the tx id as seen in the batch in progress - the action is applied
+    /// The earlier mark is kept
+    pub fn mark_seen(&mut self, tx: u32) -> Result<(), anyhow::Error> {
+        if self.current.is_none() {
+            return Err(anyhow!("No batch in progress"));
+        }
+        if let Entry::Vacant(v) = self.seen.entry(tx) {
+            v.insert(self.batches.len());
+            self.pending.push(tx);
+        }
+        Ok(())
+    }
+
+    /// Remove the seen mark of the batch in progress - i.e. on rollback
+    pub fn unmark_seen(&mut self, tx: u32) {
+        if self.seen.get(&tx) == Some(&self.batches.len()) {
+            self.seen.remove(&tx);
+            self.pending.retain(|d| *d != tx);
+        }
+    }
+
+    /// Prepare the batch in progress to be finished - should be called before clients states are
+    /// persisted to the state file. If the run is interrupted before the batch is finished,
+    /// it's finished on the next open if the state file is changed since
+    pub fn prepare_batch(
+        &mut self,
+        rows: u64,
+        state_file: Option<&Path>,
+    ) -> Result<(), anyhow::Error> {
+        let (file, hash) = self
+            .current
+            .take()
+            .ok_or_else(|| anyhow!("No batch in progress"))?;
+        let prepared = PreparedBatch {
+            entry: BatchEntry {
+                file,
+                hash,
+                rows,
+                processed_at: now_rfc3339()?,
+            },
+            seen: std::mem::take(&mut self.pending),
+            state_file: state_file.map(Path::to_path_buf),
+            state_hash: state_file.map(existing_file_hash).transpose()?.flatten(),
+        };
+
+        let tmp_path = self.prepared_path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(&prepared)?)?;
+        std::fs::rename(&tmp_path, &self.prepared_path)?;
+        self.prepared = Some(prepared);
+        Ok(())
+    }
+
+    /// Finish the prepared batch - seen tx ids are written and the file is added to the manifest
+    /// Marks of the interrupted batch are not written, so it's processed again on rerun
+    pub fn finish_batch(&mut self) -> Result<BatchEntry, anyhow::Error> {
+        let prepared = self
+            .prepared
+            .take()
+            .ok_or_else(|| anyhow!("No prepared batch"))?;
+        // Recovered batch may be partially written already - seen tx ids are written again
+        let position = self
+            .batches
+            .iter()
+            .position(|d| d.hash == prepared.entry.hash)
+            .unwrap_or(self.batches.len());
+
+        let mut seen = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.seen_path)?,
+        );
+        for tx in prepared.seen {
+            writeln!(seen, "{},{}", tx, position)?;
+            self.seen.entry(tx).or_insert(position);
+        }
+        seen.flush()?;
+
+        if position == self.batches.len() {
+            let mut manifest = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.manifest_path)?;
+            writeln!(manifest, "{}", serde_json::to_string(&prepared.entry)?)?;
+            self.batches.push(prepared.entry.clone());
+        }
+
+        std::fs::remove_file(&self.prepared_path)?;
+        Ok(prepared.entry)
+    }
+
+    /// Processed batches in the processing order
+    pub fn batches(&self) -> &[BatchEntry] {
+        &self.batches
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ledger::{file_hash, Ledger};
+
+    #[test]
+    pub fn test_ledger() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount\ndeposit,1,1,1.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "transaction_type,client,tx,amount\ndeposit,1,2,1.0\n",
+        )
+        .unwrap();
+        let ledger_dir = dir.path().join("ledger");
+
+        {
+            let mut ledger = Ledger::open(&ledger_dir).unwrap();
+            assert!(ledger.mark_seen(1).is_err(), "No batch in progress");
+
+            assert_eq!(ledger.start_batch(first.to_str().unwrap()).unwrap(), None);
+            assert_eq!(ledger.batch(), Some(file_hash(&first).unwrap().as_str()));
+            assert_eq!(ledger.seen_in(1), None);
+            ledger.mark_seen(1).unwrap();
+            assert_eq!(ledger.seen_in(1), Some(file_hash(&first).unwrap().as_str()));
+
+            assert!(ledger.finish_batch().is_err(), "Batch is not prepared");
+            ledger.prepare_batch(1, None).unwrap();
+            assert_eq!(ledger.batch(), None);
+            let entry = ledger.finish_batch().unwrap();
+            assert_eq!(entry.rows, 1);
+        }
+
+        // Reopened ledger keeps the manifest and the seen index
+        let mut ledger = Ledger::open(&ledger_dir).unwrap();
+        assert_eq!(ledger.batches().len(), 1);
+        let processed = ledger.start_batch(first.to_str().unwrap()).unwrap();
+        assert_eq!(processed.unwrap().rows, 1);
+
+        assert_eq!(ledger.start_batch(second.to_str().unwrap()).unwrap(), None);
+        assert_eq!(ledger.seen_in(1), Some(file_hash(&first).unwrap().as_str()));
+        ledger.mark_seen(2).unwrap();
+        ledger.unmark_seen(2);
+        assert_eq!(ledger.seen_in(2), None);
+    }
+
+    #[test]
+    pub fn test_ledger_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.csv");
+        std::fs::write(
+            &file,
+            "transaction_type,client,tx,amount\ndeposit,1,1,1.0\n",
+        )
+        .unwrap();
+        let state_file = dir.path().join("states.csv");
+        std::fs::write(&state_file, "client\n").unwrap();
+        let ledger_dir = dir.path().join("ledger");
+
+        // Interrupted before the states are persisted - the batch is processed again
+        {
+            let mut ledger = Ledger::open(&ledger_dir).unwrap();
+            ledger.start_batch(file.to_str().unwrap()).unwrap();
+            ledger.mark_seen(1).unwrap();
+            ledger.prepare_batch(1, Some(&state_file)).unwrap();
+        }
+        let mut ledger = Ledger::open(&ledger_dir).unwrap();
+        assert!(ledger.batches().is_empty());
+        assert_eq!(ledger.seen_in(1), None);
+
+        // Interrupted after the states are persisted - the batch is finished on open
+        ledger.start_batch(file.to_str().unwrap()).unwrap();
+        ledger.mark_seen(1).unwrap();
+        ledger.prepare_batch(1, Some(&state_file)).unwrap();
+        std::fs::write(&state_file, "client\n1\n").unwrap();
+        drop(ledger);
+
+        let mut ledger = Ledger::open(&ledger_dir).unwrap();
+        assert_eq!(ledger.batches().len(), 1);
+        assert_eq!(ledger.seen_in(1), Some(file_hash(&file).unwrap().as_str()));
+        assert!(ledger
+            .start_batch(file.to_str().unwrap())
+            .unwrap()
+            .is_some());
+    }
+}
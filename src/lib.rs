@@ -0,0 +1,33 @@
+pub use crate::client_state::{AccountStatus, ClientState};
+pub use crate::client_state_mgr::ClientsStatesMgr;
+pub use crate::transaction_details::{TransactionDetails, TransactionType};
+pub use crate::transaction_mgr::TransactionMgr;
+pub use crate::transaction_processor::TransactionsProcessor;
+
+pub mod audit;
+pub mod client_state;
+pub mod client_state_mgr;
+pub mod client_state_store;
+pub mod columns;
+pub mod config;
+pub mod csv_processor;
+pub mod diff;
+pub mod disputes;
+pub mod fees;
+pub mod history;
+pub mod interest;
+pub mod ledger;
+pub mod limits;
+pub mod metrics;
+pub mod ordering;
+pub mod policy;
+pub mod reconcile;
+pub mod rejection;
+pub mod replay;
+pub mod report;
+pub mod rules;
+pub mod transaction_details;
+pub mod transaction_mgr;
+pub mod transaction_processor;
+pub mod transaction_store;
+pub mod validation;
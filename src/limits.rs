@@ -0,0 +1,295 @@
+use crate::config::ClientTiers;
+use crate::rejection::Rejection;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Limits of the client account. Not provided limit is not checked
+#[derive(Default, Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum amount of single withdrawal
+    pub max_withdrawal: Option<f32>,
+    /// Maximum cumulative withdrawal amount within the window
+    pub max_window_withdrawal: Option<f32>,
+    /// Window size in rows. If not provided - all processed rows are considered
+    pub window_rows: Option<u64>,
+    /// Window size in millis - based on timestamps of the actions
+    pub window_millis: Option<i64>,
+    /// Maximum total balance of the account
+    pub max_balance: Option<f32>,
+}
+
+impl Limits {
+    /// Merge limits - not provided values are taken from the fallback
+    fn or(self, fallback: Limits) -> Limits {
+        Limits {
+            max_withdrawal: self.max_withdrawal.or(fallback.max_withdrawal),
+            max_window_withdrawal: self
+                .max_window_withdrawal
+                .or(fallback.max_window_withdrawal),
+            window_rows: self.window_rows.or(fallback.window_rows),
+            window_millis: self.window_millis.or(fallback.window_millis),
+            max_balance: self.max_balance.or(fallback.max_balance),
+        }
+    }
+}
+
+/// Limits configuration - default, per tier and per client limits
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Limits applied on all clients
+    pub default: Limits,
+    /// Tier name to limits mapping - overrides default limits
+    pub tiers: BTreeMap<String, Limits>,
+    /// Client id to limits mapping - overrides tier and default limits
+    pub clients: BTreeMap<u16, Limits>,
+}
+
+impl LimitsConfig {
+    /// Get effective limits of the client
+    pub fn limits_of(&self, client_id: u16, tiers: &ClientTiers) -> Limits {
+        let tier_limits = tiers
+            .tier_of(client_id)
+            .and_then(|t| self.tiers.get(t))
+            .copied()
+            .unwrap_or_default();
+
+        self.clients
+            .get(&client_id)
+            .copied()
+            .unwrap_or_default()
+            .or(tier_limits)
+            .or(self.default)
+    }
+}
+
+/// Tracker of clients withdrawals - to check cumulative withdrawal within the window
+#[derive(Default, Clone)]
+pub struct WithdrawalWindows {
+    /// Client id to withdrawals in processing order
+    client_to_withdrawals: HashMap<u16, VecDeque<WindowEntry>>,
+}
+
+/// Applied withdrawal within the window
+#[derive(Clone)]
+struct WindowEntry {
+    row: u64,
+    timestamp: Option<i64>,
+    amount: f32,
+}
+
+impl WindowEntry {
+    /// Whether the entry is out of the window ending on the provided row/timestamp
+    /// Entries without timestamp are considered only by row
+    fn is_expired(&self, row: u64, timestamp: Option<i64>, limits: &Limits) -> bool {
+        let row_expired = limits
+            .window_rows
+            .filter(|d| self.row <= row.saturating_sub(*d))
+            .is_some();
+        let time_expired = match (limits.window_millis, self.timestamp, timestamp) {
+            (Some(window), Some(entry_time), Some(time)) => {
+                entry_time <= time.saturating_sub(window)
+            }
+            _ => false,
+        };
+
+        row_expired || time_expired
+    }
+}
+
+impl WithdrawalWindows {
+    /// Check withdrawal against the limits
+    pub fn check(
+        &self,
+        client_id: u16,
+        amount: f32,
+        row: u64,
+        timestamp: Option<i64>,
+        limits: &Limits,
+    ) -> Result<(), Rejection> {
+        if limits.max_withdrawal.filter(|d| amount > *d).is_some() {
+            return Err(Rejection::MaxWithdrawalExceeded);
+        }
+
+        let max_window_withdrawal = match limits.max_window_withdrawal {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        let withdrawn: f32 = self
+            .client_to_withdrawals
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .filter(|d| !d.is_expired(row, timestamp, limits))
+            .map(|d| d.amount)
+            .sum();
+
+        if withdrawn + amount > max_window_withdrawal {
+            return Err(Rejection::WindowWithdrawalExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Keep track on applied withdrawal
+    /// Withdrawals out of the window are dropped
+    pub fn record(
+        &mut self,
+        client_id: u16,
+        amount: f32,
+        row: u64,
+        timestamp: Option<i64>,
+        limits: &Limits,
+    ) {
+        let withdrawals = self.client_to_withdrawals.entry(client_id).or_default();
+        withdrawals.push_back(WindowEntry {
+            row,
+            timestamp,
+            amount,
+        });
+
+        while withdrawals
+            .front()
+            .filter(|d| d.is_expired(row, timestamp, limits))
+            .is_some()
+        {
+            withdrawals.pop_front();
+        }
+    }
+
+    /// Drop the withdrawal of the row - i.e. declined one
+    pub fn remove(&mut self, client_id: u16, row: u64) {
+        if let Some(withdrawals) = self.client_to_withdrawals.get_mut(&client_id) {
+            withdrawals.retain(|d| d.row != row);
+        }
+    }
+}
+
+/// Check balance of the client against the limits
+pub fn check_balance(total: f32, limits: &Limits) -> Result<(), Rejection> {
+    if limits.max_balance.filter(|d| total > *d).is_some() {
+        return Err(Rejection::MaxBalanceExceeded);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+    use crate::limits::{check_balance, WithdrawalWindows};
+    use crate::rejection::Rejection;
+
+    #[test]
+    pub fn test_limits_config() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [tiers]
+            gold = [1, 2]
+
+            [limits.default]
+            max_withdrawal = 100.0
+            max_balance = 1000.0
+
+            [limits.tiers.gold]
+            max_withdrawal = 500.0
+            max_window_withdrawal = 700.0
+
+            [limits.clients.2]
+            max_balance = 5000.0
+            "#,
+        )
+        .unwrap();
+
+        let limits = config.limits.limits_of(3, &config.tiers);
+        assert_eq!(limits.max_withdrawal, Some(100.));
+        assert_eq!(limits.max_balance, Some(1000.));
+        assert!(limits.max_window_withdrawal.is_none());
+
+        let limits = config.limits.limits_of(1, &config.tiers);
+        assert_eq!(limits.max_withdrawal, Some(500.));
+        assert_eq!(limits.max_window_withdrawal, Some(700.));
+        assert_eq!(limits.max_balance, Some(1000.));
+
+        let limits = config.limits.limits_of(2, &config.tiers);
+        assert_eq!(limits.max_withdrawal, Some(500.));
+        assert_eq!(limits.max_balance, Some(5000.));
+    }
+
+    #[test]
+    pub fn test_withdrawal_windows() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [limits.default]
+            max_withdrawal = 10.0
+            max_window_withdrawal = 15.0
+            window_rows = 3
+            "#,
+        )
+        .unwrap();
+        let limits = config.limits.limits_of(1, &config.tiers);
+        let mut windows = WithdrawalWindows::default();
+
+        assert_eq!(
+            windows.check(1, 11., 1, None, &limits),
+            Err(Rejection::MaxWithdrawalExceeded)
+        );
+        assert!(windows.check(1, 10., 1, None, &limits).is_ok());
+        windows.record(1, 10., 1, None, &limits);
+
+        assert_eq!(
+            windows.check(1, 6., 2, None, &limits),
+            Err(Rejection::WindowWithdrawalExceeded)
+        );
+        assert!(
+            windows.check(2, 6., 2, None, &limits).is_ok(),
+            "Other client!"
+        );
+        assert!(windows.check(1, 5., 3, None, &limits).is_ok());
+        windows.record(1, 5., 3, None, &limits);
+
+        assert_eq!(
+            windows.check(1, 1., 3, None, &limits),
+            Err(Rejection::WindowWithdrawalExceeded)
+        );
+        assert!(
+            windows.check(1, 10., 4, None, &limits).is_ok(),
+            "Row 1 is out of the window"
+        );
+
+        assert!(check_balance(10., &limits).is_ok(), "No balance limit");
+    }
+
+    #[test]
+    pub fn test_time_windows() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [limits.default]
+            max_window_withdrawal = 15.0
+            window_millis = 1000
+            "#,
+        )
+        .unwrap();
+        let limits = config.limits.limits_of(1, &config.tiers);
+        let mut windows = WithdrawalWindows::default();
+
+        windows.record(1, 10., 1, Some(1000), &limits);
+        assert_eq!(
+            windows.check(1, 6., 2, Some(1999), &limits),
+            Err(Rejection::WindowWithdrawalExceeded)
+        );
+        assert_eq!(
+            windows.check(1, 6., 2, None, &limits),
+            Err(Rejection::WindowWithdrawalExceeded),
+            "No timestamp - can't be expired"
+        );
+        assert!(windows.check(1, 6., 2, Some(2000), &limits).is_ok());
+        assert_eq!(
+            windows.check(1, 6., 2, Some(i64::MIN), &limits),
+            Err(Rejection::WindowWithdrawalExceeded),
+            "Far past time doesn't overflow"
+        );
+    }
+}
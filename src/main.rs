@@ -1,59 +1,784 @@
-use crate::client_state::ClientState;
-use crate::client_state_mgr::ClientsStatesMgr;
-use crate::csv_processor::{generate_csv, TransactionIOLoader};
-use crate::transaction_details::{TransactionDetails, TransactionType};
-use crate::transaction_mgr::TransactionMgr;
-use crate::transaction_processor::TransactionsProcessor;
-
-mod client_state;
-mod client_state_mgr;
-mod csv_processor;
-mod transaction_details;
-mod transaction_mgr;
-mod transaction_processor;
-
-fn main() {
-    let mut args = std::env::args();
-    if args.len() != 2 {
-        return;
-    }
-
-    let transactions_path = match args.nth(1) {
-        Some(path) => path,
-        None => return,
+use base_transaction::audit::{verify_audit_file, AuditLog};
+use base_transaction::client_state_mgr::ClientsStatesMgr;
+use base_transaction::client_state_store::{
+    ClientStateStore, FileClientStateStore, InMemoryClientStateStore,
+};
+use base_transaction::config::EngineConfig;
+use base_transaction::csv_processor::{
+    generate_csv, load_client_states, load_reviews, TransactionIOLoader,
+};
+use base_transaction::diff::StatesDiff;
+use base_transaction::ledger::Ledger;
+use base_transaction::metrics::MetricsRegistry;
+use base_transaction::ordering::{OrderingMode, ReorderingLoader};
+use base_transaction::policy::ParseErrorPolicy;
+use base_transaction::reconcile::ReconcileReport;
+use base_transaction::rejection::Rejection;
+use base_transaction::replay::{replay, Breakpoints};
+use base_transaction::transaction_details::parse_timestamp;
+use base_transaction::transaction_mgr::{Review, TransactionMgr};
+use base_transaction::transaction_processor::{ProcessingState, TransactionsProcessor};
+use base_transaction::transaction_store::{
+    CompactTransactionStore, DiskTransactionStore, HashMapTransactionStore, LruCachedStore,
+};
+use base_transaction::validation::ValidationReport;
+use clap::{Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use std::net::TcpListener;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Store of the transaction history
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TxStore {
+    /// Memory efficient in-memory store - for dense tx ids
+    Compact,
+    /// Plain in-memory hash map
+    HashMap,
+    /// On-disk store with in-memory LRU cache - for histories larger than RAM
+    Disk,
+}
+
+/// Output format of the report
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    /// Human-readable text
+    Text,
+    /// Csv - one row per change
+    Csv,
+}
+
+/// Command line arguments
+/// Without command the input files are processed and clients states are printed
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Csv files of transaction details - processed in order, each file is a separate batch
+    inputs: Vec<String>,
+    /// TOML file of engine configuration - i.e. fee schedule, limits
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// Column mapping profile of the input files - overrides the one of the config file
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    /// Point in time (epoch millis or RFC 3339) - actions after it are skipped
+    #[arg(long, global = true, value_parser = parse_timestamp)]
+    as_of: Option<i64>,
+    /// Store of the transaction history - disk with --state-file, hash-map otherwise
+    #[arg(long, value_enum)]
+    tx_store: Option<TxStore>,
+    /// Directory of the on-disk transaction store - next to the state file (i.e. states.csv.tx_history)
+    /// or temporary one if not provided
+    #[arg(long)]
+    tx_store_path: Option<String>,
+    /// Number of transaction records cached in memory for the on-disk store
+    #[arg(long, default_value_t = 100_000)]
+    tx_cache_size: usize,
+    /// Csv file of clients states - states are loaded from it and written back after processing
+    #[arg(long, global = true)]
+    state_file: Option<String>,
+    /// Increase logging verbosity (stderr): -v rejections, -vv applied actions, -vvv debug
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Disable logging
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Audit log file (JSON lines with hash chain) - new records are appended
+    #[arg(long)]
+    audit_log: Option<String>,
+    /// Directory of the ingestion ledger - already processed files and transactions seen in
+    /// other files are skipped across runs
+    #[arg(long)]
+    ledger: Option<String>,
+    /// Apply each file all-or-nothing - any rejected row rolls back the whole file
+    #[arg(long)]
+    atomic: bool,
+    /// Csv file of admin actions (unlock, lock, close, approve, decline) - applied after the input files
+    #[arg(long)]
+    admin: Option<String>,
+    /// Csv file of open reviews - reviews are loaded from it and written back after processing
+    #[arg(long, global = true)]
+    reviews: Option<String>,
+    /// JSON file to dump metrics of the run to
+    #[arg(long)]
+    metrics_json: Option<String>,
+    /// Local address (i.e. 127.0.0.1:9100) to expose metrics in Prometheus text format during the processing
+    #[arg(long)]
+    metrics_listen: Option<String>,
+    /// Seconds to keep serving the final metrics after the processing - i.e. for the last scrape
+    #[arg(long, default_value_t = 0, requires = "metrics_listen")]
+    metrics_linger: u64,
+}
+
+/// Supported commands
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Dry-run of the file - reports parse errors, rejections and lock status changes
+    /// Nothing is written, clients states are not printed
+    Validate {
+        /// Csv file of transaction details
+        input: String,
+    },
+    /// Process the file and compare clients states with the expected ones
+    /// Transactions of mismatched clients are shown
+    Reconcile {
+        /// Csv file of transaction details
+        input: String,
+        /// Csv file of expected clients states - i.e. partner's closing balances
+        #[arg(long)]
+        expected: String,
+        /// Differences not more than the tolerance are ignored
+        #[arg(long, default_value_t = 0.0001)]
+        tolerance: f32,
+        /// Exit code is non-zero if any difference is more than the threshold
+        #[arg(long, default_value_t = 0.)]
+        threshold: f32,
+    },
+    /// Re-run the file and stop at the tx, row or client - client state and tx record are printed
+    Replay {
+        /// Csv file of transaction details
+        input: String,
+        /// Stop at actions referencing the transaction
+        #[arg(long)]
+        tx: Vec<u32>,
+        /// Stop at the row
+        #[arg(long)]
+        row: Vec<u64>,
+        /// Stop at actions of the client
+        #[arg(long)]
+        client: Vec<u16>,
+        /// Interactive step mode - next command is read from the terminal after each stop
+        #[arg(long)]
+        step: bool,
+    },
+    /// Engine configuration commands
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Accrue interest on the clients states of the state file - states are written back
+    /// Rates are taken from the config file, the audit log is appended if provided
+    Accrue,
+    /// List open reviews of the review file
+    Reviews,
+    /// Verify hash chain of the audit log - any edited, dropped or truncated record is reported
+    VerifyAudit {
+        /// Audit log file
+        path: String,
+    },
+    /// Compare two csv files of clients states - i.e. today's output vs yesterday's one
+    Diff {
+        /// Old clients states
+        old: String,
+        /// New clients states
+        new: String,
+        /// Balance changes not more than the tolerance are ignored
+        #[arg(long, default_value_t = 0.0001)]
+        tolerance: f32,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+}
+
+/// Engine configuration commands
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration in TOML format - defaults included
+    Show,
+}
+
+/// Create transaction manager with the requested store
+/// Transactions are kept with the state file - otherwise the next run would post them again
+/// Dry run works on temporary copy of the kept transactions - they aren't changed
+fn create_transaction_mgr(cli: &Cli, dry_run: bool) -> Result<TransactionMgr, anyhow::Error> {
+    let tx_store = match (cli.tx_store, cli.state_file.as_deref()) {
+        (Some(TxStore::Disk) | None, Some(_)) => TxStore::Disk,
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!(
+            "State file requires the disk transaction store - transactions are kept between runs"
+        ))
+        }
+        (tx_store, None) => tx_store.unwrap_or(TxStore::HashMap),
     };
 
-    match run_flow(&transactions_path) {
-        Ok(csv_data) => println!("{}", csv_data.as_str()),
-        Err(e) => eprintln!("{:?}", e),
+    Ok(match tx_store {
+        TxStore::Compact => TransactionMgr::with_store(Box::<CompactTransactionStore>::default()),
+        TxStore::HashMap => TransactionMgr::with_store(Box::<HashMapTransactionStore>::default()),
+        TxStore::Disk => {
+            // History of the temporary store is dropped at the exit - the next run starts over
+            let path = cli.tx_store_path.clone().or_else(|| {
+                cli.state_file
+                    .as_deref()
+                    .map(|d| format!("{}.tx_history", d))
+            });
+            let store = match (path, dry_run) {
+                (Some(path), false) => DiskTransactionStore::open(&path)?,
+                (Some(path), true) => DiskTransactionStore::temporary_copy(&path)?,
+                (None, _) => DiskTransactionStore::temporary()?,
+            };
+            TransactionMgr::with_store(Box::new(LruCachedStore::new(store, cli.tx_cache_size)))
+        }
+    })
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::Builder::new()
+        .filter_level(log_level(cli.verbose, cli.quiet))
+        .target(env_logger::Target::Stderr)
+        .init();
+
+    match run(cli) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Logging level based on the verbosity flags - only errors by default
+fn log_level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Off,
+        (false, 0) => LevelFilter::Error,
+        (false, 1) => LevelFilter::Warn,
+        (false, 2) => LevelFilter::Info,
+        (false, 3) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Run the requested command
+fn run(cli: Cli) -> Result<ExitCode, anyhow::Error> {
+    let mut config = match cli.config.as_deref() {
+        Some(path) => EngineConfig::from_file(path)?,
+        None => EngineConfig::default(),
+    };
+    if let Some(profile) = cli.profile.as_deref() {
+        config.input.profile = Some(profile.to_string());
+        config.input.validate()?;
+    }
+
+    match &cli.command {
+        Some(Command::Validate { input }) => {
+            let report = run_validation(
+                input,
+                config,
+                cli.as_of,
+                cli.state_file.as_deref(),
+                create_transaction_mgr(&cli, true)?,
+            )?;
+            print!("{}", report);
+            if !report.is_clean() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Some(Command::Replay {
+            input,
+            tx,
+            row,
+            client,
+            step,
+        }) => {
+            let breakpoints = Breakpoints {
+                tx: tx.clone(),
+                row: row.clone(),
+                client: client.clone(),
+            };
+            run_replay(
+                input,
+                config,
+                cli.as_of,
+                cli.state_file.as_deref(),
+                create_transaction_mgr(&cli, true)?,
+                &breakpoints,
+                *step,
+            )?;
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Show,
+        }) => {
+            print!("{}", config.to_toml()?);
+        }
+        Some(Command::Accrue) => {
+            let path = cli
+                .state_file
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("State file is not provided"))?;
+            let csv_data = run_accrual(
+                config,
+                create_transaction_mgr(&cli, false)?,
+                ClientsStatesMgr::with_store(Box::new(FileClientStateStore::open(path)?)),
+                cli.audit_log.as_deref().map(AuditLog::open).transpose()?,
+            )?;
+            println!("{}", csv_data.as_str());
+        }
+        Some(Command::Reviews) => {
+            let path = cli
+                .reviews
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("Review file is not provided"))?;
+            let reviews = match std::path::Path::new(path).exists() {
+                true => load_reviews(path)?,
+                false => Vec::new(),
+            };
+            print!("{}", format_reviews(&reviews));
+        }
+        Some(Command::VerifyAudit { path }) => {
+            let summary = verify_audit_file(path)?;
+            println!(
+                "ok: {} records, last hash {}",
+                summary.records, summary.last_hash
+            );
+        }
+        Some(Command::Diff {
+            old,
+            new,
+            tolerance,
+            format,
+        }) => {
+            let diff = StatesDiff::new(
+                &load_client_states(old)?,
+                &load_client_states(new)?,
+                *tolerance,
+            );
+            match format {
+                ReportFormat::Text => print!("{}", diff),
+                ReportFormat::Csv => print!("{}", diff.to_csv()?),
+            }
+        }
+        Some(Command::Reconcile {
+            input,
+            expected,
+            tolerance,
+            threshold,
+        }) => {
+            let report = run_reconciliation(
+                input,
+                config,
+                cli.as_of,
+                cli.state_file.as_deref(),
+                create_transaction_mgr(&cli, true)?,
+                expected,
+                *tolerance,
+            )?;
+            print!("{}", report);
+            if report.exceeds(*threshold) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        None => {
+            if cli.inputs.is_empty() && cli.admin.is_none() {
+                return Err(anyhow::anyhow!("Input file is not provided"));
+            }
+            let transaction_mgr = create_transaction_mgr(&cli, false)?;
+            let client_state_mgr = match cli.state_file.as_deref() {
+                Some(path) => {
+                    ClientsStatesMgr::with_store(Box::new(FileClientStateStore::open(path)?))
+                }
+                None => ClientsStatesMgr::new(),
+            };
+
+            let metrics = MetricsRegistry::default();
+            // Server thread is stopped with the process
+            if let Some(address) = cli.metrics_listen.as_deref() {
+                let listener = TcpListener::bind(address)?;
+                let metrics = metrics.clone();
+                std::thread::spawn(move || metrics.serve(listener));
+            }
+
+            let options = FlowOptions {
+                as_of: cli.as_of,
+                metrics: metrics.clone(),
+                audit: cli.audit_log.as_deref().map(AuditLog::open).transpose()?,
+                ledger: cli.ledger.as_deref().map(Ledger::open).transpose()?,
+                atomic: cli.atomic,
+                admin: cli.admin.clone(),
+                reviews: cli.reviews.clone(),
+                state_file: cli.state_file.clone(),
+            };
+
+            let csv_data = run_flow(
+                &cli.inputs,
+                config,
+                transaction_mgr,
+                client_state_mgr,
+                options,
+            )?;
+            println!("{}", csv_data.as_str());
+
+            if let Some(path) = cli.metrics_json.as_deref() {
+                std::fs::write(path, metrics.to_json()?)?;
+            }
+            if cli.metrics_linger > 0 {
+                log::info!(
+                    "Processing is done, metrics are served for {} more second(s)",
+                    cli.metrics_linger
+                );
+                std::thread::sleep(Duration::from_secs(cli.metrics_linger));
+            }
+        }
     }
+
+    Ok(ExitCode::SUCCESS)
 }
 
-/// Run the workflow
-fn run_flow(path: &str) -> Result<String, anyhow::Error> {
-    let mut client_state_mgr = ClientsStatesMgr::new();
-    let mut transaction_mgr = TransactionMgr::new();
+/// Load the input file - headers are mapped with the column aliases of the config
+/// Rows which can't be parsed fail the load or are skipped based on the config
+fn load_input(path: &str, config: &EngineConfig) -> Result<TransactionIOLoader, anyhow::Error> {
+    let mapping = config.input.mapping()?;
+    match config.input.parse_errors {
+        ParseErrorPolicy::Fail => TransactionIOLoader::new(path, &mapping),
+        ParseErrorPolicy::Skip => {
+            let (loader, parse_errors) = TransactionIOLoader::new_lenient(path, &mapping)?;
+            for e in parse_errors {
+                log::warn!(
+                    file = path,
+                    line = e.line;
+                    "row skipped - {}",
+                    e.message
+                );
+            }
+            Ok(loader)
+        }
+    }
+}
+
+/// Reorder window of the loader - window 0 keeps the file order
+fn reorder_window(config: &EngineConfig) -> usize {
+    match config.ordering.mode {
+        OrderingMode::Reorder => config.ordering.reorder_window,
+        _ => 0,
+    }
+}
+
+/// Optional parts of the workflow
+#[derive(Default)]
+struct FlowOptions {
+    /// Point in time - actions after it are skipped
+    as_of: Option<i64>,
+    metrics: MetricsRegistry,
+    audit: Option<AuditLog>,
+    /// Ledger of ingested batches - already processed files are skipped
+    ledger: Option<Ledger>,
+    /// Whether each file is applied all-or-nothing
+    atomic: bool,
+    /// File of admin actions - processed as the last batch
+    admin: Option<String>,
+    /// File of open reviews - loaded before the first batch, written after each one
+    reviews: Option<String>,
+    /// File the clients states are persisted to - to recover the ledger batch interrupted on finish
+    state_file: Option<String>,
+}
+
+/// Run the workflow - each file is processed as a separate batch
+fn run_flow<P: AsRef<str>>(
+    paths: &[P],
+    config: EngineConfig,
+    mut transaction_mgr: TransactionMgr,
+    mut client_state_mgr: ClientsStatesMgr,
+    mut options: FlowOptions,
+) -> Result<String, anyhow::Error> {
+    let reorder_window = reorder_window(&config);
+    if let Some(path) = options
+        .reviews
+        .as_deref()
+        .filter(|d| std::path::Path::new(d).exists())
+    {
+        for review in load_reviews(path)? {
+            transaction_mgr.insert_review(review);
+        }
+    }
+
+    let admin = options.admin.take();
+    // Windows and deadlines continue across the files
+    let mut state = ProcessingState::default();
+    let batches = paths
+        .iter()
+        .map(|d| (d.as_ref(), false))
+        .chain(admin.as_deref().map(|d| (d, true)));
+
+    for (path, is_admin) in batches {
+        if let Some(ledger) = options.ledger.as_mut() {
+            if let Some(entry) = ledger.start_batch(path)? {
+                log::warn!(
+                    file = path,
+                    processed_at = entry.processed_at.as_str();
+                    "file is already processed - skipped"
+                );
+                continue;
+            }
+        }
+
+        let loader = match is_admin {
+            true => TransactionIOLoader::new_admin(path, &config.input.mapping()?)?,
+            false => load_input(path, &config)?,
+        };
+        let mut transaction_actions_processor = TransactionsProcessor::new(
+            &mut client_state_mgr,
+            &mut transaction_mgr,
+            ReorderingLoader::new(loader, reorder_window),
+        )
+        .with_config(config.clone())
+        .with_as_of(options.as_of)
+        .with_metrics(options.metrics.clone())
+        .with_state(state.clone());
+        if let Some(audit) = options.audit.take() {
+            transaction_actions_processor = transaction_actions_processor.with_audit(audit);
+        }
+        if let Some(ledger) = options.ledger.take() {
+            transaction_actions_processor = transaction_actions_processor.with_ledger(ledger);
+        }
+
+        let report = match options.atomic {
+            true => transaction_actions_processor.apply_transaction_actions_atomically()?,
+            false => transaction_actions_processor.apply_transaction_actions()?,
+        };
+        options.audit = transaction_actions_processor.take_audit();
+        options.ledger = transaction_actions_processor.take_ledger();
+
+        // Rolled back file isn't added to the ledger - it can be fixed and sent again
+        // Its state is dropped as well - the next file continues from the previous one
+        if options.atomic && report.rejected() > 0 {
+            log::error!(
+                file = path,
+                rows = report.rows;
+                "file is rolled back - {} row(s) rejected",
+                report.rejected() - report.rejections.get(&Rejection::BatchRolledBack).unwrap_or(&0)
+            );
+            continue;
+        }
+        state = transaction_actions_processor.take_state();
+
+        // States are persisted before the batch is marked as processed
+        // Prepared batch is finished on the next open if the run is interrupted after the states are persisted
+        if let Some(ledger) = options.ledger.as_mut() {
+            ledger.prepare_batch(
+                report.rows,
+                options.state_file.as_deref().map(std::path::Path::new),
+            )?;
+        }
+        client_state_mgr.flush()?;
+        if let Some(path) = options.reviews.as_deref() {
+            std::fs::write(path, generate_csv(&transaction_mgr.reviews())?)?;
+        }
+        if let Some(ledger) = options.ledger.as_mut() {
+            ledger.finish_batch()?;
+        }
+    }
+
+    generate_csv(&client_state_mgr.get_states()?)
+}
+
+/// Accrue interest on the clients states - states are persisted after it
+fn run_accrual(
+    config: EngineConfig,
+    mut transaction_mgr: TransactionMgr,
+    mut client_state_mgr: ClientsStatesMgr,
+    audit: Option<AuditLog>,
+) -> Result<String, anyhow::Error> {
+    let mut processor = TransactionsProcessor::new(
+        &mut client_state_mgr,
+        &mut transaction_mgr,
+        TransactionIOLoader::from(Vec::new()),
+    )
+    .with_config(config);
+    if let Some(audit) = audit {
+        processor = processor.with_audit(audit);
+    }
+
+    let credited = match processor.accrue_interest() {
+        Ok(d) => d,
+        Err(Rejection::StoreFailure) => return Err(processor.take_store_error()),
+        Err(reason) => return Err(anyhow::anyhow!("Accrual is rejected: {}", reason)),
+    };
+    log::info!(clients = credited; "interest accrued");
+
+    client_state_mgr.flush()?;
+    generate_csv(&client_state_mgr.get_states()?)
+}
+
+/// Open reviews in text format - one line per review
+fn format_reviews(reviews: &[Review]) -> String {
+    let mut output = format!("open reviews: {}\n", reviews.len());
+    for d in reviews {
+        let transaction_type: &str = d.transaction_type.into();
+        output.push_str(&format!(
+            "  tx {}: client {} {} {}{} row {} rules {}\n",
+            d.tx,
+            d.client,
+            transaction_type,
+            d.amount,
+            if d.held { " held" } else { "" },
+            d.row,
+            d.rules
+        ));
+    }
+    output
+}
+
+/// Load in-memory copy of the clients states - the file isn't changed
+fn load_states_copy(state_file: Option<&str>) -> Result<InMemoryClientStateStore, anyhow::Error> {
+    let mut states = InMemoryClientStateStore::default();
+    if let Some(state_file) = state_file {
+        for state in FileClientStateStore::open(state_file)?.iterate()? {
+            states.upsert(state)?;
+        }
+    }
+
+    Ok(states)
+}
+
+/// Run the workflow against in-memory copy of the states - nothing is written
+/// Transaction manager should work on copy of the kept transactions as well
+/// Rows which can't be parsed are reported instead of stopping the processing
+fn run_validation(
+    path: &str,
+    config: EngineConfig,
+    as_of: Option<i64>,
+    state_file: Option<&str>,
+    mut transaction_mgr: TransactionMgr,
+) -> Result<ValidationReport, anyhow::Error> {
+    let states = load_states_copy(state_file)?;
+    let before = states.iterate()?;
+
+    let reorder_window = reorder_window(&config);
+    let (loader, parse_errors) = TransactionIOLoader::new_lenient(path, &config.input.mapping()?)?;
+
+    let mut client_state_mgr = ClientsStatesMgr::with_store(Box::new(states));
+    let processing = TransactionsProcessor::new(
+        &mut client_state_mgr,
+        &mut transaction_mgr,
+        ReorderingLoader::new(loader, reorder_window),
+    )
+    .with_config(config)
+    .with_as_of(as_of)
+    .apply_transaction_actions()?;
+
+    Ok(ValidationReport::new(
+        parse_errors,
+        processing,
+        &before,
+        &client_state_mgr.get_states()?,
+    ))
+}
+
+/// Run the workflow against in-memory copy of the states and compare them with the expected ones
+/// Transaction manager should work on copy of the kept transactions as well
+fn run_reconciliation(
+    path: &str,
+    config: EngineConfig,
+    as_of: Option<i64>,
+    state_file: Option<&str>,
+    mut transaction_mgr: TransactionMgr,
+    expected_path: &str,
+    tolerance: f32,
+) -> Result<ReconcileReport, anyhow::Error> {
+    let expected = load_client_states(expected_path)?;
+    let reorder_window = reorder_window(&config);
+
+    let mut client_state_mgr =
+        ClientsStatesMgr::with_store(Box::new(load_states_copy(state_file)?));
     let mut transaction_actions_processor = TransactionsProcessor::new(
         &mut client_state_mgr,
         &mut transaction_mgr,
-        TransactionIOLoader::new(path)?,
-    );
+        ReorderingLoader::new(load_input(path, &config)?, reorder_window),
+    )
+    .with_config(config)
+    .with_as_of(as_of)
+    .with_history();
+
+    transaction_actions_processor.apply_transaction_actions()?;
+    let history = transaction_actions_processor
+        .take_history()
+        .ok_or_else(|| anyhow::anyhow!("History is not kept"))?;
+
+    Ok(ReconcileReport::new(
+        &expected,
+        &client_state_mgr.get_states()?,
+        &history,
+        tolerance,
+    ))
+}
 
-    transaction_actions_processor.apply_transaction_actions();
+/// Re-run the file against in-memory copy of the states and stop at the breakpoints
+/// Transaction manager should work on copy of the kept transactions as well
+fn run_replay(
+    path: &str,
+    config: EngineConfig,
+    as_of: Option<i64>,
+    state_file: Option<&str>,
+    mut transaction_mgr: TransactionMgr,
+    breakpoints: &Breakpoints,
+    step: bool,
+) -> Result<(), anyhow::Error> {
+    let reorder_window = reorder_window(&config);
+
+    let mut client_state_mgr =
+        ClientsStatesMgr::with_store(Box::new(load_states_copy(state_file)?));
+    let mut transaction_actions_processor = TransactionsProcessor::new(
+        &mut client_state_mgr,
+        &mut transaction_mgr,
+        ReorderingLoader::new(load_input(path, &config)?, reorder_window),
+    )
+    .with_config(config)
+    .with_as_of(as_of);
 
-    generate_csv(&client_state_mgr.get_states())
+    replay(
+        &mut transaction_actions_processor,
+        breakpoints,
+        step,
+        std::io::stdin().lock(),
+        &mut std::io::stdout(),
+    )
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{run_flow, ClientState};
+    use crate::{
+        create_transaction_mgr, format_reviews, log_level, run_accrual, run_flow,
+        run_reconciliation, run_validation, Cli, EngineConfig, FlowOptions,
+    };
+    use base_transaction::audit::{verify_audit_file, AuditLog};
+    use base_transaction::client_state_store::FileClientStateStore;
+    use base_transaction::csv_processor::load_reviews;
+    use base_transaction::ledger::Ledger;
+    use base_transaction::metrics::MetricsRegistry;
+    use base_transaction::rejection::Rejection;
+    use base_transaction::transaction_store::DiskTransactionStore;
+    use base_transaction::{
+        AccountStatus, ClientState, ClientsStatesMgr, TransactionDetails, TransactionMgr,
+        TransactionType,
+    };
+    use clap::Parser;
     use float_cmp::approx_eq;
     use std::collections::HashMap;
 
+    /// Managers of the run persisted with the state file - states are reloaded from it on each call
+    /// The database is opened once by the test - each run gets the manager on the shared store
+    fn persisted_mgrs(
+        store: &DiskTransactionStore,
+        state_path: &std::path::Path,
+    ) -> (TransactionMgr, ClientsStatesMgr) {
+        (
+            TransactionMgr::with_store(Box::new(store.clone())),
+            ClientsStatesMgr::with_store(Box::new(FileClientStateStore::open(state_path).unwrap())),
+        )
+    }
+
     #[test]
     pub fn test_flow() {
         let path = "./src/test_utils/transactions.csv";
-        let result = run_flow(path);
+        let result = run_flow(
+            &[path],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        );
 
         assert!(result.is_ok());
 
@@ -70,14 +795,14 @@ mod test {
         assert_eq!(id_to_data.len(), 3);
 
         let c3 = id_to_data.get(&3).unwrap();
-        assert!(c3.locked, "Should be locked due to chargeback!");
+        assert!(c3.is_locked(), "Should be locked due to chargeback!");
         assert!(approx_eq!(f32, c3.total, 11.5, ulps = 4));
         assert!(approx_eq!(f32, c3.available, 11.5, ulps = 4));
         assert!(approx_eq!(f32, c3.held, 0., ulps = 4));
 
         let c5 = id_to_data.get(&5).unwrap();
         assert!(
-            !c5.locked,
+            !c5.is_locked(),
             "Should not be locked due to incorrect chargeback!"
         );
         assert!(approx_eq!(f32, c5.total, 32.3343, ulps = 4));
@@ -86,11 +811,951 @@ mod test {
 
         let c1 = id_to_data.get(&1).unwrap();
         assert!(
-            !c1.locked,
+            !c1.is_locked(),
             "Should not be locked as there is no chargeback!"
         );
         assert!(approx_eq!(f32, c1.total, 28., ulps = 4));
         assert!(approx_eq!(f32, c1.held, 0., ulps = 4));
         assert!(approx_eq!(f32, c1.available, 28., ulps = 4));
     }
+
+    #[test]
+    pub fn test_flow_with_fees() {
+        let path = "./src/test_utils/transactions.csv";
+        let config = EngineConfig::from_file("./src/test_utils/config.toml").unwrap();
+        let result = run_flow(
+            &[path],
+            config,
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        )
+        .unwrap();
+
+        let mut rdr = csv::Reader::from_reader(result.as_bytes());
+        let id_to_data: HashMap<u16, ClientState> = rdr
+            .deserialize()
+            .map(|r: Result<ClientState, _>| r.unwrap())
+            .map(|d| (d.client, d))
+            .collect();
+        assert_eq!(id_to_data.len(), 3);
+
+        // 1% deposit fee: 2.5 & 3 deposits, then the fee of chargebacked tx 5 is refunded
+        let c3 = id_to_data.get(&3).unwrap();
+        assert!(c3.is_locked());
+        assert!(approx_eq!(f32, c3.fees, 0.025 + 0.105 + 0.5, ulps = 4));
+        assert!(approx_eq!(f32, c3.total, 11.5 - c3.fees, ulps = 4));
+
+        // Gold tier - no deposit fee, flat withdrawal fee
+        let c1 = id_to_data.get(&1).unwrap();
+        assert!(!c1.is_locked());
+        assert!(approx_eq!(f32, c1.fees, 0.5, ulps = 4));
+        assert!(approx_eq!(f32, c1.total, 27.5, ulps = 4));
+        assert!(approx_eq!(f32, c1.available, 27.5, ulps = 4));
+    }
+
+    #[test]
+    pub fn test_flow_with_timestamps() {
+        let path = "./src/test_utils/transactions_timestamps.csv";
+        let config = EngineConfig::from_toml("[ordering]\nmode = \"reorder\"\nreorder_window = 2");
+        let result = run_flow(
+            &[path],
+            config.unwrap(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                as_of: Some(1650000060000),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let mut rdr = csv::Reader::from_reader(result.as_bytes());
+        let id_to_data: HashMap<u16, ClientState> = rdr
+            .deserialize()
+            .map(|r: Result<ClientState, _>| r.unwrap())
+            .map(|d| (d.client, d))
+            .collect();
+        assert_eq!(id_to_data.len(), 1);
+
+        // Withdrawal is applied after reordered deposit, late deposit is rejected, the last one is after as-of
+        let c1 = id_to_data.get(&1).unwrap();
+        assert!(approx_eq!(f32, c1.total, 15., ulps = 4));
+    }
+
+    #[test]
+    pub fn test_flow_with_disk_store() {
+        let path = "./src/test_utils/transactions.csv";
+        let store = DiskTransactionStore::temporary().unwrap();
+        let transaction_mgr = TransactionMgr::with_store(Box::new(store));
+        let result = run_flow(
+            &[path],
+            EngineConfig::default(),
+            transaction_mgr,
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        )
+        .unwrap();
+
+        let expected = run_flow(
+            &[path],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        );
+        let mut lines: Vec<&str> = result.lines().collect();
+        let expected = expected.unwrap();
+        let mut expected_lines: Vec<&str> = expected.lines().collect();
+        lines.sort();
+        expected_lines.sort();
+        assert_eq!(lines, expected_lines, "Store shouldn't change the result!");
+    }
+
+    #[test]
+    pub fn test_flow_with_disk_store_rerun() {
+        let path = "./src/test_utils/transactions.csv";
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("tx_history");
+        let run = |args: &[&str]| {
+            let cli = Cli::parse_from([&["base_transaction", path], args].concat());
+            let result = run_flow(
+                &cli.inputs,
+                EngineConfig::default(),
+                create_transaction_mgr(&cli, false).unwrap(),
+                ClientsStatesMgr::new(),
+                FlowOptions::default(),
+            )
+            .unwrap();
+            let mut lines: Vec<String> = result.lines().map(|d| d.to_string()).collect();
+            lines.sort();
+            lines.join("\n")
+        };
+
+        // Temporary store is dropped after the run - the rerun gives the same result
+        let first = run(&["--tx-store", "disk"]);
+        assert!(first.contains("1,28.0,0.0,28.0,false"));
+        assert_eq!(run(&["--tx-store", "disk"]), first);
+
+        // Provided store is kept - it can't be reopened within the process, so only once
+        let args = [
+            "--tx-store",
+            "disk",
+            "--tx-store-path",
+            store_path.to_str().unwrap(),
+        ];
+        assert_eq!(run(&args), first);
+        assert!(store_path.exists());
+    }
+
+    #[test]
+    pub fn test_flow_with_state_file() {
+        let path = "./src/test_utils/transactions.csv";
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("states.csv");
+        let state_file = state_path.to_str().unwrap();
+
+        // Transactions are kept next to the state file
+        let cli = Cli::parse_from(["base_transaction", path, "--state-file", state_file]);
+        let transaction_mgr = create_transaction_mgr(&cli, false).unwrap();
+        assert!(dir.path().join("states.csv.tx_history").exists());
+        drop(transaction_mgr);
+        let cli = Cli::parse_from([
+            "base_transaction",
+            path,
+            "--state-file",
+            state_file,
+            "--tx-store",
+            "compact",
+        ]);
+        assert!(create_transaction_mgr(&cli, false).is_err());
+
+        // The same file is processed twice - its transactions are rejected by the second run
+        let store_path = dir.path().join("tx_history");
+        let store = DiskTransactionStore::open(store_path.to_str().unwrap()).unwrap();
+        for _ in 0..2 {
+            let (transaction_mgr, client_state_mgr) = persisted_mgrs(&store, &state_path);
+            let result = run_flow(
+                &[path],
+                EngineConfig::default(),
+                transaction_mgr,
+                client_state_mgr,
+                FlowOptions::default(),
+            )
+            .unwrap();
+
+            let mut rdr = csv::Reader::from_reader(result.as_bytes());
+            let id_to_data: HashMap<u16, ClientState> = rdr
+                .deserialize()
+                .map(|r: Result<ClientState, _>| r.unwrap())
+                .map(|d| (d.client, d))
+                .collect();
+            let c1 = id_to_data.get(&1).unwrap();
+            assert!(approx_eq!(f32, c1.total, 28., ulps = 4));
+        }
+
+        let store = FileClientStateStore::open(&state_path).unwrap();
+        let states = ClientsStatesMgr::with_store(Box::new(store));
+        assert_eq!(states.get_states().unwrap().len(), 3);
+    }
+
+    #[test]
+    pub fn test_flow_with_dispute_across_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("tx_history");
+        let state_path = dir.path().join("states.csv");
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\ndispute,1,1,4.0\n",
+        )
+        .unwrap();
+        std::fs::write(&second, "transaction_type,client,tx,amount\nresolve,1,1,\n").unwrap();
+
+        let store = DiskTransactionStore::open(store_path.to_str().unwrap()).unwrap();
+        let run = |path: &std::path::Path| {
+            let (transaction_mgr, client_state_mgr) = persisted_mgrs(&store, &state_path);
+            run_flow(
+                &[path.to_str().unwrap()],
+                EngineConfig::default(),
+                transaction_mgr,
+                client_state_mgr,
+                FlowOptions::default(),
+            )
+            .unwrap()
+        };
+
+        // Only the disputed portion is released by the resolve of the next run
+        assert!(run(&first).contains("1,6.0,4.0,10.0,false"));
+        assert!(run(&second).contains("1,10.0,0.0,10.0,false"));
+    }
+
+    #[test]
+    pub fn test_flow_with_transfer_and_fee_across_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("tx_history");
+        let state_path = dir.path().join("states.csv");
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount,destination\ndeposit,1,1,10.0,\n\
+             deposit,2,2,5.0,\ntransfer,1,3,3.0,2\ndeposit,3,4,10.0,\ndeposit,3,5,10.0,\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "transaction_type,client,tx,amount\ndispute,1,3,\nchargeback,1,3,\n\
+             dispute,3,4,\nchargeback,3,4,\n",
+        )
+        .unwrap();
+        let config = "[[fees]]\ntransaction_type = \"deposit\"\nflat = 1.0";
+
+        let store = DiskTransactionStore::open(store_path.to_str().unwrap()).unwrap();
+        let run = |path: &std::path::Path| {
+            let (transaction_mgr, client_state_mgr) = persisted_mgrs(&store, &state_path);
+            run_flow(
+                &[path.to_str().unwrap()],
+                EngineConfig::from_toml(config).unwrap(),
+                transaction_mgr,
+                client_state_mgr,
+                FlowOptions::default(),
+            )
+            .unwrap()
+        };
+
+        // Transfer of the previous run is charged back from its destination, deposit fee is refunded
+        run(&first);
+        let result = run(&second);
+        assert!(result.contains("1,9.0,0.0,9.0,false,1.0,active"));
+        assert!(result.contains("2,4.0,0.0,4.0,true,1.0,locked"));
+        assert!(result.contains("3,9.0,0.0,9.0,true,1.0,locked"));
+    }
+
+    #[test]
+    pub fn test_validation() {
+        let path = "./src/test_utils/transactions_invalid.csv";
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("states.csv");
+        std::fs::write(
+            &state_path,
+            "client,available,held,total,locked,fees\n1,5.0,0.0,5.0,false,0.0\n",
+        )
+        .unwrap();
+        let state_file = state_path.to_str();
+
+        let report = run_validation(
+            path,
+            EngineConfig::default(),
+            None,
+            state_file,
+            TransactionMgr::new(),
+        )
+        .unwrap();
+        assert!(!report.is_clean());
+
+        let lines: Vec<u64> = report.parse_errors.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![3]);
+        assert_eq!(report.processing.rows, 8);
+        // The last withdrawal is covered by the balance from the state file
+        assert_eq!(report.processing.applied, 5);
+        assert_eq!(report.processing.rejected(), 3);
+        assert_eq!(
+            report
+                .processing
+                .rejections
+                .get(&Rejection::InsufficientFunds),
+            Some(&1)
+        );
+
+        assert_eq!(report.lock_changes.len(), 1);
+        assert_eq!(report.lock_changes[0].client, 2);
+        assert!(report.lock_changes[0].locked);
+
+        let states = std::fs::read_to_string(&state_path).unwrap();
+        assert!(
+            states.contains("1,5.0,0.0,5.0,false"),
+            "State file shouldn't be changed!"
+        );
+    }
+
+    #[test]
+    pub fn test_validation_with_kept_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("states.csv");
+        let state_file = state_path.to_str().unwrap();
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "transaction_type,client,tx,amount\ndispute,1,1,\ndeposit,1,1,5.0\n",
+        )
+        .unwrap();
+
+        let cli = Cli::parse_from(["base_transaction", "--state-file", state_file]);
+        let states = FileClientStateStore::open(state_file).unwrap();
+        run_flow(
+            &[first.to_str().unwrap()],
+            EngineConfig::default(),
+            create_transaction_mgr(&cli, false).unwrap(),
+            ClientsStatesMgr::with_store(Box::new(states)),
+            FlowOptions::default(),
+        )
+        .unwrap();
+
+        // Transactions of the previous run are known - the kept ones aren't changed by validation
+        for _ in 0..2 {
+            let report = run_validation(
+                second.to_str().unwrap(),
+                EngineConfig::default(),
+                None,
+                Some(state_file),
+                create_transaction_mgr(&cli, true).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(report.processing.applied, 1, "Dispute of the kept deposit");
+            assert_eq!(
+                report.processing.rejections.get(&Rejection::DuplicateTx),
+                Some(&1)
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_reconciliation() {
+        let path = "./src/test_utils/transactions.csv";
+        let expected = "./src/test_utils/expected_balances.csv";
+        let report = run_reconciliation(
+            path,
+            EngineConfig::default(),
+            None,
+            None,
+            TransactionMgr::new(),
+            expected,
+            0.0001,
+        )
+        .unwrap();
+
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.client, 1);
+        assert_eq!(mismatch.deltas.len(), 2, "Available and total");
+        assert!(report.exceeds(0.5));
+        assert!(!report.exceeds(1.));
+
+        // All actions of the client are shown - including rejected duplicate deposit
+        let txs: Vec<u32> = mismatch.history.iter().map(|d| d.tx).collect();
+        assert_eq!(txs, vec![11, 7, 11, 7, 8, 9, 8, 7, 8]);
+        assert_eq!(mismatch.history[2].rejection, Some(Rejection::DuplicateTx));
+    }
+
+    #[test]
+    pub fn test_reconciliation_with_kept_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("states.csv");
+        let state_file = state_path.to_str().unwrap();
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        let expected = dir.path().join("expected.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "transaction_type,client,tx,amount\ndispute,1,1,\nchargeback,1,1,\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &expected,
+            "client,available,held,total,locked\n1,0.0,0.0,0.0,true\n",
+        )
+        .unwrap();
+
+        let cli = Cli::parse_from(["base_transaction", "--state-file", state_file]);
+        let states = FileClientStateStore::open(state_file).unwrap();
+        run_flow(
+            &[first.to_str().unwrap()],
+            EngineConfig::default(),
+            create_transaction_mgr(&cli, false).unwrap(),
+            ClientsStatesMgr::with_store(Box::new(states)),
+            FlowOptions::default(),
+        )
+        .unwrap();
+
+        // Deposit of the previous run is charged back
+        let report = run_reconciliation(
+            second.to_str().unwrap(),
+            EngineConfig::default(),
+            None,
+            Some(state_file),
+            create_transaction_mgr(&cli, true).unwrap(),
+            expected.to_str().unwrap(),
+            0.0001,
+        )
+        .unwrap();
+        assert!(report.mismatches.is_empty());
+    }
+
+    #[test]
+    pub fn test_flow_metrics() {
+        let path = "./src/test_utils/transactions.csv";
+        let metrics = MetricsRegistry::default();
+        run_flow(
+            &[path],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                metrics: metrics.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let metrics = metrics.snapshot();
+        assert_eq!(metrics.rows_read, 21);
+        assert_eq!(metrics.applied.get("deposit"), Some(&7));
+        assert_eq!(metrics.applied.get("chargeback"), Some(&1));
+        assert_eq!(metrics.rejections.get("duplicate_tx"), Some(&2));
+        assert_eq!(metrics.clients_locked, 1);
+        assert_eq!(metrics.row_duration_micros.count, 21);
+    }
+
+    #[test]
+    pub fn test_log_level() {
+        use log::LevelFilter;
+
+        assert_eq!(log_level(0, false), LevelFilter::Error);
+        assert_eq!(log_level(1, false), LevelFilter::Warn);
+        assert_eq!(log_level(2, false), LevelFilter::Info);
+        assert_eq!(log_level(7, false), LevelFilter::Trace);
+        assert_eq!(log_level(0, true), LevelFilter::Off);
+    }
+
+    #[test]
+    pub fn test_flow_with_audit_log() {
+        let path = "./src/test_utils/transactions.csv";
+        let dir = tempfile::tempdir().unwrap();
+        let audit_path = dir.path().join("audit.jsonl");
+
+        run_flow(
+            &[path],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                audit: Some(AuditLog::open(&audit_path).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let content = std::fs::read_to_string(&audit_path).unwrap();
+        let summary = verify_audit_file(&audit_path).unwrap();
+        assert_eq!(summary.records, 21, "Record per processed row");
+
+        let chargeback: serde_json::Value =
+            serde_json::from_str(content.lines().nth(10).unwrap()).unwrap();
+        assert_eq!(chargeback["details"]["transaction_type"], "chargeback");
+        assert_eq!(chargeback["outcome"], "applied");
+        assert_eq!(chargeback["state"]["locked"], true);
+    }
+
+    #[test]
+    pub fn test_flow_with_state_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount\ndeposit,1,1,100.0\nwithdrawal,1,2,10.0\ndispute,1,1,\n\
+            deposit,2,3,3.0\nwithdrawal,2,6,1.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "transaction_type,client,tx,amount\ndeposit,2,4,1.0\nwithdrawal,1,5,6.0\nwithdrawal,2,7,1.0\n",
+        )
+        .unwrap();
+        let config = EngineConfig::from_toml(
+            r#"
+            [limits.default]
+            max_window_withdrawal = 15.0
+            window_rows = 10
+
+            [disputes]
+            insufficient_funds = "hold"
+            auto_resolve_rows = 2
+
+            [[rules]]
+            rule = "velocity"
+            max_withdrawals = 1
+            window_rows = 10
+            verdict = "deny"
+            "#,
+        )
+        .unwrap();
+
+        let result = run_flow(
+            &[first.to_str().unwrap(), second.to_str().unwrap()],
+            config,
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        )
+        .unwrap();
+
+        // Withdrawal window, dispute deadline and activity for the rules continue in the second file
+        let mut rdr = csv::Reader::from_reader(result.as_bytes());
+        let id_to_data: HashMap<u16, ClientState> = rdr
+            .deserialize()
+            .map(|r: Result<ClientState, _>| r.unwrap())
+            .map(|d| (d.client, d))
+            .collect();
+        let c2 = id_to_data.get(&2).unwrap();
+        assert!(
+            approx_eq!(f32, c2.available, 3., ulps = 4),
+            "Velocity rule denied"
+        );
+        let c1 = id_to_data.get(&1).unwrap();
+        assert!(
+            approx_eq!(f32, c1.held, 0., ulps = 4),
+            "Resolved automatically"
+        );
+        assert!(
+            approx_eq!(f32, c1.available, 90., ulps = 4),
+            "Window is exceeded"
+        );
+    }
+
+    #[test]
+    pub fn test_flow_with_ledger() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger_dir = dir.path().join("ledger");
+        let state_path = dir.path().join("states.csv");
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        std::fs::write(
+            &first,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n",
+        )
+        .unwrap();
+        // Overlaps with the first file by tx 2
+        std::fs::write(
+            &second,
+            "transaction_type,client,tx,amount\ndeposit,1,2,5.0\nwithdrawal,1,3,1.0\nwithdrawal,1,4,100.0\n",
+        )
+        .unwrap();
+        // Rejected tx 4 isn't seen - it can be sent again
+        let third = dir.path().join("third.csv");
+        std::fs::write(
+            &third,
+            "transaction_type,client,tx,amount\ndeposit,1,4,1.0\n",
+        )
+        .unwrap();
+        let first = first.to_str().unwrap();
+        let second = second.to_str().unwrap();
+        let third = third.to_str().unwrap();
+
+        let run = |paths: &[&str]| {
+            let metrics = MetricsRegistry::default();
+            let store = FileClientStateStore::open(&state_path).unwrap();
+            let result = run_flow(
+                paths,
+                EngineConfig::default(),
+                TransactionMgr::new(),
+                ClientsStatesMgr::with_store(Box::new(store)),
+                FlowOptions {
+                    metrics: metrics.clone(),
+                    ledger: Some(Ledger::open(&ledger_dir).unwrap()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let mut rdr = csv::Reader::from_reader(result.as_bytes());
+            let c1: ClientState = rdr.deserialize().next().unwrap().unwrap();
+            (c1.total, metrics.snapshot())
+        };
+
+        let (total, metrics) = run(&[first]);
+        assert!(approx_eq!(f32, total, 15., ulps = 4));
+        assert_eq!(metrics.rows_read, 2);
+
+        // The first file is skipped, overlapping row of the second one is rejected
+        let (total, metrics) = run(&[first, second, third]);
+        assert!(approx_eq!(f32, total, 15., ulps = 4));
+        assert_eq!(metrics.rows_read, 4);
+        assert_eq!(metrics.rejections.get("duplicate_across_batch"), Some(&1));
+        assert_eq!(metrics.rejections.get("insufficient_funds"), Some(&1));
+
+        // Rerun is a no-op
+        let (total, metrics) = run(&[third, second, first]);
+        assert!(approx_eq!(f32, total, 15., ulps = 4));
+        assert_eq!(metrics.rows_read, 0);
+
+        let ledger = Ledger::open(&ledger_dir).unwrap();
+        let rows: Vec<u64> = ledger.batches().iter().map(|d| d.rows).collect();
+        assert_eq!(rows, vec![2, 3, 1]);
+    }
+
+    #[test]
+    pub fn test_atomic_flow() {
+        let dir = tempfile::tempdir().unwrap();
+        let valid = dir.path().join("valid.csv");
+        let invalid = dir.path().join("invalid.csv");
+        std::fs::write(
+            &valid,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &invalid,
+            "transaction_type,client,tx,amount\ndeposit,2,3,5.0\nwithdrawal,1,4,7.0\n",
+        )
+        .unwrap();
+
+        let result = run_flow(
+            &[valid.to_str().unwrap(), invalid.to_str().unwrap()],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                atomic: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let mut rdr = csv::Reader::from_reader(result.as_bytes());
+        let states: Vec<ClientState> = rdr.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(states.len(), 1, "Deposit of client 2 is rolled back");
+        assert!(approx_eq!(f32, states[0].total, 6., ulps = 4));
+    }
+
+    #[test]
+    pub fn test_flow_with_parse_errors() {
+        let path = "./src/test_utils/transactions_invalid.csv";
+        let result = run_flow(
+            &[path],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        );
+        assert!(result.is_err(), "Parse error fails the flow by default");
+
+        let result = run_flow(
+            &[path],
+            EngineConfig::from_toml("[input]\nparse_errors = \"skip\"").unwrap(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        )
+        .unwrap();
+        assert!(result.contains("1,10.0,0.0,10.0,false"));
+        assert!(result.contains("2,0.0,0.0,0.0,true"));
+    }
+
+    #[test]
+    pub fn test_flow_with_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("partner.csv");
+        std::fs::write(
+            &input,
+            "Ref,Type,Customer,Sum,Memo\n1,deposit,1,10.0,salary\n2,withdrawal,1,4.0,rent\n",
+        )
+        .unwrap();
+        let config = EngineConfig::from_toml(
+            r#"
+            [input.profiles.acme]
+            tx = ["ref"]
+            client = ["customer"]
+            amount = ["sum"]
+            "#,
+        )
+        .unwrap();
+
+        // Headers are mapped only with the selected profile
+        let result = run_flow(
+            &[input.to_str().unwrap()],
+            config.clone(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions::default(),
+        );
+        assert!(result.is_err());
+
+        // Admin file is mapped with the profile as well
+        let admin = dir.path().join("admin.csv");
+        std::fs::write(
+            &admin,
+            "Ref,Type,Customer,Sum,Operator,Reason\n900,lock,1,,ops-1,fraud check\n",
+        )
+        .unwrap();
+
+        let mut config = config;
+        config.input.profile = Some("acme".to_string());
+        let result = run_flow(
+            &[input.to_str().unwrap()],
+            config,
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                admin: Some(admin.to_str().unwrap().to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(result.contains("1,6.0,0.0,6.0,true,0.0,locked"));
+    }
+
+    #[test]
+    pub fn test_flow_with_admin_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let admin = dir.path().join("admin.csv");
+        std::fs::write(
+            &input,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n\
+             dispute,1,2,\nchargeback,1,2,\ndeposit,2,3,1.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &admin,
+            "transaction_type,client,tx,amount,operator,reason\n\
+             unlock,1,100,,ops-1,chargeback reviewed\nclose,2,101,,ops-1,client request\n",
+        )
+        .unwrap();
+
+        let result = run_flow(
+            &[input.to_str().unwrap()],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                admin: Some(admin.to_str().unwrap().to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let mut rdr = csv::Reader::from_reader(result.as_bytes());
+        let states: HashMap<u16, ClientState> = rdr
+            .deserialize()
+            .map(|r: Result<ClientState, _>| r.unwrap())
+            .map(|d| (d.client, d))
+            .collect();
+        assert_eq!(states[&1].status, AccountStatus::Active);
+        assert_eq!(states[&2].status, AccountStatus::Closed);
+        assert!(result.contains("1,10.0,0.0,10.0,false,0.0,active"));
+
+        // Admin file accepts only admin actions
+        std::fs::write(
+            &admin,
+            "transaction_type,client,tx,amount,operator,reason\ndeposit,1,4,1.0,ops-1,manual\n",
+        )
+        .unwrap();
+        let result = run_flow(
+            &[input.to_str().unwrap()],
+            EngineConfig::default(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            FlowOptions {
+                admin: Some(admin.to_str().unwrap().to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("record 1 is not an admin action"));
+    }
+
+    #[test]
+    pub fn test_flow_with_reviews_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let admin = dir.path().join("admin.csv");
+        let reviews = dir.path().join("reviews.csv");
+        std::fs::write(
+            &input,
+            "transaction_type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,500.0\n",
+        )
+        .unwrap();
+        let config = || {
+            EngineConfig::from_toml(
+                "[[rules]]\nrule = \"large_amount\"\nmax_percent = 50\nmin_amount = 100\nverdict = \"hold\"\n",
+            )
+            .unwrap()
+        };
+        let options = |admin: Option<&std::path::Path>| FlowOptions {
+            admin: admin.map(|d| d.to_str().unwrap().to_string()),
+            reviews: Some(reviews.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+
+        // Held deposit is kept in the reviews file
+        let result = run_flow(
+            &[input.to_str().unwrap()],
+            config(),
+            TransactionMgr::new(),
+            ClientsStatesMgr::new(),
+            options(None),
+        )
+        .unwrap();
+        assert!(result.contains("1,10.0,0.0,510.0,false,0.0,active,500.0"));
+        let open = load_reviews(&reviews).unwrap();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].tx, 2);
+        assert!(open[0].held);
+        assert_eq!(
+            format_reviews(&open),
+            "open reviews: 1\n  tx 2: client 1 deposit 500 held row 2 rules large_amount\n"
+        );
+
+        // Review is loaded by the next run and approved by the admin file
+        std::fs::write(
+            &admin,
+            "transaction_type,client,tx,amount,operator,reason\napprove,1,2,,ops-1,source verified\n",
+        )
+        .unwrap();
+        // Managers are restored as they were after the first run
+        let mut transaction_mgr = TransactionMgr::new();
+        for (tx, amount) in [(1, 10.), (2, 500.)] {
+            let details = TransactionDetails {
+                transaction_type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(amount),
+                ..Default::default()
+            };
+            transaction_mgr
+                .insert_new_transaction(details, tx as u64)
+                .unwrap();
+        }
+        let mut client_state_mgr = ClientsStatesMgr::new();
+        client_state_mgr
+            .restore_state(
+                1,
+                Some(ClientState {
+                    client: 1,
+                    available: 10.,
+                    total: 510.,
+                    pending: 500.,
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        let result = run_flow::<&str>(
+            &[],
+            config(),
+            transaction_mgr,
+            client_state_mgr,
+            options(Some(&admin)),
+        )
+        .unwrap();
+        assert!(result.contains("1,510.0,0.0,510.0,false,0.0,active,0.0"));
+        assert!(load_reviews(&reviews).unwrap().is_empty());
+        assert_eq!(format_reviews(&[]), "open reviews: 0\n");
+    }
+
+    #[test]
+    pub fn test_accrual() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("states.csv");
+        let audit_path = dir.path().join("audit.jsonl");
+        std::fs::write(
+            &state_path,
+            "client,available,held,total,locked\n1,100.0,0.0,100.0,false\n2,100.0,0.0,100.0,true\n",
+        )
+        .unwrap();
+        let config = EngineConfig::from_toml("[[interest.rates]]\npercent = 1.5").unwrap();
+        let store =
+            DiskTransactionStore::open(dir.path().join("tx_history").to_str().unwrap()).unwrap();
+        let accrue = || {
+            let (transaction_mgr, client_state_mgr) = persisted_mgrs(&store, &state_path);
+            run_accrual(
+                config.clone(),
+                transaction_mgr,
+                client_state_mgr,
+                Some(AuditLog::open(&audit_path).unwrap()),
+            )
+            .unwrap()
+        };
+
+        let result = accrue();
+        assert!(result.contains("1,101.5,0.0,101.5,false"));
+        assert!(
+            result.contains("2,100.0,0.0,100.0,true"),
+            "Locked account is skipped"
+        );
+
+        // States are written back
+        let states = std::fs::read_to_string(&state_path).unwrap();
+        assert!(states.contains("1,101.5,0.0,101.5,false"));
+
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert_eq!(audit.lines().count(), 1);
+        assert!(audit.contains(r#""transaction_type":"interest","client":1,"tx":4000000000"#));
+
+        // The next run continues the reserved range of the kept history
+        accrue();
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert_eq!(audit.lines().count(), 2);
+        assert!(audit.contains(r#""transaction_type":"interest","client":1,"tx":4000000001"#));
+        let transaction_mgr = TransactionMgr::with_store(Box::new(store.clone()));
+        assert!(transaction_mgr
+            .get_transaction(4000000001, 1)
+            .unwrap()
+            .is_some());
+    }
 }
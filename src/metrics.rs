@@ -0,0 +1,321 @@
+use crate::rejection::Rejection;
+use crate::TransactionType;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the row processing time buckets - in microseconds
+const DURATION_BUCKETS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// Histogram of the row processing time
+#[derive(Serialize, Clone, Debug)]
+pub struct Histogram {
+    /// Upper bound (microseconds) to number of rows processed within it - not cumulative
+    pub buckets: BTreeMap<u64, u64>,
+    /// Number of rows processed longer than the last bucket
+    pub overflow: u64,
+    /// Number of observed rows
+    pub count: u64,
+    /// Total processing time in microseconds
+    pub sum: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: DURATION_BUCKETS.iter().map(|d| (*d, 0)).collect(),
+            overflow: 0,
+            count: 0,
+            sum: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// Keep track on the observed duration
+    pub fn observe(&mut self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        self.count += 1;
+        self.sum += micros;
+
+        match self.buckets.range_mut(micros..).next() {
+            Some((_, count)) => *count += 1,
+            None => self.overflow += 1,
+        }
+    }
+}
+
+/// Metrics of the processing run
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct Metrics {
+    /// Number of read rows
+    pub rows_read: u64,
+    /// Number of applied actions per transaction type
+    pub applied: BTreeMap<String, u64>,
+    /// Number of rejected actions per reason
+    pub rejections: BTreeMap<String, u64>,
+    /// Number of clients locked during the run
+    pub clients_locked: u64,
+    /// Time from the first to the last processed row in seconds
+    pub elapsed_seconds: f64,
+    /// Processed rows per second
+    pub rows_per_second: f64,
+    /// Row processing time
+    pub row_duration_micros: Histogram,
+    #[serde(skip)]
+    started: Option<Instant>,
+    #[serde(skip)]
+    locked_clients: HashSet<u16>,
+}
+
+/// Shared metrics registry - updated by the processor, read by exporters
+#[derive(Clone, Default, Debug)]
+pub struct MetricsRegistry {
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl MetricsRegistry {
+    /// Keep track on the processed row
+    pub fn record_row(
+        &self,
+        transaction_type: TransactionType,
+        result: &Result<(), Rejection>,
+        duration: Duration,
+    ) {
+        let mut metrics = self.lock();
+        let now = Instant::now();
+        let started = *metrics.started.get_or_insert(now - duration);
+
+        metrics.rows_read += 1;
+        match result {
+            Ok(()) => {
+                let transaction_type: &str = transaction_type.into();
+                *metrics
+                    .applied
+                    .entry(transaction_type.to_string())
+                    .or_default() += 1;
+            }
+            Err(reason) => {
+                *metrics
+                    .rejections
+                    .entry(reason.as_str().to_string())
+                    .or_default() += 1;
+            }
+        }
+        metrics.row_duration_micros.observe(duration);
+
+        metrics.elapsed_seconds = now.duration_since(started).as_secs_f64();
+        if metrics.elapsed_seconds > 0. {
+            metrics.rows_per_second = metrics.rows_read as f64 / metrics.elapsed_seconds;
+        }
+    }
+
+    /// Keep track on the locked client account
+    pub fn record_locked(&self, client_id: u16) {
+        let mut metrics = self.lock();
+        metrics.locked_clients.insert(client_id);
+        metrics.clients_locked = metrics.locked_clients.len() as u64;
+    }
+
+    /// Account is unlocked or closed - it's not counted as locked anymore
+    pub fn record_unlocked(&self, client_id: u16) {
+        let mut metrics = self.lock();
+        metrics.locked_clients.remove(&client_id);
+        metrics.clients_locked = metrics.locked_clients.len() as u64;
+    }
+
+    /// Copy of the current metrics
+    pub fn snapshot(&self) -> Metrics {
+        self.lock().clone()
+    }
+
+    /// Metrics in JSON format
+    pub fn to_json(&self) -> Result<String, anyhow::Error> {
+        Ok(serde_json::to_string_pretty(&self.snapshot())?)
+    }
+
+    /// Metrics in Prometheus text format
+    pub fn to_prometheus(&self) -> String {
+        let metrics = self.snapshot();
+        let mut text = String::new();
+
+        // Writing to String doesn't fail
+        let _ = writeln!(text, "# TYPE transactions_rows_read_total counter");
+        let _ = writeln!(text, "transactions_rows_read_total {}", metrics.rows_read);
+
+        let _ = writeln!(text, "# TYPE transactions_applied_total counter");
+        for (transaction_type, count) in &metrics.applied {
+            let _ = writeln!(
+                text,
+                "transactions_applied_total{{type=\"{}\"}} {}",
+                transaction_type, count
+            );
+        }
+
+        let _ = writeln!(text, "# TYPE transactions_rejected_total counter");
+        for (reason, count) in &metrics.rejections {
+            let _ = writeln!(
+                text,
+                "transactions_rejected_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        let _ = writeln!(text, "# TYPE transactions_clients_locked gauge");
+        let _ = writeln!(
+            text,
+            "transactions_clients_locked {}",
+            metrics.clients_locked
+        );
+        let _ = writeln!(text, "# TYPE transactions_rows_per_second gauge");
+        let _ = writeln!(
+            text,
+            "transactions_rows_per_second {}",
+            metrics.rows_per_second
+        );
+
+        let histogram = &metrics.row_duration_micros;
+        let _ = writeln!(text, "# TYPE transactions_row_duration_micros histogram");
+        let mut cumulative = 0;
+        for (bound, count) in &histogram.buckets {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "transactions_row_duration_micros_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            text,
+            "transactions_row_duration_micros_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(
+            text,
+            "transactions_row_duration_micros_sum {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            text,
+            "transactions_row_duration_micros_count {}",
+            histogram.count
+        );
+
+        text
+    }
+
+    /// Serve metrics in Prometheus text format on the listener - any request path is accepted
+    /// Blocks while the listener is open, so it's expected to be run on separate thread
+    pub fn serve(&self, listener: TcpListener) -> Result<(), anyhow::Error> {
+        for stream in listener.incoming() {
+            // Failed connection (e.g. out of file descriptors) doesn't stop serving
+            let mut stream = match stream {
+                Ok(d) => d,
+                Err(e) => {
+                    log::warn!("Metrics connection failed: {}", e);
+                    continue;
+                }
+            };
+
+            // Only the request line is needed - the rest is skipped
+            // Client may disconnect - it doesn't stop serving
+            let mut request = String::new();
+            if BufReader::new(&stream).read_line(&mut request).is_err() {
+                continue;
+            }
+
+            let body = self.to_prometheus();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Metrics> {
+        // Metrics are still usable even if other thread panicked
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metrics::MetricsRegistry;
+    use crate::rejection::Rejection;
+    use crate::TransactionType;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    fn registry() -> MetricsRegistry {
+        let registry = MetricsRegistry::default();
+        let micros = Duration::from_micros;
+        registry.record_row(TransactionType::Deposit, &Ok(()), micros(3));
+        registry.record_row(TransactionType::Deposit, &Ok(()), micros(70));
+        registry.record_row(
+            TransactionType::Withdrawal,
+            &Err(Rejection::InsufficientFunds),
+            micros(10_000),
+        );
+        registry.record_locked(2);
+        registry.record_locked(2);
+        registry
+    }
+
+    #[test]
+    pub fn test_metrics() {
+        let metrics = registry().snapshot();
+        assert_eq!(metrics.rows_read, 3);
+        assert_eq!(metrics.applied.get("deposit"), Some(&2));
+        assert_eq!(metrics.rejections.get("insufficient_funds"), Some(&1));
+        assert_eq!(metrics.clients_locked, 1);
+
+        let histogram = &metrics.row_duration_micros;
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.sum, 10_073);
+        assert_eq!(histogram.buckets.get(&5), Some(&1));
+        assert_eq!(histogram.buckets.get(&100), Some(&1));
+        assert_eq!(histogram.overflow, 1);
+
+        let json: serde_json::Value = serde_json::from_str(&registry().to_json().unwrap()).unwrap();
+        assert_eq!(json["rows_read"], 3);
+        assert_eq!(json["applied"]["deposit"], 2);
+        assert_eq!(json["row_duration_micros"]["buckets"]["5"], 1);
+    }
+
+    #[test]
+    pub fn test_prometheus() {
+        let text = registry().to_prometheus();
+        assert!(text.contains("transactions_rows_read_total 3\n"));
+        assert!(text.contains("transactions_applied_total{type=\"deposit\"} 2\n"));
+        assert!(text.contains("transactions_rejected_total{reason=\"insufficient_funds\"} 1\n"));
+        assert!(text.contains("transactions_clients_locked 1\n"));
+        assert!(text.contains("transactions_row_duration_micros_bucket{le=\"5\"} 1\n"));
+        assert!(text.contains("transactions_row_duration_micros_bucket{le=\"5000\"} 2\n"));
+        assert!(text.contains("transactions_row_duration_micros_bucket{le=\"+Inf\"} 3\n"));
+    }
+
+    #[test]
+    pub fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = registry();
+        std::thread::spawn(move || metrics.serve(listener));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("transactions_rows_read_total 3\n"));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::csv_processor::TransactionLoader;
+use crate::transaction_details::TransactionDetails;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Processing mode of transaction actions based on their timestamps
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderingMode {
+    /// Actions are applied in file order, timestamps are not checked
+    #[default]
+    FileOrder,
+    /// Actions with timestamp earlier than already processed one are rejected
+    Reject,
+    /// Actions are sorted within the reorder window, the ones still out of order are rejected
+    Reorder,
+}
+
+/// Ordering configuration
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OrderingConfig {
+    /// Ordering mode
+    pub mode: OrderingMode,
+    /// Number of rows which can be kept back to sort - only for reorder mode
+    pub reorder_window: usize,
+}
+
+/// Buffered transaction action - ordered by timestamp and then by file order
+struct BufferedTransaction {
+    timestamp: i64,
+    seq: u64,
+    details: TransactionDetails,
+}
+
+impl PartialEq for BufferedTransaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for BufferedTransaction {}
+
+impl PartialOrd for BufferedTransaction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BufferedTransaction {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
+/// Loader which sorts transaction actions of the underlying loader within bounded window
+/// Actions without timestamp keep the timestamp of the previous action - i.e. stay in place
+pub struct ReorderingLoader<L: TransactionLoader> {
+    /// Underlying loader
+    loader: L,
+    /// Number of actions which can be kept back
+    window: usize,
+    /// Actions sorted by timestamp
+    buffer: BinaryHeap<Reverse<BufferedTransaction>>,
+    /// Number of loaded actions
+    seq: u64,
+    /// Timestamp of the last loaded action
+    last_timestamp: i64,
+}
+
+impl<L: TransactionLoader> ReorderingLoader<L> {
+    /// Create reordering loader - window 0 keeps the file order
+    pub fn new(loader: L, window: usize) -> Self {
+        Self {
+            loader,
+            window,
+            buffer: BinaryHeap::new(),
+            seq: 0,
+            last_timestamp: i64::MIN,
+        }
+    }
+}
+
+impl<L: TransactionLoader> TransactionLoader for ReorderingLoader<L> {
+    /// Get the earliest transaction details within the window
+    fn next_transaction(&mut self) -> Option<TransactionDetails> {
+        while self.buffer.len() <= self.window {
+            let details = match self.loader.next_transaction() {
+                Some(d) => d,
+                None => break,
+            };
+
+            self.last_timestamp = details.timestamp.unwrap_or(self.last_timestamp);
+            self.seq += 1;
+            self.buffer.push(Reverse(BufferedTransaction {
+                timestamp: self.last_timestamp,
+                seq: self.seq,
+                details,
+            }));
+        }
+
+        self.buffer.pop().map(|d| d.0.details)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::csv_processor::{TransactionIOLoader, TransactionLoader};
+    use crate::ordering::ReorderingLoader;
+    use crate::{TransactionDetails, TransactionType};
+
+    fn loader(timestamps: &[Option<i64>]) -> TransactionIOLoader {
+        let data: Vec<TransactionDetails> = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, t)| TransactionDetails {
+                transaction_type: TransactionType::Deposit,
+                client: 1,
+                tx: i as u32 + 1,
+                amount: Some(1.),
+                timestamp: *t,
+                ..Default::default()
+            })
+            .collect();
+        TransactionIOLoader::from(data)
+    }
+
+    fn collect_tx<L: TransactionLoader>(mut loader: L) -> Vec<u32> {
+        let mut result = Vec::new();
+        while let Some(d) = loader.next_transaction() {
+            result.push(d.tx);
+        }
+        result
+    }
+
+    #[test]
+    pub fn test_reordering_loader() {
+        let timestamps = [Some(10), Some(30), Some(20), None, Some(5), Some(40)];
+
+        let result = collect_tx(ReorderingLoader::new(loader(&timestamps), 0));
+        assert_eq!(result, vec![1, 2, 3, 4, 5, 6], "Window 0 keeps file order");
+
+        let result = collect_tx(ReorderingLoader::new(loader(&timestamps), 1));
+        assert_eq!(result, vec![1, 3, 4, 5, 2, 6]);
+
+        let result = collect_tx(ReorderingLoader::new(loader(&timestamps), 10));
+        assert_eq!(
+            result,
+            vec![5, 1, 3, 4, 2, 6],
+            "Action without timestamp stays after the previous one"
+        );
+    }
+}
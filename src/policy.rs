@@ -0,0 +1,248 @@
+use crate::columns::{check_aliases, ColumnAliases, ColumnMapping};
+use crate::TransactionType;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Actions on the locked account - i.e. after chargeback
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LockPolicy {
+    /// All actions are applied - the account is only marked as locked
+    #[default]
+    Allow,
+    /// Deposits, withdrawals and transfers are rejected with account_locked
+    RejectFunds,
+    /// Any action except admin actions and reviews is rejected with account_locked
+    RejectAll,
+}
+
+/// Accounts configuration
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Actions on the locked account
+    pub locked: LockPolicy,
+}
+
+impl AccountsConfig {
+    /// Whether the action of the type is rejected on the locked account
+    pub fn rejects_locked(&self, transaction_type: TransactionType) -> bool {
+        let funds = matches!(
+            transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        );
+        let disputes = matches!(
+            transaction_type,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Reversal
+        );
+        match self.locked {
+            LockPolicy::Allow => false,
+            LockPolicy::RejectFunds => funds,
+            LockPolicy::RejectAll => funds || disputes,
+        }
+    }
+}
+
+/// Rounding of the amounts to the configured decimals
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Half is rounded away from zero, i.e. 0.125 -> 0.13
+    #[default]
+    HalfUp,
+    /// Half is rounded to the even digit, i.e. 0.125 -> 0.12
+    HalfEven,
+    /// Extra decimals are dropped
+    Down,
+}
+
+/// Amounts configuration
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountsConfig {
+    /// Number of decimals kept - amounts and fees are rounded. Not rounded if not provided
+    pub decimals: Option<u32>,
+    /// Rounding mode
+    pub rounding: RoundingMode,
+    /// Whether zero amount deposits, withdrawals and transfers are accepted - negative ones never are
+    pub allow_zero: bool,
+}
+
+impl AmountsConfig {
+    /// Round the amount to the configured decimals
+    pub fn round(&self, amount: f32) -> f32 {
+        let decimals = match self.decimals {
+            Some(d) => d,
+            None => return amount,
+        };
+
+        // Shortest decimal form of the amount is rounded - i.e. 0.015, not 0.01499.. of f32
+        let exact = amount.to_string().parse().unwrap_or(amount as f64);
+        let scale = 10_f64.powi(decimals as i32);
+        let scaled = exact * scale;
+        let rounded = match self.rounding {
+            RoundingMode::HalfUp => scaled.round(),
+            RoundingMode::HalfEven => scaled.round_ties_even(),
+            RoundingMode::Down => scaled.trunc(),
+        };
+        (rounded / scale) as f32
+    }
+
+    /// Whether the amount of the funds movement is accepted
+    pub fn is_valid(&self, amount: f32) -> bool {
+        amount > 0. || (self.allow_zero && amount == 0.)
+    }
+}
+
+/// Handling of input rows which can't be parsed
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseErrorPolicy {
+    /// Processing stops on the first row which can't be parsed
+    #[default]
+    Fail,
+    /// Row is skipped and logged
+    Skip,
+}
+
+/// Input files configuration
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    /// Handling of rows which can't be parsed
+    pub parse_errors: ParseErrorPolicy,
+    /// Column mapping profile of the input files - i.e. of the partner, overridden by --profile
+    pub profile: Option<String>,
+    /// Column aliases of all input files - column name to its aliases
+    pub aliases: ColumnAliases,
+    /// Column aliases per profile - added to the common ones
+    pub profiles: BTreeMap<String, ColumnAliases>,
+}
+
+impl InputConfig {
+    /// Check that aliases are of known columns and the selected profile exists
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        check_aliases(&self.aliases)?;
+        for (name, aliases) in &self.profiles {
+            check_aliases(aliases).map_err(|e| anyhow!("Profile {}: {}", name, e))?;
+        }
+        self.profile_aliases().map(|_| ())
+    }
+
+    /// Column mapping of the input files - common aliases and the ones of the selected profile
+    pub fn mapping(&self) -> Result<ColumnMapping, anyhow::Error> {
+        Ok(match self.profile_aliases()? {
+            Some(profile) => ColumnMapping::new(&[&self.aliases, profile]),
+            None => ColumnMapping::new(&[&self.aliases]),
+        })
+    }
+
+    /// Aliases of the selected profile - none if it isn't selected
+    fn profile_aliases(&self) -> Result<Option<&ColumnAliases>, anyhow::Error> {
+        match self.profile.as_deref() {
+            Some(name) => match self.profiles.get(name) {
+                Some(d) => Ok(Some(d)),
+                None => Err(anyhow!("Unknown input profile {}", name)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+    use crate::policy::{LockPolicy, ParseErrorPolicy};
+    use crate::TransactionType;
+    use float_cmp::approx_eq;
+
+    #[test]
+    pub fn test_policy() {
+        let config = EngineConfig::from_toml("").unwrap();
+        assert_eq!(config.accounts.locked, LockPolicy::Allow);
+        assert!(!config.accounts.rejects_locked(TransactionType::Deposit));
+        assert!(approx_eq!(
+            f32,
+            config.amounts.round(0.12345),
+            0.12345,
+            ulps = 4
+        ));
+        assert!(!config.amounts.is_valid(0.));
+        assert_eq!(config.input.parse_errors, ParseErrorPolicy::Fail);
+
+        let config = EngineConfig::from_toml(
+            r#"
+            [accounts]
+            locked = "reject_funds"
+
+            [amounts]
+            decimals = 2
+            rounding = "half_even"
+            allow_zero = true
+
+            [input]
+            parse_errors = "skip"
+            "#,
+        )
+        .unwrap();
+        assert!(config.accounts.rejects_locked(TransactionType::Transfer));
+        assert!(!config.accounts.rejects_locked(TransactionType::Dispute));
+        assert!(approx_eq!(f32, config.amounts.round(0.125), 0.12, ulps = 4));
+        assert!(approx_eq!(f32, config.amounts.round(0.135), 0.14, ulps = 4));
+        assert!(approx_eq!(f32, config.amounts.round(0.015), 0.02, ulps = 4));
+        assert!(config.amounts.is_valid(0.));
+        assert!(!config.amounts.is_valid(-1.));
+        assert_eq!(config.input.parse_errors, ParseErrorPolicy::Skip);
+
+        let config = EngineConfig::from_toml(
+            "[accounts]\nlocked = \"reject_all\"\n[amounts]\ndecimals = 2\nrounding = \"down\"",
+        )
+        .unwrap();
+        assert!(config.accounts.rejects_locked(TransactionType::Dispute));
+        assert!(!config.accounts.rejects_locked(TransactionType::Unlock));
+        assert!(approx_eq!(f32, config.amounts.round(0.129), 0.12, ulps = 4));
+
+        assert!(EngineConfig::from_toml("[amounts]\nprecision = 2").is_err());
+    }
+
+    #[test]
+    pub fn test_input_profiles() {
+        let content = r#"
+            [input]
+            profile = "acme"
+
+            [input.aliases]
+            amount = ["value"]
+
+            [input.profiles.acme]
+            client = ["customer"]
+            amount = ["sum"]
+
+            [input.profiles.globex]
+            tx = ["ref"]
+            "#;
+        let mut config = EngineConfig::from_toml(content).unwrap();
+        let mapping = config.input.mapping().unwrap();
+        assert_eq!(mapping.column_of("Customer"), "client");
+        assert_eq!(mapping.column_of("value"), "amount");
+        assert_eq!(mapping.column_of("sum"), "amount");
+        assert_eq!(mapping.column_of("ref"), "ref");
+
+        config.input.profile = Some("globex".to_string());
+        let mapping = config.input.mapping().unwrap();
+        assert_eq!(mapping.column_of("ref"), "tx");
+        assert_eq!(mapping.column_of("customer"), "customer");
+
+        config.input.profile = Some("initech".to_string());
+        assert!(config.input.validate().is_err());
+        assert!(config.input.mapping().is_err());
+
+        assert!(EngineConfig::from_toml("[input]\nprofile = \"acme\"").is_err());
+        assert!(EngineConfig::from_toml("[input.aliases]\nkind = [\"type\"]").is_err());
+        assert!(EngineConfig::from_toml("[input.profiles.acme]\nkind = [\"type\"]").is_err());
+    }
+}
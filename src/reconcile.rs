@@ -0,0 +1,181 @@
+use crate::diff::{BalanceDelta, StatesDiff};
+use crate::history::{ClientHistory, HistoryEntry};
+use crate::ClientState;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Mismatch of the client state with the expected one
+#[derive(Default, Debug)]
+pub struct ClientMismatch {
+    pub client: u16,
+    /// Client is expected, but not present after processing
+    pub missing: bool,
+    /// Client is present after processing, but not expected
+    pub unexpected: bool,
+    /// Balance differences - old is the expected value, new is the actual one
+    pub deltas: Vec<BalanceDelta>,
+    /// Actual lock status - if it differs from the expected one
+    pub locked: Option<bool>,
+    /// Processed actions of the client which contributed to the difference
+    pub history: Vec<HistoryEntry>,
+}
+
+impl ClientMismatch {
+    /// Whether the mismatch is more than the threshold
+    /// Missing/unexpected clients and lock differences always exceed it
+    pub fn exceeds(&self, threshold: f32) -> bool {
+        self.missing
+            || self.unexpected
+            || self.locked.is_some()
+            || self.deltas.iter().any(|d| d.delta().abs() > threshold)
+    }
+}
+
+/// Result of the reconciliation against expected clients states
+#[derive(Default, Debug)]
+pub struct ReconcileReport {
+    /// Mismatched clients sorted by client id
+    pub mismatches: Vec<ClientMismatch>,
+}
+
+impl ReconcileReport {
+    /// Compare actual clients states with the expected ones
+    /// Differences not more than the tolerance are ignored
+    pub fn new(
+        expected: &[ClientState],
+        actual: &[ClientState],
+        history: &ClientHistory,
+        tolerance: f32,
+    ) -> Self {
+        let diff = StatesDiff::new(expected, actual, tolerance);
+
+        let mut mismatches: BTreeMap<u16, ClientMismatch> = BTreeMap::new();
+        for client in diff.removed {
+            mismatch_of(&mut mismatches, history, client).missing = true;
+        }
+        for client in diff.added {
+            mismatch_of(&mut mismatches, history, client).unexpected = true;
+        }
+        for d in diff.deltas {
+            mismatch_of(&mut mismatches, history, d.client)
+                .deltas
+                .push(d);
+        }
+        for d in diff.lock_changes {
+            mismatch_of(&mut mismatches, history, d.client).locked = Some(d.locked);
+        }
+
+        Self {
+            mismatches: mismatches.into_values().collect(),
+        }
+    }
+
+    /// Whether any mismatch is more than the threshold
+    pub fn exceeds(&self, threshold: f32) -> bool {
+        self.mismatches.iter().any(|d| d.exceeds(threshold))
+    }
+}
+
+/// Mismatch of the client - created with the client history if not present
+fn mismatch_of<'a>(
+    mismatches: &'a mut BTreeMap<u16, ClientMismatch>,
+    history: &ClientHistory,
+    client: u16,
+) -> &'a mut ClientMismatch {
+    mismatches.entry(client).or_insert_with(|| ClientMismatch {
+        client,
+        history: history.of(client).to_vec(),
+        ..Default::default()
+    })
+}
+
+impl fmt::Display for ReconcileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "mismatched clients: {}", self.mismatches.len())?;
+
+        for d in &self.mismatches {
+            if d.missing {
+                writeln!(f, "client {}: expected, but not present", d.client)?;
+            }
+            if d.unexpected {
+                writeln!(f, "client {}: present, but not expected", d.client)?;
+            }
+            for delta in &d.deltas {
+                writeln!(
+                    f,
+                    "client {}: {} expected {} actual {} ({:+})",
+                    d.client,
+                    delta.field,
+                    delta.old,
+                    delta.new,
+                    delta.delta()
+                )?;
+            }
+            if let Some(locked) = d.locked {
+                writeln!(
+                    f,
+                    "client {}: locked expected {} actual {}",
+                    d.client, !locked, locked
+                )?;
+            }
+            for entry in &d.history {
+                writeln!(f, "    {}", entry)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::history::ClientHistory;
+    use crate::reconcile::ReconcileReport;
+    use crate::rejection::Rejection;
+    use crate::{ClientState, TransactionDetails, TransactionType};
+
+    fn state(client: u16, total: f32) -> ClientState {
+        ClientState {
+            client,
+            available: total,
+            total,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_reconcile_report() {
+        let mut history = ClientHistory::default();
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 3,
+            amount: Some(2.),
+            ..Default::default()
+        };
+        history.record(&tx, 2, &Err(Rejection::InsufficientFunds));
+
+        let expected = [state(1, 8.), state(2, 5.), state(3, 1.)];
+        let actual = [state(1, 10.), state(2, 5.00001), state(4, 1.)];
+        let report = ReconcileReport::new(&expected, &actual, &history, 0.0001);
+
+        let clients: Vec<u16> = report.mismatches.iter().map(|d| d.client).collect();
+        assert_eq!(clients, vec![1, 3, 4]);
+        assert_eq!(report.mismatches[0].deltas.len(), 2);
+        assert_eq!(report.mismatches[0].history.len(), 1);
+        assert!(report.mismatches[1].missing);
+        assert!(report.mismatches[2].unexpected);
+
+        assert!(report.exceeds(5.), "Missing client always exceeds");
+        assert!(report.mismatches[0].exceeds(1.));
+        assert!(!report.mismatches[0].exceeds(2.));
+
+        let text = report.to_string();
+        assert!(text.contains("client 1: total expected 8 actual 10 (+2)"));
+        assert!(text.contains("    row 2 tx 3 withdrawal 2 rejected: insufficient_funds"));
+
+        let report = ReconcileReport::new(&expected, &expected, &history, 0.);
+        assert!(report.mismatches.is_empty());
+        assert!(!report.exceeds(0.));
+    }
+}
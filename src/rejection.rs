@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// Reason of transaction action rejection
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum Rejection {
+    /// Transaction type is not supported
+    UnknownType,
+    /// Amount is missing, non-positive or provided for the action not expecting it
+    InvalidAmount,
+    /// Transaction with the same id is already processed
+    DuplicateTx,
+    /// Referenced transaction is not found for the client
+    TxNotFound,
+    /// Client account doesn't exist
+    UnknownClient,
+    /// Client's available/held funds are not enough for the action
+    InsufficientFunds,
+    /// Fee is more than the deposited amount
+    FeeExceedsAmount,
+    /// Amount is more than the maximum single withdrawal limit
+    MaxWithdrawalExceeded,
+    /// Cumulative withdrawal within the window is more than the limit
+    WindowWithdrawalExceeded,
+    /// Balance after the action is more than the maximum balance limit
+    MaxBalanceExceeded,
+    /// Timestamp is earlier than the one of already processed action
+    OutOfOrder,
+    /// Timestamp is after the requested point in time
+    AfterAsOf,
+    /// Transaction is older than the dispute window
+    DisputeExpired,
+    /// Transaction already has open dispute
+    AlreadyDisputed,
+    /// Disputed amount is more than the remaining undisputed amount of the transaction
+    ExceedsUndisputed,
+    /// Transaction is already charged back
+    AlreadyChargedBack,
+    /// Transaction is already reversed
+    AlreadyReversed,
+    /// Transaction has no open dispute to resolve or charge back
+    NotDisputed,
+    /// Denied by the fraud rule
+    RuleDenied,
+    /// Funds of the transaction are held until its review
+    UnderReview,
+    /// Transaction has no open review to approve or decline
+    NotUnderReview,
+    /// Transaction id is in the range reserved for generated accruals
+    ReservedTx,
+    /// Destination of the transfer is missing or the same as the source client
+    InvalidDestination,
+    /// Admin action has no operator id or reason
+    MissingOperatorOrReason,
+    /// Account is closed - only admin actions and disputes of earlier transactions are accepted
+    AccountClosed,
+    /// Account is locked and the lock policy rejects the action
+    AccountLocked,
+    /// Account already has the status requested by admin action
+    StatusUnchanged,
+    /// Transaction is already seen in the previously ingested batch
+    DuplicateAcrossBatch,
+    /// Action of the atomic batch is rolled back - other action of the batch is rejected
+    BatchRolledBack,
+    /// Transaction store failed - i.e. disk error
+    StoreFailure,
+}
+
+impl Rejection {
+    /// Short name of the rejection reason - used in reports
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::UnknownType => "unknown_type",
+            Rejection::InvalidAmount => "invalid_amount",
+            Rejection::DuplicateTx => "duplicate_tx",
+            Rejection::TxNotFound => "tx_not_found",
+            Rejection::UnknownClient => "unknown_client",
+            Rejection::InsufficientFunds => "insufficient_funds",
+            Rejection::FeeExceedsAmount => "fee_exceeds_amount",
+            Rejection::MaxWithdrawalExceeded => "max_withdrawal_exceeded",
+            Rejection::WindowWithdrawalExceeded => "window_withdrawal_exceeded",
+            Rejection::MaxBalanceExceeded => "max_balance_exceeded",
+            Rejection::OutOfOrder => "out_of_order",
+            Rejection::AfterAsOf => "after_as_of",
+            Rejection::DisputeExpired => "dispute_expired",
+            Rejection::AlreadyDisputed => "already_disputed",
+            Rejection::ExceedsUndisputed => "exceeds_undisputed",
+            Rejection::AlreadyChargedBack => "already_charged_back",
+            Rejection::AlreadyReversed => "already_reversed",
+            Rejection::NotDisputed => "not_disputed",
+            Rejection::RuleDenied => "rule_denied",
+            Rejection::UnderReview => "under_review",
+            Rejection::NotUnderReview => "not_under_review",
+            Rejection::ReservedTx => "reserved_tx",
+            Rejection::InvalidDestination => "invalid_destination",
+            Rejection::MissingOperatorOrReason => "missing_operator_or_reason",
+            Rejection::AccountClosed => "account_closed",
+            Rejection::AccountLocked => "account_locked",
+            Rejection::StatusUnchanged => "status_unchanged",
+            Rejection::DuplicateAcrossBatch => "duplicate_across_batch",
+            Rejection::BatchRolledBack => "batch_rolled_back",
+            Rejection::StoreFailure => "store_failure",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::csv_processor::TransactionLoader;
+use crate::rejection::Rejection;
+use crate::{TransactionDetails, TransactionsProcessor};
+use std::io::{BufRead, Write};
+
+/// Positions to stop the replay at
+#[derive(Default, Clone, Debug)]
+pub struct Breakpoints {
+    /// Actions referencing the transactions
+    pub tx: Vec<u32>,
+    /// Processing rows
+    pub row: Vec<u64>,
+    /// Actions of the clients
+    pub client: Vec<u16>,
+}
+
+impl Breakpoints {
+    /// Whether there are no breakpoints
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty() && self.row.is_empty() && self.client.is_empty()
+    }
+
+    /// Whether the action processed on the row should stop the replay
+    pub fn matches(&self, action_details: &TransactionDetails, row: u64) -> bool {
+        self.tx.contains(&action_details.tx)
+            || self.row.contains(&row)
+            || self.client.contains(&action_details.client)
+    }
+}
+
+/// Re-run the actions and print the client state and the transaction record at each stop
+/// In step mode the next command is read from the input after each stop:
+/// s (or empty) - stop at the next row, c - continue to the next breakpoint, q - quit
+/// Without breakpoints step mode stops at the first row
+pub fn replay<L, R, W>(
+    processor: &mut TransactionsProcessor<L>,
+    breakpoints: &Breakpoints,
+    step: bool,
+    mut input: R,
+    output: &mut W,
+) -> Result<(), anyhow::Error>
+where
+    L: TransactionLoader,
+    R: BufRead,
+    W: Write,
+{
+    let mut stepping = step && breakpoints.is_empty();
+
+    while let Some((action_details, result)) = processor.step() {
+        if result == Err(Rejection::StoreFailure) {
+            return Err(processor.take_store_error());
+        }
+
+        let row = processor.row();
+        if !stepping && !breakpoints.matches(&action_details, row) {
+            continue;
+        }
+
+        print_stop(processor, &action_details, &result, output)?;
+        if !step {
+            continue;
+        }
+
+        loop {
+            write!(output, "(s)tep, (c)ontinue, (q)uit> ")?;
+            output.flush()?;
+
+            let mut command = String::new();
+            if input.read_line(&mut command)? == 0 {
+                return Ok(());
+            }
+
+            match command.trim() {
+                "" | "s" => stepping = true,
+                "c" => stepping = false,
+                "q" => return Ok(()),
+                d => {
+                    writeln!(output, "unknown command: {}", d)?;
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    processor.resolve_overdue_disputes()?;
+    writeln!(output, "end of input at row {}", processor.row())?;
+    Ok(())
+}
+
+/// Print the processed action, state of its client and the record of its transaction
+fn print_stop<L: TransactionLoader, W: Write>(
+    processor: &TransactionsProcessor<L>,
+    action_details: &TransactionDetails,
+    result: &Result<(), Rejection>,
+    output: &mut W,
+) -> Result<(), anyhow::Error> {
+    let transaction_type: &str = action_details.transaction_type.into();
+    write!(
+        output,
+        "row {}: {} client {} tx {}",
+        processor.row(),
+        transaction_type,
+        action_details.client,
+        action_details.tx
+    )?;
+    if let Some(amount) = action_details.amount {
+        write!(output, " amount {}", amount)?;
+    }
+    match result {
+        Ok(()) => writeln!(output, " -> applied")?,
+        Err(reason) => writeln!(output, " -> rejected: {}", reason)?,
+    }
+
+    match processor
+        .client_state_mgr()
+        .get_state(action_details.client)?
+    {
+        Some(d) => writeln!(
+            output,
+            "  client {}: available {} held {} total {} status {} fees {}",
+            d.client, d.available, d.held, d.total, d.status, d.fees
+        )?,
+        None => writeln!(output, "  client {}: no state", action_details.client)?,
+    }
+
+    match processor.transaction_mgr().get_record(action_details.tx)? {
+        Some(d) => writeln!(output, "  tx {}: {}", action_details.tx, d)?,
+        None => writeln!(output, "  tx {}: no record", action_details.tx)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::csv_processor::TransactionIOLoader;
+    use crate::replay::{replay, Breakpoints};
+    use crate::transaction_processor::test::tx;
+    use crate::{ClientsStatesMgr, TransactionMgr, TransactionType, TransactionsProcessor};
+
+    fn loader() -> TransactionIOLoader {
+        TransactionIOLoader::from(vec![
+            tx(TransactionType::Deposit, 1, 1, Some(5.)),
+            tx(TransactionType::Deposit, 2, 2, Some(3.)),
+            tx(TransactionType::Withdrawal, 1, 3, Some(7.)),
+            tx(TransactionType::Dispute, 1, 1, None),
+        ])
+    }
+
+    fn run(breakpoints: &Breakpoints, step: bool, input: &str) -> String {
+        let mut client_state_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+        let mut processor =
+            TransactionsProcessor::new(&mut client_state_mgr, &mut transaction_mgr, loader());
+
+        let mut output = Vec::new();
+        replay(
+            &mut processor,
+            breakpoints,
+            step,
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    pub fn test_breakpoints() {
+        let breakpoints = Breakpoints {
+            tx: vec![3],
+            row: vec![2],
+            ..Default::default()
+        };
+        let output = run(&breakpoints, false, "");
+
+        assert!(output.contains("row 2: deposit client 2 tx 2 amount 3 -> applied\n"));
+        assert!(output.contains(
+            "row 3: withdrawal client 1 tx 3 amount 7 -> rejected: insufficient_funds\n"
+        ));
+        assert!(output.contains("  client 1: available 5 held 0 total 5 status active fees 0\n"));
+        assert!(output.contains("  tx 3: no record\n"));
+        assert!(!output.contains("row 1:"));
+        assert!(!output.contains("row 4:"));
+        assert!(output.ends_with("end of input at row 4\n"));
+
+        let breakpoints = Breakpoints {
+            client: vec![1],
+            ..Default::default()
+        };
+        let output = run(&breakpoints, false, "");
+        assert!(output.contains("row 4: dispute client 1 tx 1 -> applied\n"));
+        assert!(output.contains("  tx 1: deposit client 1 amount 5 row 1 dispute Open\n"));
+        assert!(!output.contains("row 2:"));
+    }
+
+    #[test]
+    pub fn test_step_mode() {
+        // Stop at the first row, step to the second one, then continue to the end
+        let output = run(&Breakpoints::default(), true, "s\nx\nc\n");
+        assert!(output.contains("row 1:"));
+        assert!(output.contains("row 2:"));
+        assert!(output.contains("unknown command: x"));
+        assert!(!output.contains("row 3:"));
+        assert!(output.ends_with("end of input at row 4\n"));
+
+        let breakpoints = Breakpoints {
+            row: vec![2],
+            ..Default::default()
+        };
+        let output = run(&breakpoints, true, "q\n");
+        assert!(output.contains("row 2:"));
+        assert!(!output.contains("end of input"), "Replay is stopped!");
+    }
+}
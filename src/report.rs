@@ -0,0 +1,49 @@
+use crate::rejection::Rejection;
+use std::collections::BTreeMap;
+
+/// Summary of the processed transaction actions
+#[derive(Default, Clone, Debug)]
+pub struct ProcessingReport {
+    /// Number of processed rows
+    pub rows: u64,
+    /// Number of applied actions
+    pub applied: u64,
+    /// Number of rejected actions per reason
+    pub rejections: BTreeMap<Rejection, u64>,
+}
+
+impl ProcessingReport {
+    /// Keep track on the result of the processed action
+    pub fn record(&mut self, result: &Result<(), Rejection>) {
+        self.rows += 1;
+        match result {
+            Ok(()) => self.applied += 1,
+            Err(reason) => *self.rejections.entry(*reason).or_default() += 1,
+        }
+    }
+
+    /// Total number of rejected actions
+    pub fn rejected(&self) -> u64 {
+        self.rejections.values().sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rejection::Rejection;
+    use crate::report::ProcessingReport;
+
+    #[test]
+    pub fn test_processing_report() {
+        let mut report = ProcessingReport::default();
+        report.record(&Ok(()));
+        report.record(&Err(Rejection::TxNotFound));
+        report.record(&Err(Rejection::InsufficientFunds));
+        report.record(&Err(Rejection::TxNotFound));
+
+        assert_eq!(report.rows, 4);
+        assert_eq!(report.applied, 1);
+        assert_eq!(report.rejected(), 3);
+        assert_eq!(report.rejections.get(&Rejection::TxNotFound), Some(&2));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{ClientState, TransactionDetails, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Number of recent actions kept per client for the rules
+const MAX_RECENT_ACTIVITY: usize = 256;
+
+/// Verdict of the fraud rule - the most severe one of all rules is applied
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// Action is applied
+    Allow,
+    /// Action is applied, but it's queued for review - the default verdict of the fired rule
+    #[default]
+    Flag,
+    /// Funds of the action are held until it's approved
+    Hold,
+    /// Action is rejected
+    Deny,
+}
+
+impl Verdict {
+    /// Short name of the verdict - used in logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Allow => "allow",
+            Verdict::Flag => "flag",
+            Verdict::Hold => "hold",
+            Verdict::Deny => "deny",
+        }
+    }
+}
+
+/// Applied action of the client - recent history for the rules
+#[derive(Clone, Debug)]
+pub struct Activity {
+    /// Processing row of the action
+    pub row: u64,
+    pub transaction_type: TransactionType,
+}
+
+/// Recent applied withdrawals and disputes per client - only the last ones are kept
+#[derive(Default, Clone)]
+pub struct RecentActivity {
+    client_to_activity: HashMap<u16, VecDeque<Activity>>,
+}
+
+impl RecentActivity {
+    /// Keep the applied action - only withdrawals and disputes are tracked
+    pub fn record(&mut self, client_id: u16, transaction_type: TransactionType, row: u64) {
+        if !matches!(
+            transaction_type,
+            TransactionType::Withdrawal | TransactionType::Dispute
+        ) {
+            return;
+        }
+
+        let activity = self.client_to_activity.entry(client_id).or_default();
+        if activity.len() == MAX_RECENT_ACTIVITY {
+            activity.pop_front();
+        }
+        activity.push_back(Activity {
+            row,
+            transaction_type,
+        });
+    }
+
+    /// Recent actions of the client in processing order
+    pub fn of(&self, client_id: u16) -> Vec<&Activity> {
+        self.client_to_activity
+            .get(&client_id)
+            .map_or_else(Vec::new, |d| d.iter().collect())
+    }
+}
+
+/// Fraud rule checked before deposit, withdrawal or transfer is applied
+pub trait FraudRule {
+    /// Name of the rule - used in logs
+    fn name(&self) -> &str;
+
+    /// Check the action against the client state (none for the new client) and its recent activity
+    fn check(
+        &self,
+        action_details: &TransactionDetails,
+        state: Option<&ClientState>,
+        activity: &[&Activity],
+        row: u64,
+    ) -> Verdict;
+}
+
+/// Number of the actions of the type within the window
+fn count_within(activity: &[&Activity], transaction_type: TransactionType, from_row: u64) -> usize {
+    activity
+        .iter()
+        .filter(|d| d.transaction_type == transaction_type && d.row >= from_row)
+        .count()
+}
+
+/// Velocity rule - withdrawal after N withdrawals within the window of M rows
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityRule {
+    /// Number of withdrawals within the window - the next one fires the rule
+    pub max_withdrawals: usize,
+    /// Window size in rows
+    pub window_rows: u64,
+    #[serde(default)]
+    pub verdict: Verdict,
+}
+
+impl FraudRule for VelocityRule {
+    fn name(&self) -> &str {
+        "velocity"
+    }
+
+    fn check(
+        &self,
+        action_details: &TransactionDetails,
+        _state: Option<&ClientState>,
+        activity: &[&Activity],
+        row: u64,
+    ) -> Verdict {
+        let from_row = row.saturating_sub(self.window_rows);
+        match action_details.transaction_type == TransactionType::Withdrawal
+            && count_within(activity, TransactionType::Withdrawal, from_row) >= self.max_withdrawals
+        {
+            true => self.verdict,
+            false => Verdict::Allow,
+        }
+    }
+}
+
+/// Large amount rule - amount more than the percent of the available funds
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LargeAmountRule {
+    /// Percent of the available funds, i.e. 50 -> amount more than half of the funds fires the rule
+    pub max_percent: f32,
+    /// Amounts not more than it are not checked
+    #[serde(default)]
+    pub min_amount: f32,
+    #[serde(default)]
+    pub verdict: Verdict,
+}
+
+impl FraudRule for LargeAmountRule {
+    fn name(&self) -> &str {
+        "large_amount"
+    }
+
+    fn check(
+        &self,
+        action_details: &TransactionDetails,
+        state: Option<&ClientState>,
+        _activity: &[&Activity],
+        _row: u64,
+    ) -> Verdict {
+        let amount = action_details.amount.unwrap_or_default();
+        let available = state.map_or(0., |d| d.available);
+        match amount > self.min_amount && amount > available * self.max_percent / 100. {
+            true => self.verdict,
+            false => Verdict::Allow,
+        }
+    }
+}
+
+/// Repeated disputes rule - action of the client with N disputes within the window of M rows
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatedDisputesRule {
+    /// Number of disputes within the window which fires the rule
+    pub max_disputes: usize,
+    /// Window size in rows
+    pub window_rows: u64,
+    #[serde(default)]
+    pub verdict: Verdict,
+}
+
+impl FraudRule for RepeatedDisputesRule {
+    fn name(&self) -> &str {
+        "repeated_disputes"
+    }
+
+    fn check(
+        &self,
+        _action_details: &TransactionDetails,
+        _state: Option<&ClientState>,
+        activity: &[&Activity],
+        row: u64,
+    ) -> Verdict {
+        let from_row = row.saturating_sub(self.window_rows);
+        match count_within(activity, TransactionType::Dispute, from_row) >= self.max_disputes {
+            true => self.verdict,
+            false => Verdict::Allow,
+        }
+    }
+}
+
+/// Built-in rule configuration - [[rules]] entries of the config file
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RuleConfig {
+    Velocity(VelocityRule),
+    LargeAmount(LargeAmountRule),
+    RepeatedDisputes(RepeatedDisputesRule),
+}
+
+impl RuleConfig {
+    /// Create the configured rule
+    pub fn build(&self) -> Box<dyn FraudRule> {
+        match self {
+            RuleConfig::Velocity(d) => Box::new(d.clone()),
+            RuleConfig::LargeAmount(d) => Box::new(d.clone()),
+            RuleConfig::RepeatedDisputes(d) => Box::new(d.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::EngineConfig;
+    use crate::rules::{RecentActivity, Verdict};
+    use crate::{ClientState, TransactionDetails, TransactionType};
+
+    #[test]
+    pub fn test_rules() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[rules]]
+            rule = "velocity"
+            max_withdrawals = 2
+            window_rows = 10
+
+            [[rules]]
+            rule = "large_amount"
+            max_percent = 50
+            min_amount = 5
+            verdict = "flag"
+
+            [[rules]]
+            rule = "repeated_disputes"
+            max_disputes = 2
+            window_rows = 100
+            verdict = "flag"
+            "#,
+        )
+        .unwrap();
+        let rules: Vec<_> = config.rules.iter().map(|d| d.build()).collect();
+        assert_eq!(rules[0].name(), "velocity");
+
+        let withdrawal = |amount| TransactionDetails {
+            transaction_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: Some(amount),
+            ..Default::default()
+        };
+        let state = ClientState {
+            client: 1,
+            available: 100.,
+            total: 100.,
+            ..Default::default()
+        };
+        let mut recent = RecentActivity::default();
+        let verdicts = |recent: &RecentActivity, amount, row| -> Vec<Verdict> {
+            let activity = recent.of(1);
+            rules
+                .iter()
+                .map(|d| d.check(&withdrawal(amount), Some(&state), &activity, row))
+                .collect()
+        };
+        assert_eq!(verdicts(&recent, 1., 1), vec![Verdict::Allow; 3]);
+
+        // Velocity - the third withdrawal within 10 rows is flagged - the default verdict
+        recent.record(1, TransactionType::Withdrawal, 1);
+        recent.record(1, TransactionType::Withdrawal, 5);
+        recent.record(1, TransactionType::Deposit, 6);
+        recent.record(2, TransactionType::Withdrawal, 6);
+        assert_eq!(verdicts(&recent, 1., 10)[0], Verdict::Flag);
+        assert_eq!(verdicts(&recent, 1., 12)[0], Verdict::Allow);
+
+        // Large amount - more than half of the funds, small amounts are not checked
+        assert_eq!(verdicts(&recent, 51., 20)[1], Verdict::Flag);
+        assert_eq!(verdicts(&recent, 50., 20)[1], Verdict::Allow);
+        let activity = recent.of(1);
+        assert_eq!(
+            rules[1].check(&withdrawal(4.), None, &activity, 20),
+            Verdict::Allow
+        );
+        assert_eq!(
+            rules[1].check(&withdrawal(6.), None, &activity, 20),
+            Verdict::Flag
+        );
+
+        // Repeated disputes
+        recent.record(1, TransactionType::Dispute, 30);
+        assert_eq!(verdicts(&recent, 1., 31)[2], Verdict::Allow);
+        recent.record(1, TransactionType::Dispute, 31);
+        assert_eq!(verdicts(&recent, 1., 32)[2], Verdict::Flag);
+        assert_eq!(verdicts(&recent, 1., 200)[2], Verdict::Allow);
+
+        assert!(EngineConfig::from_toml("[[rules]]\nrule = \"unknown\"").is_err());
+    }
+}
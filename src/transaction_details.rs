@@ -1,9 +1,11 @@
+use anyhow::anyhow;
+use chrono::DateTime;
 use serde::Serialize;
 use serde::{Deserialize, Deserializer};
 use std::str::FromStr;
 
 /// Supported transaction types
-#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Default, Clone, Copy, Deserialize, Serialize, PartialEq, Debug)]
 #[serde(into = "&str", from = "&str")]
 pub enum TransactionType {
     Deposit,
@@ -11,11 +13,30 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Undo of erroneous deposit/withdrawal - without locking the account
+    Reversal,
+    /// Move of funds to the destination client
+    Transfer,
+    /// Marker to accrue interest on available funds of all clients
+    Accrue,
+    /// Accrued interest - generated by the accrual, not accepted in the input
+    Interest,
+    /// Review decision - approve the flagged transaction
+    Approve,
+    /// Review decision - decline the flagged transaction
+    Decline,
+    /// Admin action - unlock the locked account
+    Unlock,
+    /// Admin action - lock the account, funds movements are rejected with any lock policy
+    Lock,
+    /// Admin action - close the account, funds movements are rejected after it
+    Close,
+    #[default]
     Unknown,
 }
 
 /// Base transaction details
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
 pub struct TransactionDetails {
     /// The transaction type
     pub transaction_type: TransactionType,
@@ -28,6 +49,53 @@ pub struct TransactionDetails {
     /// Amount of transaction - only for deposit and withdrawal
     #[serde(deserialize_with = "f32_with_whitespace")]
     pub amount: Option<f32>,
+    /// Destination client - optional column, mandatory for transfer
+    #[serde(
+        default,
+        deserialize_with = "optional_u16_with_whitespace",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub destination: Option<u16>,
+    /// Time of the transaction in epoch millis - optional column, RFC 3339 is accepted as well
+    #[serde(default, deserialize_with = "timestamp_with_whitespace")]
+    pub timestamp: Option<i64>,
+    /// Id of the operator - optional column, mandatory for admin actions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// Reason of the action - optional column, mandatory for admin actions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl TransactionType {
+    /// Whether it's the administrative action - on the account or the review decision
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock
+                | TransactionType::Lock
+                | TransactionType::Close
+                | TransactionType::Approve
+                | TransactionType::Decline
+        )
+    }
+
+    /// Whether tx of the action is the id of the transaction - created or referred by it
+    /// Account admin actions and accrual markers have their own ids
+    pub fn refers_tx(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Reversal
+                | TransactionType::Approve
+                | TransactionType::Decline
+        )
+    }
 }
 
 /// String to transaction type conversion
@@ -39,6 +107,15 @@ impl From<&str> for TransactionType {
             "dispute" => TransactionType::Dispute,
             "resolve" => TransactionType::Resolve,
             "chargeback" => TransactionType::Chargeback,
+            "reversal" => TransactionType::Reversal,
+            "transfer" => TransactionType::Transfer,
+            "accrue" => TransactionType::Accrue,
+            "interest" => TransactionType::Interest,
+            "approve" => TransactionType::Approve,
+            "decline" => TransactionType::Decline,
+            "unlock" => TransactionType::Unlock,
+            "lock" => TransactionType::Lock,
+            "close" => TransactionType::Close,
             _ => TransactionType::Unknown,
         }
     }
@@ -53,6 +130,15 @@ impl From<TransactionType> for &str {
             TransactionType::Dispute => "dispute",
             TransactionType::Resolve => "resolve",
             TransactionType::Chargeback => "chargeback",
+            TransactionType::Reversal => "reversal",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Accrue => "accrue",
+            TransactionType::Interest => "interest",
+            TransactionType::Approve => "approve",
+            TransactionType::Decline => "decline",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Lock => "lock",
+            TransactionType::Close => "close",
             TransactionType::Unknown => "unknown",
         }
     }
@@ -67,6 +153,18 @@ where
     u16::from_str(buf.trim()).map_err(serde::de::Error::custom)
 }
 
+/// To handle optional u16 column - empty value is none
+fn optional_u16_with_whitespace<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let buf: Option<String> = Option::deserialize(deserializer)?;
+    match buf.as_deref().map(str::trim).filter(|d| !d.is_empty()) {
+        Some(d) => Ok(Some(u16::from_str(d).map_err(serde::de::Error::custom)?)),
+        None => Ok(None),
+    }
+}
+
 /// To handle cases when the digit contains whitespaces
 fn u32_with_whitespace<'de, D>(deserializer: D) -> Result<u32, D::Error>
 where
@@ -89,3 +187,88 @@ where
         None => Ok(None),
     }
 }
+
+/// To handle optional timestamp - epoch millis or RFC 3339, may contain whitespaces
+fn timestamp_with_whitespace<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let buf: Option<String> = Option::deserialize(deserializer)?;
+    match buf {
+        Some(d) => Ok(Some(parse_timestamp(&d).map_err(serde::de::Error::custom)?)),
+        None => Ok(None),
+    }
+}
+
+/// Parse timestamp in epoch millis or RFC 3339 format to epoch millis
+pub fn parse_timestamp(timestamp: &str) -> Result<i64, anyhow::Error> {
+    let timestamp = timestamp.trim();
+    if let Ok(millis) = i64::from_str(timestamp) {
+        return Ok(millis);
+    }
+
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|d| d.timestamp_millis())
+        .map_err(|e| anyhow!("Invalid timestamp '{}': {}", timestamp, e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction_details::parse_timestamp;
+    use crate::{TransactionDetails, TransactionType};
+
+    #[test]
+    pub fn test_timestamp() {
+        assert_eq!(parse_timestamp(" 1650000000000 ").unwrap(), 1650000000000);
+        assert_eq!(
+            parse_timestamp("2022-04-15T05:20:00Z").unwrap(),
+            1650000000000
+        );
+        assert_eq!(
+            parse_timestamp("2022-04-15T07:20:00+02:00").unwrap(),
+            1650000000000
+        );
+        assert!(parse_timestamp("2022-04-15").is_err());
+
+        let data = "transaction_type,client,tx,amount,timestamp
+deposit,1,1,1.5, 2022-04-15T05:20:00Z
+withdrawal,1,2,1.0,1650000000001
+dispute,1,1,,
+";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let records: Vec<TransactionDetails> = rdr.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].timestamp, Some(1650000000000));
+        assert_eq!(records[1].timestamp, Some(1650000000001));
+        assert_eq!(records[2].transaction_type, TransactionType::Dispute);
+        assert!(records[2].timestamp.is_none());
+
+        let data = "transaction_type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let record: TransactionDetails = rdr.deserialize().next().unwrap().unwrap();
+        assert!(record.timestamp.is_none(), "Timestamp column is optional!");
+
+        let data = "transaction_type,client,tx,amount,timestamp\ndeposit,1,1,1.5,yesterday\n";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let record: Option<Result<TransactionDetails, _>> = rdr.deserialize().next();
+        assert!(record.unwrap().is_err());
+    }
+
+    #[test]
+    pub fn test_destination() {
+        let data = "transaction_type,client,tx,amount,destination
+transfer,1,1,1.5, 2
+deposit,1,2,1.0,
+";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let records: Vec<TransactionDetails> = rdr.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(records[0].transaction_type, TransactionType::Transfer);
+        assert_eq!(records[0].destination, Some(2));
+        assert!(records[1].destination.is_none());
+
+        let data = "transaction_type,client,tx,amount,destination\ntransfer,1,1,1.5,x\n";
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let record: Option<Result<TransactionDetails, _>> = rdr.deserialize().next();
+        assert!(record.unwrap().is_err());
+    }
+}
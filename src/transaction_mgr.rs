@@ -1,56 +1,246 @@
+use crate::disputes::DisputeState;
+use crate::transaction_store::{HashMapTransactionStore, TransactionRecord, TransactionStore};
 use crate::{TransactionDetails, TransactionType};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Fee charged for the transaction - kept in its record
+#[derive(Clone, Debug)]
+pub struct FeeEntry {
+    /// Client the fee is charged from
+    pub client: u16,
+    /// Fee amount
+    pub amount: f32,
+    /// Whether the fee is refunded, i.e. in case of chargeback
+    pub refunded: bool,
+}
+
+/// Open review of the transaction flagged by the fraud rules
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Review {
+    /// Transaction id
+    pub tx: u32,
+    /// Client of the transaction
+    pub client: u16,
+    /// Deposit or withdrawal
+    pub transaction_type: TransactionType,
+    /// Amount held in the pending bucket, the transaction amount if it's not held
+    pub amount: f32,
+    /// Whether the amount is held until the review - otherwise the transaction is applied
+    pub held: bool,
+    /// Names of the fired rules separated by ';'
+    pub rules: String,
+    /// Processing row of the transaction
+    pub row: u64,
+}
+
+/// Transaction with its open review - i.e. to roll it back
+#[derive(Clone, Debug)]
+pub struct TransactionSnapshot {
+    pub record: Option<TransactionRecord>,
+    pub review: Option<Review>,
+}
 
 /// Base transaction manager to keep track on transaction history
 pub struct TransactionMgr {
-    /// Transaction id to details mapping
-    id_to_details: HashMap<u32, TransactionDetails>,
+    /// Transaction id to compact record mapping - in memory or on disk
+    id_to_record: Box<dyn TransactionStore>,
+    /// Transaction id to its open review - ordered to list them
+    id_to_review: BTreeMap<u32, Review>,
 }
 
 impl TransactionMgr {
-    /// Create transaction manager
+    /// Create transaction manager with in-memory hash map store
     pub fn new() -> Self {
+        Self::with_store(Box::<HashMapTransactionStore>::default())
+    }
+
+    /// Create transaction manager with the provided store of transaction records
+    pub fn with_store(store: Box<dyn TransactionStore>) -> Self {
         Self {
-            id_to_details: Default::default(),
+            id_to_record: store,
+            id_to_review: Default::default(),
         }
     }
 
     /// Insert new transaction with the specified details
-    /// Only deposit and withdrawal transactions should be kept
-    /// Each transaction must have a valid amount
-    pub fn insert_new_transaction(&mut self, transaction: TransactionDetails) -> bool {
-        if transaction.transaction_type != TransactionType::Deposit
-            && transaction.transaction_type != TransactionType::Withdrawal
-        {
-            return false;
-        } else if transaction.amount.filter(|d| d >= &0.).is_none() {
-            return false;
+    /// Only deposit, withdrawal, transfer and interest transactions should be kept
+    /// Each transaction must have a valid amount, transfer - the destination as well
+    /// Row is the processing position of the transaction
+    /// Error is returned only if the store fails
+    pub fn insert_new_transaction(
+        &mut self,
+        transaction: TransactionDetails,
+        row: u64,
+    ) -> Result<bool, anyhow::Error> {
+        let mut record = TransactionRecord::new(&transaction, row);
+        match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Interest => {
+                record.destination = None
+            }
+            TransactionType::Transfer if transaction.destination.is_some() => {}
+            _ => return Ok(false),
+        }
+        if transaction.amount.filter(|d| d >= &0.).is_none() {
+            return Ok(false);
+        }
+
+        self.id_to_record.insert(transaction.tx, record)
+    }
+
+    /// Destination client of the transfer - none for other transactions
+    pub fn get_destination(&self, id: u32) -> Result<Option<u16>, anyhow::Error> {
+        Ok(self.id_to_record.get(id)?.and_then(|d| d.destination))
+    }
+
+    /// Get transaction by id and client id
+    pub fn get_transaction(
+        &self,
+        id: u32,
+        client_id: u16,
+    ) -> Result<Option<TransactionRecord>, anyhow::Error> {
+        Ok(self.id_to_record.get(id)?.filter(|d| d.client == client_id))
+    }
+
+    /// Get transaction by id - regardless of the client
+    pub fn get_record(&self, id: u32) -> Result<Option<TransactionRecord>, anyhow::Error> {
+        self.id_to_record.get(id)
+    }
+
+    pub fn transaction_exist(&self, id: u32) -> Result<bool, anyhow::Error> {
+        self.id_to_record.contains(id)
+    }
+
+    /// Get dispute state of the transaction - none if there is no transaction
+    pub fn get_dispute_state(&self, id: u32) -> Result<Option<DisputeState>, anyhow::Error> {
+        Ok(self.id_to_record.get(id)?.map(|d| d.dispute))
+    }
+
+    /// Update dispute state of existing transaction
+    pub fn set_dispute_state(
+        &mut self,
+        id: u32,
+        state: DisputeState,
+    ) -> Result<bool, anyhow::Error> {
+        match self.id_to_record.get(id)? {
+            Some(mut record) => {
+                record.dispute = state;
+                self.id_to_record.update(id, record)
+            }
+            None => Ok(false),
         }
+    }
 
-        match self.id_to_details.entry(transaction.tx) {
-            Entry::Occupied(_) => return false,
-            Entry::Vacant(v) => {
-                v.insert(transaction);
-                true
+    /// Update disputed amount of existing transaction - zero when the dispute is closed
+    pub fn set_disputed(&mut self, id: u32, amount: f32) -> Result<bool, anyhow::Error> {
+        match self.id_to_record.get(id)? {
+            Some(mut record) => {
+                record.disputed = amount;
+                self.id_to_record.update(id, record)
             }
+            None => Ok(false),
         }
     }
 
-    /// Get transaction by id and client id
-    pub fn get_transaction(&self, id: u32, client_id: u16) -> Option<&TransactionDetails> {
-        self.id_to_details
-            .get(&id)
-            .filter(|d| d.client == client_id)
+    /// Set fee charged for existing transaction from its client
+    /// Only one fee per transaction is allowed - returns false if it's set or there is no transaction
+    pub fn insert_fee(&mut self, id: u32, amount: f32) -> Result<bool, anyhow::Error> {
+        match self.id_to_record.get(id)?.filter(|d| d.fee == 0.) {
+            Some(mut record) => {
+                record.fee = amount;
+                self.id_to_record.update(id, record)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Get fee charged for the transaction by id and client id
+    pub fn get_fee(&self, id: u32, client_id: u16) -> Result<Option<FeeEntry>, anyhow::Error> {
+        Ok(self
+            .get_transaction(id, client_id)?
+            .filter(|d| d.fee > 0.)
+            .map(|d| FeeEntry {
+                client: d.client,
+                amount: d.fee,
+                refunded: d.fee_refunded,
+            }))
+    }
+
+    /// Open the review of the transaction - the existing one is replaced
+    pub fn insert_review(&mut self, review: Review) {
+        self.id_to_review.insert(review.tx, review);
+    }
+
+    /// Get open review of the transaction by id and client id
+    pub fn get_review(&self, id: u32, client_id: u16) -> Option<&Review> {
+        self.id_to_review.get(&id).filter(|d| d.client == client_id)
+    }
+
+    /// Close the review of the transaction - i.e. on approve or decline
+    pub fn remove_review(&mut self, id: u32) -> Option<Review> {
+        self.id_to_review.remove(&id)
+    }
+
+    /// Open reviews ordered by transaction id
+    pub fn reviews(&self) -> Vec<Review> {
+        self.id_to_review.values().cloned().collect()
+    }
+
+    /// Current state of the transaction - to restore it later
+    pub fn snapshot(&self, id: u32) -> Result<TransactionSnapshot, anyhow::Error> {
+        Ok(TransactionSnapshot {
+            record: self.id_to_record.get(id)?,
+            review: self.id_to_review.get(&id).cloned(),
+        })
+    }
+
+    /// Restore the state of the transaction - missing parts are removed, i.e. on rollback
+    pub fn restore(&mut self, id: u32, snapshot: TransactionSnapshot) -> Result<(), anyhow::Error> {
+        match snapshot.record {
+            Some(record) => {
+                if !self.id_to_record.update(id, record)? {
+                    self.id_to_record.insert(id, record)?;
+                }
+            }
+            None => {
+                self.id_to_record.remove(id)?;
+            }
+        }
+
+        match snapshot.review {
+            Some(review) => self.id_to_review.insert(id, review),
+            None => self.id_to_review.remove(&id),
+        };
+        Ok(())
+    }
+
+    /// Mark fee of the transaction as refunded
+    /// Returns false if there is no fee or it's already refunded
+    pub fn mark_fee_refunded(&mut self, id: u32) -> Result<bool, anyhow::Error> {
+        match self
+            .id_to_record
+            .get(id)?
+            .filter(|d| d.fee > 0. && !d.fee_refunded)
+        {
+            Some(mut record) => {
+                record.fee_refunded = true;
+                self.id_to_record.update(id, record)
+            }
+            None => Ok(false),
+        }
     }
+}
 
-    pub fn transaction_exist(&self, id: u32) -> bool {
-        self.id_to_details.contains_key(&id)
+impl Default for TransactionMgr {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::disputes::DisputeState;
+    use crate::transaction_store::DiskTransactionStore;
     use crate::{TransactionDetails, TransactionMgr, TransactionType};
     use float_cmp::approx_eq;
 
@@ -58,40 +248,145 @@ mod test {
     pub fn test_transaction_mgr() {
         let mut mgr = TransactionMgr::new();
 
-        assert!(mgr.id_to_details.is_empty());
+        assert!(!mgr.transaction_exist(1).unwrap());
 
         let mut tx = TransactionDetails {
             transaction_type: TransactionType::Deposit,
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
-        assert!(!mgr.insert_new_transaction(tx.clone()), "Amount is none!");
+        assert!(
+            !mgr.insert_new_transaction(tx.clone(), 1).unwrap(),
+            "Amount is none!"
+        );
         tx.amount = Some(-1.);
         assert!(
-            !mgr.insert_new_transaction(tx.clone()),
+            !mgr.insert_new_transaction(tx.clone(), 1).unwrap(),
             "Amount is negative!"
         );
-        assert!(!mgr.transaction_exist(1));
+        assert!(!mgr.transaction_exist(1).unwrap());
         tx.amount = Some(2.);
-        assert!(mgr.insert_new_transaction(tx.clone()));
+        assert!(mgr.insert_new_transaction(tx.clone(), 1).unwrap());
 
         tx.amount = Some(3.);
         assert!(
-            !mgr.insert_new_transaction(tx.clone()),
+            !mgr.insert_new_transaction(tx.clone(), 1).unwrap(),
             "Transaction with ID present!"
         );
         assert!(
             approx_eq!(
                 f32,
-                mgr.get_transaction(1, 1).unwrap().amount.unwrap(),
+                mgr.get_transaction(1, 1).unwrap().unwrap().amount,
                 2.,
                 ulps = 4
             ),
             "Amount shouldn't be changed if transaction is present!"
         );
 
-        assert!(mgr.transaction_exist(1));
+        assert!(mgr.transaction_exist(1).unwrap());
+        assert_eq!(mgr.get_transaction(1, 1).unwrap().unwrap().row, 1);
+    }
+
+    #[test]
+    pub fn test_dispute_state() {
+        let mut mgr = TransactionMgr::new();
+        assert!(
+            !mgr.set_dispute_state(1, DisputeState::Resolved).unwrap(),
+            "No tx!"
+        );
+
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(1.),
+            ..Default::default()
+        };
+        assert!(mgr.insert_new_transaction(tx, 3).unwrap());
+        assert!(mgr.get_dispute_state(2).unwrap().is_none());
+        assert_eq!(
+            mgr.get_dispute_state(1).unwrap(),
+            Some(DisputeState::NotDisputed)
+        );
+
+        assert!(mgr.set_dispute_state(1, DisputeState::Open).unwrap());
+        assert_eq!(mgr.get_dispute_state(1).unwrap(), Some(DisputeState::Open));
+        assert_eq!(mgr.get_transaction(1, 1).unwrap().unwrap().row, 3);
+
+        assert!(mgr.set_disputed(1, 0.25).unwrap());
+        assert!(!mgr.set_disputed(2, 0.25).unwrap(), "No tx!");
+        assert_eq!(mgr.get_record(1).unwrap().unwrap().disputed, 0.25);
+
+        let snapshot = mgr.snapshot(1).unwrap();
+        mgr.set_disputed(1, 0.).unwrap();
+        mgr.set_dispute_state(1, DisputeState::Resolved).unwrap();
+        assert_eq!(mgr.get_record(1).unwrap().unwrap().disputed, 0.);
+
+        mgr.restore(1, snapshot).unwrap();
+        let record = mgr.get_record(1).unwrap().unwrap();
+        assert_eq!(record.dispute, DisputeState::Open);
+        assert_eq!(record.disputed, 0.25);
+    }
+
+    #[test]
+    pub fn test_fees() {
+        let mut mgr = TransactionMgr::new();
+
+        assert!(mgr.get_fee(1, 1).unwrap().is_none());
+        assert!(!mgr.insert_fee(1, 0.5).unwrap(), "No tx!");
+
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(10.),
+            ..Default::default()
+        };
+        assert!(mgr.insert_new_transaction(tx, 1).unwrap());
+        assert!(mgr.get_fee(1, 1).unwrap().is_none());
+        assert!(mgr.insert_fee(1, 0.5).unwrap());
+        assert!(
+            !mgr.insert_fee(1, 0.7).unwrap(),
+            "Fee for the tx is present!"
+        );
+        assert!(mgr.get_fee(1, 2).unwrap().is_none(), "Client mismatch!");
+
+        let fee = mgr.get_fee(1, 1).unwrap().unwrap();
+        assert!(approx_eq!(f32, fee.amount, 0.5, ulps = 4));
+        assert!(!fee.refunded);
+
+        assert!(mgr.mark_fee_refunded(1).unwrap());
+        assert!(mgr.get_fee(1, 1).unwrap().unwrap().refunded);
+        assert!(!mgr.mark_fee_refunded(1).unwrap(), "Already refunded!");
+        assert!(!mgr.mark_fee_refunded(2).unwrap(), "No fee for tx 2!");
+    }
+
+    #[test]
+    pub fn test_disk_store() {
+        let store = DiskTransactionStore::temporary().unwrap();
+        let mut mgr = TransactionMgr::with_store(Box::new(store));
+
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Withdrawal,
+            client: 2,
+            tx: 7,
+            amount: Some(1.5),
+            timestamp: Some(100),
+            ..Default::default()
+        };
+        assert!(mgr.insert_new_transaction(tx.clone(), 1).unwrap());
+        assert!(!mgr.insert_new_transaction(tx, 2).unwrap());
+        assert!(
+            mgr.get_transaction(7, 1).unwrap().is_none(),
+            "Client mismatch!"
+        );
+
+        assert!(mgr.set_dispute_state(7, DisputeState::Open).unwrap());
+        let record = mgr.get_transaction(7, 2).unwrap().unwrap();
+        assert_eq!(record.dispute, DisputeState::Open);
+        assert_eq!(record.timestamp(), Some(100));
     }
 }
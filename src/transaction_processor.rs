@@ -1,7 +1,67 @@
+use crate::audit::AuditLog;
 use crate::client_state_mgr::ClientsStatesMgr;
+use crate::config::EngineConfig;
 use crate::csv_processor::TransactionLoader;
-use crate::transaction_mgr::TransactionMgr;
-use crate::{TransactionDetails, TransactionType};
+use crate::disputes::{DisputeDeadlines, DisputeState, InsufficientFundsPolicy, OpenDispute};
+use crate::history::ClientHistory;
+use crate::ledger::Ledger;
+use crate::limits::{check_balance, Limits, WithdrawalWindows};
+use crate::metrics::MetricsRegistry;
+use crate::ordering::OrderingMode;
+use crate::rejection::Rejection;
+use crate::report::ProcessingReport;
+use crate::rules::{FraudRule, RecentActivity, Verdict};
+use crate::transaction_mgr::{Review, TransactionMgr, TransactionSnapshot};
+use crate::{AccountStatus, ClientState, TransactionDetails, TransactionType};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Before-images of the state changed by the atomic batch - to roll it back
+struct BatchUndo {
+    /// Client id to its state before the batch - none if the client is created by the batch
+    clients: HashMap<u16, Option<ClientState>>,
+    /// Transaction id to its state before the batch
+    transactions: HashMap<u32, TransactionSnapshot>,
+    /// Transactions marked as seen in the ledger by the batch
+    seen: Vec<u32>,
+    /// Applied actions with their rows - to record the rollback
+    applied: Vec<(u64, TransactionDetails)>,
+    /// Metrics of the processed actions - recorded when the batch is committed or rolled back
+    metrics: Vec<MetricsEvent>,
+    withdrawal_windows: WithdrawalWindows,
+    dispute_deadlines: DisputeDeadlines,
+    activity: RecentActivity,
+    last_timestamp: Option<i64>,
+}
+
+/// Metrics update of the processed action - deferred within the atomic batch
+enum MetricsEvent {
+    Row(TransactionType, Result<(), Rejection>, Duration),
+    Locked(u16),
+    Unlocked(u16),
+}
+
+/// State of the processing carried between batches - i.e. files of one run
+/// Limits windows, dispute deadlines, activity for the rules and the position continue in the next batch
+#[derive(Default, Clone)]
+pub struct ProcessingState {
+    withdrawal_windows: WithdrawalWindows,
+    dispute_deadlines: DisputeDeadlines,
+    activity: RecentActivity,
+    row: u64,
+    last_timestamp: Option<i64>,
+    next_accrual_tx: Option<u32>,
+}
+
+/// Admin action requires the operator and non-empty reason
+fn check_operator(action_details: &TransactionDetails) -> Result<(), Rejection> {
+    let missing = |d: &Option<String>| d.as_deref().is_none_or(|d| d.trim().is_empty());
+    match missing(&action_details.operator) || missing(&action_details.reason) {
+        true => Err(Rejection::MissingOperatorOrReason),
+        false => Ok(()),
+    }
+}
 
 /// Processor to apply new transaction actions
 pub struct TransactionsProcessor<'a, L: TransactionLoader> {
@@ -11,6 +71,36 @@ pub struct TransactionsProcessor<'a, L: TransactionLoader> {
     transaction_mgr: &'a mut TransactionMgr,
     /// Transaction actions loader/streamer
     transaction_loader: L,
+    /// Engine configuration - fees, limits, etc.
+    config: EngineConfig,
+    /// Fraud rules checked before deposits and withdrawals - configured and custom ones
+    rules: Vec<Box<dyn FraudRule>>,
+    /// Recent withdrawals and disputes per client - for the rules
+    activity: RecentActivity,
+    /// Clients withdrawals within the limits window
+    withdrawal_windows: WithdrawalWindows,
+    /// Open disputes waiting for auto-resolve
+    dispute_deadlines: DisputeDeadlines,
+    /// Number of processed rows
+    row: u64,
+    /// Latest timestamp of processed actions
+    last_timestamp: Option<i64>,
+    /// Point in time - actions after it are skipped
+    as_of: Option<i64>,
+    /// Processed actions per client - kept only if requested
+    history: Option<ClientHistory>,
+    /// Metrics of the run - updated only if requested
+    metrics: Option<MetricsRegistry>,
+    /// Audit log of processed actions - written only if requested
+    audit: Option<AuditLog>,
+    /// Ledger of ingested batches - deposits/withdrawals seen in other batches are rejected
+    ledger: Option<Ledger>,
+    /// Next tx id of the generated accrual - first id of the reserved range if none
+    next_accrual_tx: Option<u32>,
+    /// Before-images of the atomic batch in progress
+    undo: Option<BatchUndo>,
+    /// Failure of the store - processing is stopped on it
+    store_error: Option<anyhow::Error>,
 }
 
 impl<'a, L: TransactionLoader> TransactionsProcessor<'a, L> {
@@ -24,347 +114,3016 @@ impl<'a, L: TransactionLoader> TransactionsProcessor<'a, L> {
             client_state_mgr,
             transaction_mgr,
             transaction_loader,
+            config: Default::default(),
+            rules: Vec::new(),
+            activity: Default::default(),
+            withdrawal_windows: Default::default(),
+            dispute_deadlines: Default::default(),
+            row: 0,
+            last_timestamp: None,
+            as_of: None,
+            history: None,
+            metrics: None,
+            audit: None,
+            ledger: None,
+            next_accrual_tx: None,
+            undo: None,
+            store_error: None,
+        }
+    }
+
+    /// Use provided engine configuration instead of the default one
+    /// Configured fraud rules replace the current ones - custom rules should be added after it
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.rules = config.rules.iter().map(|d| d.build()).collect();
+        self.config = config;
+        self
+    }
+
+    /// Add custom fraud rule - checked after the configured ones
+    pub fn with_rule(mut self, rule: Box<dyn FraudRule>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Skip actions with timestamp after the provided one - to get states at the point in time
+    pub fn with_as_of(mut self, as_of: Option<i64>) -> Self {
+        self.as_of = as_of;
+        self
+    }
+
+    /// Keep processed actions per client - i.e. to explain balances on reconciliation
+    pub fn with_history(mut self) -> Self {
+        self.history = Some(ClientHistory::default());
+        self
+    }
+
+    /// Update the metrics registry on each processed action
+    pub fn with_metrics(mut self, metrics: MetricsRegistry) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Append each processed action to the audit log
+    /// Processing is stopped if the log can't be written
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Check deposits/withdrawals against the ledger - its batch should be started
+    pub fn with_ledger(mut self, ledger: Ledger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Continue the processing state of the previous batch
+    pub fn with_state(mut self, state: ProcessingState) -> Self {
+        self.withdrawal_windows = state.withdrawal_windows;
+        self.dispute_deadlines = state.dispute_deadlines;
+        self.activity = state.activity;
+        self.row = state.row;
+        self.last_timestamp = state.last_timestamp;
+        self.next_accrual_tx = state.next_accrual_tx;
+        self
+    }
+
+    /// Take the processing state - to continue it in the next batch
+    pub fn take_state(&mut self) -> ProcessingState {
+        ProcessingState {
+            withdrawal_windows: std::mem::take(&mut self.withdrawal_windows),
+            dispute_deadlines: std::mem::take(&mut self.dispute_deadlines),
+            activity: std::mem::take(&mut self.activity),
+            row: self.row,
+            last_timestamp: self.last_timestamp,
+            next_accrual_tx: self.next_accrual_tx,
         }
     }
 
+    /// Take processed actions per client - none if not requested
+    pub fn take_history(&mut self) -> Option<ClientHistory> {
+        self.history.take()
+    }
+
+    /// Take the audit log - i.e. to continue it with the next batch
+    pub fn take_audit(&mut self) -> Option<AuditLog> {
+        self.audit.take()
+    }
+
+    /// Take the ledger - i.e. to finish its batch
+    pub fn take_ledger(&mut self) -> Option<Ledger> {
+        self.ledger.take()
+    }
+
     /// Apply transaction actions on existing states
-    pub fn apply_transaction_actions(&mut self) {
+    /// Returns the summary of applied and rejected actions
+    /// Stops with error if the transaction or client state store fails
+    pub fn apply_transaction_actions(&mut self) -> Result<ProcessingReport, anyhow::Error> {
+        let mut report = ProcessingReport::default();
         while let Some(action_details) = self.transaction_loader.next_transaction() {
-            match action_details.transaction_type {
-                TransactionType::Deposit => self.apply_deposit(action_details),
-                TransactionType::Withdrawal => self.apply_withdrawal(action_details),
-                TransactionType::Dispute => self.apply_dispute(action_details),
-                TransactionType::Resolve => self.apply_resolve(action_details),
-                TransactionType::Chargeback => self.apply_chargeback(action_details),
-                TransactionType::Unknown => false,
-            };
+            // Rejected actions are skipped
+            let result = self.process_transaction(action_details);
+            report.record(&result);
+            if result == Err(Rejection::StoreFailure) {
+                return Err(self.take_store_error());
+            }
         }
+        self.resolve_overdue_disputes()?;
+
+        Ok(report)
     }
 
-    fn apply_deposit(&mut self, action_details: TransactionDetails) -> bool {
-        if action_details.transaction_type != TransactionType::Deposit
-            || action_details.amount.is_none()
-        {
-            return false;
+    /// Apply all transaction actions as one atomic batch - see apply_batch
+    pub fn apply_transaction_actions_atomically(
+        &mut self,
+    ) -> Result<ProcessingReport, anyhow::Error> {
+        let mut batch = Vec::new();
+        while let Some(action_details) = self.transaction_loader.next_transaction() {
+            batch.push(action_details);
         }
 
-        let amount = action_details.amount.unwrap();
-        if amount <= 0_f32 {
-            return false;
-        } else if self.transaction_mgr.transaction_exist(action_details.tx) {
-            return false;
+        let report = self.apply_batch(batch)?;
+        if report.rejected() == 0 {
+            self.resolve_overdue_disputes()?;
         }
+        Ok(report)
+    }
 
-        if !self
-            .client_state_mgr
-            .apply_deposit(action_details.client, amount)
-        {
-            return false;
+    /// Resolve disputes overdue at the end of the input - otherwise they wait for the next row
+    /// The point in time is the as-of one if set, the latest applied timestamp otherwise
+    pub fn resolve_overdue_disputes(&mut self) -> Result<(), anyhow::Error> {
+        match self.auto_resolve_disputes(self.as_of.or(self.last_timestamp)) {
+            Err(Rejection::StoreFailure) => Err(self.take_store_error()),
+            _ => Ok(()),
         }
-
-        self.transaction_mgr.insert_new_transaction(action_details)
     }
 
-    fn apply_withdrawal(&mut self, action_details: TransactionDetails) -> bool {
-        if action_details.transaction_type != TransactionType::Withdrawal
-            || action_details.amount.is_none()
-        {
-            return false;
-        } else if self.transaction_mgr.transaction_exist(action_details.tx) {
-            return false;
-        }
+    /// Apply the actions all-or-nothing - the first rejected action rolls back the applied ones
+    /// Rolled back and not processed actions are reported as batch_rolled_back
+    /// Stops with error if the store fails - the batch is rolled back as well
+    pub fn apply_batch(
+        &mut self,
+        batch: Vec<TransactionDetails>,
+    ) -> Result<ProcessingReport, anyhow::Error> {
+        self.undo = Some(BatchUndo {
+            clients: HashMap::new(),
+            transactions: HashMap::new(),
+            seen: Vec::new(),
+            applied: Vec::new(),
+            metrics: Vec::new(),
+            withdrawal_windows: self.withdrawal_windows.clone(),
+            dispute_deadlines: self.dispute_deadlines.clone(),
+            activity: self.activity.clone(),
+            last_timestamp: self.last_timestamp,
+        });
 
-        let amount = action_details.amount.unwrap();
-        if amount <= 0_f32 {
-            return false;
+        let size = batch.len();
+        let mut results = Vec::with_capacity(size);
+        for action_details in batch {
+            let details = action_details.clone();
+            let result = self.process_transaction(action_details);
+            results.push(result);
+
+            match result {
+                Ok(()) => {
+                    if let Some(undo) = self.undo.as_mut() {
+                        undo.applied.push((self.row, details));
+                    }
+                }
+                Err(reason) => {
+                    let rollback = self.rollback();
+                    if reason == Rejection::StoreFailure {
+                        return Err(self.take_store_error());
+                    }
+                    rollback?;
+                    break;
+                }
+            }
+        }
+        // Batch is committed
+        if let Some(undo) = self.undo.take() {
+            for event in undo.metrics {
+                self.record_metrics(event);
+            }
         }
 
-        if !self
-            .client_state_mgr
-            .apply_withdrawal(action_details.client, amount)
-        {
-            return false;
+        let mut report = ProcessingReport::default();
+        let rolled_back = results.iter().any(|d| d.is_err());
+        for result in results {
+            report.record(&match result {
+                Ok(()) if rolled_back => Err(Rejection::BatchRolledBack),
+                d => d,
+            });
+        }
+        for _ in report.rows..size as u64 {
+            report.record(&Err(Rejection::BatchRolledBack));
         }
 
-        self.transaction_mgr.insert_new_transaction(action_details)
+        Ok(report)
     }
 
-    fn apply_dispute(&mut self, action_details: TransactionDetails) -> bool {
-        if action_details.transaction_type != TransactionType::Dispute
-            || action_details.amount.is_some()
-        {
-            return false;
+    /// Restore the before-images of the batch in progress
+    /// Applied actions are logged and recorded as rolled back
+    fn rollback(&mut self) -> Result<(), anyhow::Error> {
+        let undo = match self.undo.take() {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        for (client, state) in undo.clients {
+            self.client_state_mgr.restore_state(client, state)?;
+        }
+        for (tx, snapshot) in undo.transactions {
+            self.transaction_mgr.restore(tx, snapshot)?;
         }
+        if let Some(ledger) = self.ledger.as_mut() {
+            for tx in undo.seen {
+                ledger.unmark_seen(tx);
+            }
+        }
+        self.withdrawal_windows = undo.withdrawal_windows;
+        self.dispute_deadlines = undo.dispute_deadlines;
+        self.activity = undo.activity;
+        self.last_timestamp = undo.last_timestamp;
 
-        let transaction = self
-            .transaction_mgr
-            .get_transaction(action_details.tx, action_details.client);
-        // If transaction is not found - ignore!
-        if transaction.is_none() {
-            return false;
+        // Applied rows are counted as rolled back, locks of the batch are not counted
+        for event in undo.metrics {
+            match event {
+                MetricsEvent::Row(transaction_type, Ok(()), duration) => self.record_metrics(
+                    MetricsEvent::Row(transaction_type, Err(Rejection::BatchRolledBack), duration),
+                ),
+                MetricsEvent::Row(..) => self.record_metrics(event),
+                MetricsEvent::Locked(_) | MetricsEvent::Unlocked(_) => {}
+            }
         }
 
-        let amount = transaction.unwrap().amount.unwrap();
+        let result = Err(Rejection::BatchRolledBack);
+        for (row, action_details) in undo.applied {
+            log::warn!(
+                row = row,
+                client = action_details.client,
+                tx = action_details.tx,
+                outcome = Rejection::BatchRolledBack.as_str();
+                "action rolled back"
+            );
+            if let Some(history) = self.history.as_mut() {
+                history.record(&action_details, row, &result);
+            }
+            self.append_audit(row, &action_details, &result)?;
+        }
 
-        if !self
-            .client_state_mgr
-            .apply_dispute(action_details.client, amount)
-        {
-            return false;
+        Ok(())
+    }
+
+    /// Keep the client state and the transaction before the change - only within the atomic batch
+    fn keep_before_image(&mut self, client_id: u16, tx: u32) -> Result<(), anyhow::Error> {
+        let undo = match self.undo.as_mut() {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        if let Entry::Vacant(v) = undo.clients.entry(client_id) {
+            v.insert(self.client_state_mgr.get_state(client_id)?);
+        }
+        if let Entry::Vacant(v) = undo.transactions.entry(tx) {
+            v.insert(self.transaction_mgr.snapshot(tx)?);
         }
 
-        true
+        Ok(())
     }
 
-    fn apply_resolve(&mut self, action_details: TransactionDetails) -> bool {
-        if action_details.transaction_type != TransactionType::Resolve
-            || action_details.amount.is_some()
-        {
-            return false;
+    /// Load and apply the next transaction action - for step-by-step processing
+    /// Returns the action with its result, none at the end of the input
+    pub fn step(&mut self) -> Option<(TransactionDetails, Result<(), Rejection>)> {
+        let action_details = self.transaction_loader.next_transaction()?;
+        let result = self.process_transaction(action_details.clone());
+        Some((action_details, result))
+    }
+
+    /// Number of processed rows - the row of the last processed action
+    pub fn row(&self) -> u64 {
+        self.row
+    }
+
+    /// Clients states - i.e. to inspect them between steps
+    pub fn client_state_mgr(&self) -> &ClientsStatesMgr {
+        self.client_state_mgr
+    }
+
+    /// Transaction history - i.e. to inspect it between steps
+    pub fn transaction_mgr(&self) -> &TransactionMgr {
+        self.transaction_mgr
+    }
+
+    /// Take the error of the failed store - if the action is rejected with store_failure
+    pub fn take_store_error(&mut self) -> anyhow::Error {
+        self.store_error
+            .take()
+            .or_else(|| self.client_state_mgr.take_store_error())
+            .unwrap_or_else(|| anyhow::anyhow!("Store failure"))
+    }
+
+    /// Keep the store error to report it - the action is rejected
+    fn store_failure(&mut self, error: anyhow::Error) -> Rejection {
+        self.store_error = Some(error);
+        Rejection::StoreFailure
+    }
+
+    /// Apply single transaction action
+    /// Returns the reason if the action is rejected
+    pub fn process_transaction(
+        &mut self,
+        action_details: TransactionDetails,
+    ) -> Result<(), Rejection> {
+        let started = Instant::now();
+        let (transaction_type, client, tx) = (
+            action_details.transaction_type,
+            action_details.client,
+            action_details.tx,
+        );
+        let details_copy =
+            (self.history.is_some() || self.audit.is_some()).then(|| action_details.clone());
+        // State before the action is needed only to log balance deltas
+        let before = match log::log_enabled!(log::Level::Info) {
+            true => self.client_state_mgr.get_state(client).ok().flatten(),
+            false => None,
+        };
+        let mut result = self.apply_action(action_details);
+        if result.is_ok() {
+            self.activity.record(client, transaction_type, self.row);
         }
 
-        let transaction = self
-            .transaction_mgr
-            .get_transaction(action_details.tx, action_details.client);
-        // If transaction is not found - ignore!
-        if transaction.is_none() {
-            return false;
+        self.log_action(transaction_type, client, tx, &result, before);
+        if let Some(details) = details_copy {
+            if let Some(history) = self.history.as_mut() {
+                history.record(&details, self.row, &result);
+            }
+            if let Err(e) = self.append_audit(self.row, &details, &result) {
+                result = Err(self.store_failure(e));
+            }
         }
+        if self.metrics.is_some() {
+            self.defer_metrics(MetricsEvent::Row(
+                transaction_type,
+                result,
+                started.elapsed(),
+            ));
+            match transaction_type {
+                // Chargeback of the transfer locks its destination
+                TransactionType::Chargeback if result.is_ok() => {
+                    let holder = self
+                        .transaction_mgr
+                        .get_destination(tx)
+                        .ok()
+                        .flatten()
+                        .unwrap_or(client);
+                    self.defer_metrics(MetricsEvent::Locked(holder))
+                }
+                TransactionType::Lock if result.is_ok() => {
+                    self.defer_metrics(MetricsEvent::Locked(client))
+                }
+                TransactionType::Unlock | TransactionType::Close if result.is_ok() => {
+                    self.defer_metrics(MetricsEvent::Unlocked(client))
+                }
+                _ => {}
+            }
+        }
+        result
+    }
 
-        let amount = transaction.unwrap().amount.unwrap();
+    /// Record the metrics - kept until the end of the atomic batch in progress
+    fn defer_metrics(&mut self, event: MetricsEvent) {
+        match self.undo.as_mut() {
+            Some(undo) => undo.metrics.push(event),
+            None => self.record_metrics(event),
+        }
+    }
 
-        if !self
-            .client_state_mgr
-            .apply_resolve(action_details.client, amount)
-        {
-            return false;
+    fn record_metrics(&self, event: MetricsEvent) {
+        let metrics = match &self.metrics {
+            Some(d) => d,
+            None => return,
+        };
+        match event {
+            MetricsEvent::Row(transaction_type, result, duration) => {
+                metrics.record_row(transaction_type, &result, duration)
+            }
+            MetricsEvent::Locked(client) => metrics.record_locked(client),
+            MetricsEvent::Unlocked(client) => metrics.record_unlocked(client),
         }
+    }
+
+    /// Append the processed action with the client state after it to the audit log
+    fn append_audit(
+        &mut self,
+        row: u64,
+        action_details: &TransactionDetails,
+        result: &Result<(), Rejection>,
+    ) -> Result<(), anyhow::Error> {
+        let audit = match self.audit.as_mut() {
+            Some(d) => d,
+            None => return Ok(()),
+        };
 
-        true
+        let state = self.client_state_mgr.get_state(action_details.client)?;
+        audit.append(row, action_details, result, state.as_ref())
     }
 
-    fn apply_chargeback(&mut self, action_details: TransactionDetails) -> bool {
-        if action_details.transaction_type != TransactionType::Chargeback
-            || action_details.amount.is_some()
+    /// Log the processed action - applied ones with balance deltas on info, rejected ones on warn
+    fn log_action(
+        &self,
+        transaction_type: TransactionType,
+        client: u16,
+        tx: u32,
+        result: &Result<(), Rejection>,
+        before: Option<ClientState>,
+    ) {
+        let transaction_type: &str = transaction_type.into();
+        match result {
+            Ok(()) if log::log_enabled!(log::Level::Info) => {
+                let before = before.unwrap_or_default();
+                let after = self
+                    .client_state_mgr
+                    .get_state(client)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                log::info!(
+                    row = self.row,
+                    client = client,
+                    tx = tx,
+                    transaction_type = transaction_type,
+                    outcome = "applied",
+                    delta_available:% = after.available - before.available,
+                    delta_held:% = after.held - before.held,
+                    delta_total:% = after.total - before.total;
+                    "action applied"
+                );
+            }
+            Ok(()) => {}
+            Err(reason) => log::warn!(
+                row = self.row,
+                client = client,
+                tx = tx,
+                transaction_type = transaction_type,
+                outcome = reason.as_str();
+                "action rejected"
+            ),
+        }
+    }
+
+    fn apply_action(&mut self, mut action_details: TransactionDetails) -> Result<(), Rejection> {
+        self.row += 1;
+        action_details.amount = action_details.amount.map(|d| self.config.amounts.round(d));
+        self.keep_before_image(action_details.client, action_details.tx)
+            .map_err(|e| self.store_failure(e))?;
+        self.check_timestamp(&action_details)?;
+        self.auto_resolve_disputes(action_details.timestamp)?;
+        // Generated interest transactions can't be created or referred by the input
+        if action_details.transaction_type.refers_tx()
+            && self.config.interest.is_reserved(action_details.tx)
         {
-            return false;
+            return Err(Rejection::ReservedTx);
         }
+        if matches!(
+            action_details.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        ) {
+            self.check_not_closed(action_details.client)?;
+        }
+        self.check_not_locked(action_details.client, action_details.transaction_type)?;
 
-        let transaction = self
-            .transaction_mgr
-            .get_transaction(action_details.tx, action_details.client);
-        // If transaction is not found - ignore!
-        if transaction.is_none() {
-            return false;
-        } else if transaction.as_ref().unwrap().client != action_details.client {
-            return false;
+        let (timestamp, transaction_type, tx) = (
+            action_details.timestamp,
+            action_details.transaction_type,
+            action_details.tx,
+        );
+        let result = match action_details.transaction_type {
+            TransactionType::Deposit => self.apply_deposit(action_details),
+            TransactionType::Withdrawal => self.apply_withdrawal(action_details),
+            TransactionType::Dispute => self.apply_dispute(action_details),
+            TransactionType::Resolve => self.apply_resolve(action_details),
+            TransactionType::Chargeback => self.apply_chargeback(action_details),
+            TransactionType::Reversal => self.apply_reversal(action_details),
+            TransactionType::Transfer => self.apply_transfer(action_details),
+            TransactionType::Accrue => self.apply_accrue(action_details),
+            TransactionType::Unlock | TransactionType::Lock | TransactionType::Close => {
+                self.apply_admin(action_details)
+            }
+            TransactionType::Approve | TransactionType::Decline => {
+                self.apply_review(action_details)
+            }
+            TransactionType::Interest | TransactionType::Unknown => Err(Rejection::UnknownType),
+        };
+
+        // Rejected action doesn't move the point in time of the ordering check
+        // and isn't marked as seen in the ledger
+        result?;
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+        if matches!(
+            transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        ) {
+            self.mark_in_ledger(tx)?;
         }
+        Ok(())
+    }
 
-        let amount = transaction.unwrap().amount.unwrap();
+    /// Check timestamp of the action against the point in time and ordering mode
+    /// Actions without timestamp are not checked
+    fn check_timestamp(&self, action_details: &TransactionDetails) -> Result<(), Rejection> {
+        let timestamp = match action_details.timestamp {
+            Some(d) => d,
+            None => return Ok(()),
+        };
 
-        if !self
-            .client_state_mgr
-            .apply_chargeback(action_details.client, amount)
+        if self.as_of.filter(|d| timestamp > *d).is_some() {
+            return Err(Rejection::AfterAsOf);
+        } else if self.config.ordering.mode != OrderingMode::FileOrder
+            && self.last_timestamp.filter(|d| timestamp < *d).is_some()
         {
-            return false;
+            return Err(Rejection::OutOfOrder);
         }
 
-        true
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::csv_processor::TransactionLoader;
-    use crate::{
-        ClientsStatesMgr, TransactionDetails, TransactionMgr, TransactionType,
-        TransactionsProcessor,
-    };
-    use float_cmp::approx_eq;
+    /// Closed account doesn't accept new funds movements
+    /// Disputes of its earlier transactions are still processed
+    fn check_not_closed(&mut self, client: u16) -> Result<(), Rejection> {
+        let state = self
+            .client_state_mgr
+            .get_state(client)
+            .map_err(|e| self.store_failure(e))?;
+        match state {
+            Some(d) if d.status == AccountStatus::Closed => Err(Rejection::AccountClosed),
+            _ => Ok(()),
+        }
+    }
 
-    pub struct TransactionTestLoader {
-        data: Vec<TransactionDetails>,
-        curr_idx: usize,
+    /// Locked account rejects the action if the lock policy says so
+    fn check_not_locked(
+        &mut self,
+        client: u16,
+        transaction_type: TransactionType,
+    ) -> Result<(), Rejection> {
+        let state = self
+            .client_state_mgr
+            .get_state(client)
+            .map_err(|e| self.store_failure(e))?;
+        let rejected = match state.map(|d| d.status) {
+            Some(AccountStatus::Locked) => self.config.accounts.rejects_locked(transaction_type),
+            _ => false,
+        };
+        match rejected {
+            true => Err(Rejection::AccountLocked),
+            false => Ok(()),
+        }
     }
 
-    impl TransactionLoader for TransactionTestLoader {
-        fn next_transaction(&mut self) -> Option<TransactionDetails> {
-            match self.data.get(self.curr_idx) {
-                Some(data) => {
-                    self.curr_idx += 1;
-                    Some(data.clone())
+    /// Resolve open disputes which are overdue on the current position
+    fn auto_resolve_disputes(&mut self, timestamp: Option<i64>) -> Result<(), Rejection> {
+        while let Some(dispute) = self
+            .dispute_deadlines
+            .pop_overdue(&self.config.disputes, (self.row, timestamp))
+        {
+            let amount = self
+                .transaction_mgr
+                .get_transaction(dispute.tx, dispute.client)
+                .map_err(|e| self.store_failure(e))?
+                .filter(|d| d.dispute == DisputeState::Open)
+                .map(|d| d.disputed);
+            if let Some(amount) = amount {
+                self.keep_before_image(dispute.client, dispute.tx)
+                    .map_err(|e| self.store_failure(e))?;
+                let holder = self.holder_of(dispute.tx, dispute.client)?;
+                if self.client_state_mgr.apply_resolve(holder, amount).is_ok() {
+                    self.transaction_mgr
+                        .set_disputed(dispute.tx, 0.)
+                        .map_err(|e| self.store_failure(e))?;
+                    self.transaction_mgr
+                        .set_dispute_state(dispute.tx, DisputeState::Resolved)
+                        .map_err(|e| self.store_failure(e))?;
+                    log::info!(
+                        row = self.row,
+                        client = dispute.client,
+                        tx = dispute.tx,
+                        outcome = "auto_resolved";
+                        "dispute resolved automatically"
+                    );
                 }
-                None => None,
             }
         }
-    }
 
-    #[test]
-    pub fn test_deposit() {
-        let loader = TransactionTestLoader {
-            data: vec![],
-            curr_idx: 0,
-        };
+        Ok(())
+    }
 
-        let mut client_mgr = ClientsStatesMgr::new();
-        let mut transaction_mgr = TransactionMgr::new();
+    /// Client whose funds are held by the dispute of the transaction - destination of the transfer
+    /// Its state is kept for the rollback
+    fn holder_of(&mut self, tx: u32, client: u16) -> Result<u16, Rejection> {
+        let holder = self
+            .transaction_mgr
+            .get_destination(tx)
+            .map_err(|e| self.store_failure(e))?
+            .unwrap_or(client);
+        self.keep_before_image(holder, tx)
+            .map_err(|e| self.store_failure(e))?;
+        Ok(holder)
+    }
 
-        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader);
+    /// Dispute state of the transaction should be open - to resolve or charge back
+    fn check_open_dispute(&mut self, tx: u32) -> Result<(), Rejection> {
+        let state = self
+            .transaction_mgr
+            .get_dispute_state(tx)
+            .map_err(|e| self.store_failure(e))?;
+        match state {
+            Some(DisputeState::Open) => Ok(()),
+            Some(DisputeState::ChargedBack) => Err(Rejection::AlreadyChargedBack),
+            Some(DisputeState::Reversed) => Err(Rejection::AlreadyReversed),
+            _ => Err(Rejection::NotDisputed),
+        }
+    }
 
-        let mut action = TransactionDetails {
-            transaction_type: TransactionType::Deposit,
-            client: 2,
-            tx: 1,
-            amount: None,
+    /// Transaction seen in the other batch is rejected - otherwise it's marked as seen in the current one
+    fn check_ledger(&self, tx: u32) -> Result<(), Rejection> {
+        let ledger = match self.ledger.as_ref() {
+            Some(d) => d,
+            None => return Ok(()),
         };
 
-        assert!(
-            !mgr.apply_deposit(action.clone()),
-            "Should be failed as amount is not provided!"
-        );
-        assert!(mgr.client_state_mgr.get_states().is_empty());
-        assert!(!mgr.transaction_mgr.transaction_exist(1));
+        match ledger.seen_in(tx) {
+            // Duplicates within the batch are rejected by the transaction history
+            Some(batch) if ledger.batch() != Some(batch) => Err(Rejection::DuplicateAcrossBatch),
+            _ => Ok(()),
+        }
+    }
 
-        action.amount = Some(13.);
-        assert!(mgr.apply_deposit(action.clone()));
+    /// Mark the applied deposit/withdrawal/transfer as seen in the ledger batch
+    fn mark_in_ledger(&mut self, tx: u32) -> Result<(), Rejection> {
+        let ledger = match self.ledger.as_mut() {
+            Some(d) => d,
+            None => return Ok(()),
+        };
 
-        let clients = mgr.client_state_mgr.get_states();
-        assert_eq!(clients.len(), 1);
-        assert_eq!(clients[0].client, 2);
-        assert!(approx_eq!(f32, clients[0].available, 13., ulps = 4));
-        assert!(approx_eq!(f32, clients[0].held, 0., ulps = 4));
-        assert!(approx_eq!(f32, clients[0].total, 13., ulps = 4));
-        assert!(!clients[0].locked);
+        if let Err(e) = ledger.mark_seen(tx) {
+            return Err(self.store_failure(e));
+        }
+        if let Some(undo) = self.undo.as_mut() {
+            undo.seen.push(tx);
+        }
+        Ok(())
+    }
 
-        let transaction = mgr.transaction_mgr.get_transaction(1, 2);
-        assert!(transaction.is_some());
-        assert_eq!(
-            transaction.unwrap().transaction_type,
-            TransactionType::Deposit
-        );
-        assert!(approx_eq!(
-            f32,
-            transaction.unwrap().amount.unwrap(),
-            13.,
-            ulps = 4
-        ));
+    /// Check the deposit/withdrawal/transfer against the fraud rules - denied one is rejected
+    /// Returns the most severe verdict - allow, flag or hold, with names of the fired rules
+    fn check_rules(
+        &mut self,
+        action_details: &TransactionDetails,
+    ) -> Result<(Verdict, String), Rejection> {
+        if self.rules.is_empty() {
+            return Ok((Verdict::Allow, String::new()));
+        }
 
-        action.amount = Some(23.);
-        assert!(
-            !mgr.apply_deposit(action.clone()),
-            "Transaction ID is not unique!"
-        );
+        let state = self
+            .client_state_mgr
+            .get_state(action_details.client)
+            .map_err(|e| self.store_failure(e))?;
+        let activity = self.activity.of(action_details.client);
+        let mut verdict = Verdict::Allow;
+        let mut fired = Vec::new();
+        for rule in &self.rules {
+            let rule_verdict = rule.check(action_details, state.as_ref(), &activity, self.row);
+            if rule_verdict != Verdict::Allow {
+                fired.push(rule.name());
+                log::warn!(
+                    row = self.row,
+                    client = action_details.client,
+                    tx = action_details.tx,
+                    rule = rule.name(),
+                    verdict = rule_verdict.as_str();
+                    "fraud rule fired"
+                );
+            }
+            verdict = verdict.max(rule_verdict);
+        }
 
-        action.tx = 3;
-        assert!(
-            mgr.apply_deposit(action.clone()),
-            "Transaction ID is unique!"
-        );
+        match verdict {
+            Verdict::Deny => Err(Rejection::RuleDenied),
+            d => Ok((d, fired.join(";"))),
+        }
+    }
 
-        let clients = mgr.client_state_mgr.get_states();
-        assert_eq!(clients.len(), 1);
-        assert_eq!(clients[0].client, 2);
-        assert!(approx_eq!(f32, clients[0].available, 36., ulps = 4));
-        assert!(approx_eq!(f32, clients[0].held, 0., ulps = 4));
-        assert!(approx_eq!(f32, clients[0].total, 36., ulps = 4));
-        assert!(!clients[0].locked);
+    /// Queue the flagged deposit/withdrawal for review
+    /// Held amount should be already credited to or moved to the pending bucket
+    fn queue_review(
+        &mut self,
+        action_details: &TransactionDetails,
+        amount: f32,
+        verdict: Verdict,
+        rules: String,
+    ) {
+        let held = match verdict {
+            Verdict::Flag => false,
+            Verdict::Hold => true,
+            _ => return,
+        };
 
-        action.client = 4;
-        assert!(
-            !mgr.apply_deposit(action.clone()),
-            "Transaction ID is not unique!"
+        self.transaction_mgr.insert_review(Review {
+            tx: action_details.tx,
+            client: action_details.client,
+            transaction_type: action_details.transaction_type,
+            amount,
+            held,
+            rules,
+            row: self.row,
+        });
+        log::info!(
+            row = self.row,
+            client = action_details.client,
+            tx = action_details.tx,
+            verdict = verdict.as_str();
+            "transaction queued for review"
         );
-        assert_eq!(mgr.client_state_mgr.get_states().len(), 1);
+    }
 
-        action.tx = 5;
-        assert!(
-            mgr.apply_deposit(action.clone()),
-            "Transaction ID is unique!"
-        );
+    /// Transaction with funds held until its review can't be disputed or reversed
+    fn check_not_held(&self, tx: u32, client: u16) -> Result<(), Rejection> {
+        match self.transaction_mgr.get_review(tx, client) {
+            Some(d) if d.held => Err(Rejection::UnderReview),
+            _ => Ok(()),
+        }
+    }
 
-        let clients = mgr.client_state_mgr.get_states();
-        assert_eq!(clients.len(), 2);
+    /// Fee of the deposit/withdrawal based on the configured fee schedule
+    fn fee_for(&self, action_details: &TransactionDetails, amount: f32) -> f32 {
+        let tier = self.config.tiers.tier_of(action_details.client);
+        let fee = self
+            .config
+            .fees
+            .fee_for(action_details.transaction_type, tier, amount);
+        self.config.amounts.round(fee)
     }
 
-    #[test]
-    pub fn test_withdraw() {
-        let loader = TransactionTestLoader {
-            data: vec![],
-            curr_idx: 0,
-        };
+    /// Effective limits of the client
+    fn limits_of(&self, client_id: u16) -> Limits {
+        self.config.limits.limits_of(client_id, &self.config.tiers)
+    }
 
-        let mut client_mgr = ClientsStatesMgr::new();
-        let mut transaction_mgr = TransactionMgr::new();
+    fn apply_deposit(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Deposit {
+            return Err(Rejection::UnknownType);
+        }
 
-        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader);
+        let amount = action_details
+            .amount
+            .filter(|d| self.config.amounts.is_valid(*d))
+            .ok_or(Rejection::InvalidAmount)?;
+        if self
+            .transaction_mgr
+            .transaction_exist(action_details.tx)
+            .map_err(|e| self.store_failure(e))?
+        {
+            return Err(Rejection::DuplicateTx);
+        }
+        self.check_ledger(action_details.tx)?;
 
-        let mut action = TransactionDetails {
-            transaction_type: TransactionType::Withdrawal,
-            client: 2,
-            tx: 1,
-            amount: None,
-        };
+        // Fee is charged from the deposited amount - can't be more than it
+        let fee = self.fee_for(&action_details, amount);
+        if fee > amount {
+            return Err(Rejection::FeeExceedsAmount);
+        }
 
-        assert!(
-            !mgr.apply_withdrawal(action.clone()),
-            "Should be failed as amount is not provided!"
-        );
-        assert!(mgr.client_state_mgr.get_states().is_empty());
-        assert!(!mgr.transaction_mgr.transaction_exist(1));
+        let total = self
+            .client_state_mgr
+            .get_state(action_details.client)
+            .map_err(|e| self.store_failure(e))?
+            .map_or(0_f32, |d| d.total);
+        check_balance(total + amount - fee, &self.limits_of(action_details.client))?;
+        let (verdict, rules) = self.check_rules(&action_details)?;
 
-        action.amount = Some(13.);
-        assert!(
-            !mgr.apply_withdrawal(action.clone()),
-            "Total can't be negative: 0-13."
+        // Deposited amount after the fee is held
+        self.client_state_mgr.apply_charged_deposit(
+            action_details.client,
+            amount,
+            fee,
+            verdict == Verdict::Hold,
+        )?;
+        self.queue_review(&action_details, amount - fee, verdict, rules);
+
+        let tx = action_details.tx;
+        if !self
+            .transaction_mgr
+            .insert_new_transaction(action_details, self.row)
+            .map_err(|e| self.store_failure(e))?
+        {
+            return Err(Rejection::DuplicateTx);
+        }
+        if fee > 0_f32 {
+            self.transaction_mgr
+                .insert_fee(tx, fee)
+                .map_err(|e| self.store_failure(e))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_withdrawal(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Withdrawal {
+            return Err(Rejection::UnknownType);
+        } else if self
+            .transaction_mgr
+            .transaction_exist(action_details.tx)
+            .map_err(|e| self.store_failure(e))?
+        {
+            return Err(Rejection::DuplicateTx);
+        }
+
+        let amount = action_details
+            .amount
+            .filter(|d| self.config.amounts.is_valid(*d))
+            .ok_or(Rejection::InvalidAmount)?;
+        self.check_ledger(action_details.tx)?;
+
+        let limits = self.limits_of(action_details.client);
+        self.withdrawal_windows.check(
+            action_details.client,
+            amount,
+            self.row,
+            action_details.timestamp,
+            &limits,
+        )?;
+
+        // Both amount and fee should be covered by available funds
+        let fee = self.fee_for(&action_details, amount);
+        let (verdict, rules) = self.check_rules(&action_details)?;
+
+        // Held withdrawal is taken out of the account only when it's approved
+        self.client_state_mgr.apply_charged_withdrawal(
+            action_details.client,
+            amount,
+            fee,
+            verdict == Verdict::Hold,
+        )?;
+
+        self.withdrawal_windows.record(
+            action_details.client,
+            amount,
+            self.row,
+            action_details.timestamp,
+            &limits,
         );
+        self.queue_review(&action_details, amount, verdict, rules);
 
-        let mut deposit = action.clone();
-        deposit.transaction_type = TransactionType::Deposit;
-        deposit.amount = Some(9.5);
-        assert!(mgr.apply_deposit(deposit.clone())); // Amount == 9.5
+        let tx = action_details.tx;
+        if !self
+            .transaction_mgr
+            .insert_new_transaction(action_details, self.row)
+            .map_err(|e| self.store_failure(e))?
+        {
+            return Err(Rejection::DuplicateTx);
+        }
+        if fee > 0_f32 {
+            self.transaction_mgr
+                .insert_fee(tx, fee)
+                .map_err(|e| self.store_failure(e))?;
+        }
 
-        assert!(
-            !mgr.apply_withdrawal(action.clone()),
-            "Tx amount more than available!"
+        Ok(())
+    }
+
+    /// Move funds to the destination client - both clients should exist
+    /// Fee is charged from the source client
+    fn apply_transfer(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Transfer {
+            return Err(Rejection::UnknownType);
+        } else if self
+            .transaction_mgr
+            .transaction_exist(action_details.tx)
+            .map_err(|e| self.store_failure(e))?
+        {
+            return Err(Rejection::DuplicateTx);
+        }
+
+        let amount = action_details
+            .amount
+            .filter(|d| self.config.amounts.is_valid(*d))
+            .ok_or(Rejection::InvalidAmount)?;
+        let destination = action_details
+            .destination
+            .filter(|d| *d != action_details.client)
+            .ok_or(Rejection::InvalidDestination)?;
+        self.check_ledger(action_details.tx)?;
+        self.check_not_closed(destination)?;
+        self.check_not_locked(destination, TransactionType::Transfer)?;
+        self.keep_before_image(destination, action_details.tx)
+            .map_err(|e| self.store_failure(e))?;
+
+        // Both amount and fee should be covered by available funds of the source
+        let fee = self.fee_for(&action_details, amount);
+        let available = self
+            .client_state_mgr
+            .get_state(action_details.client)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::UnknownClient)?
+            .available;
+        if available < amount + fee {
+            return Err(Rejection::InsufficientFunds);
+        }
+
+        let total = self
+            .client_state_mgr
+            .get_state(destination)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::UnknownClient)?
+            .total;
+        check_balance(total + amount, &self.limits_of(destination))?;
+        let (verdict, rules) = self.check_rules(&action_details)?;
+
+        // Held transfer is credited to pending funds of the destination
+        self.client_state_mgr.apply_transfer(
+            action_details.client,
+            destination,
+            amount,
+            fee,
+            verdict == Verdict::Hold,
+        )?;
+        self.queue_review(&action_details, amount, verdict, rules);
+
+        let tx = action_details.tx;
+        if !self
+            .transaction_mgr
+            .insert_new_transaction(action_details, self.row)
+            .map_err(|e| self.store_failure(e))?
+        {
+            return Err(Rejection::DuplicateTx);
+        }
+        if fee > 0_f32 {
+            self.transaction_mgr
+                .insert_fee(tx, fee)
+                .map_err(|e| self.store_failure(e))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_dispute(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Dispute {
+            return Err(Rejection::UnknownType);
+        } else if action_details
+            .amount
+            .filter(|d| !(d.is_finite() && *d > 0.))
+            .is_some()
+        {
+            return Err(Rejection::InvalidAmount);
+        }
+
+        // If transaction is not found - ignore!
+        let transaction = self
+            .transaction_mgr
+            .get_transaction(action_details.tx, action_details.client)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::TxNotFound)?;
+        match transaction.dispute {
+            // Open dispute can be extended only by the partial one
+            DisputeState::Open if action_details.amount.is_none() => {
+                return Err(Rejection::AlreadyDisputed)
+            }
+            DisputeState::ChargedBack => return Err(Rejection::AlreadyChargedBack),
+            DisputeState::Reversed => return Err(Rejection::AlreadyReversed),
+            _ => {}
+        }
+        self.check_not_held(action_details.tx, action_details.client)?;
+
+        // Full remaining amount is disputed if the amount is not provided
+        let disputed = transaction.disputed;
+        let remaining = transaction.amount - disputed;
+        let amount = action_details.amount.unwrap_or(remaining);
+        if amount > remaining {
+            return Err(Rejection::ExceedsUndisputed);
+        }
+
+        if self.config.disputes.is_expired(
+            (transaction.row, transaction.timestamp()),
+            (self.row, action_details.timestamp),
+        ) {
+            return Err(Rejection::DisputeExpired);
+        }
+
+        let holder = self.holder_of(action_details.tx, action_details.client)?;
+        match self.config.disputes.insufficient_funds {
+            InsufficientFundsPolicy::Reject => {
+                self.client_state_mgr.apply_dispute(holder, amount)?
+            }
+            InsufficientFundsPolicy::Hold => self
+                .client_state_mgr
+                .apply_overdrawn_dispute(holder, amount)?,
+        }
+        self.transaction_mgr
+            .set_disputed(action_details.tx, disputed + amount)
+            .map_err(|e| self.store_failure(e))?;
+
+        // Extended dispute keeps the deadline of the opening one
+        if transaction.dispute == DisputeState::Open {
+            return Ok(());
+        }
+        self.transaction_mgr
+            .set_dispute_state(action_details.tx, DisputeState::Open)
+            .map_err(|e| self.store_failure(e))?;
+        self.dispute_deadlines.push(OpenDispute {
+            tx: action_details.tx,
+            client: action_details.client,
+            row: self.row,
+            timestamp: action_details.timestamp,
+        });
+
+        Ok(())
+    }
+
+    fn apply_resolve(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Resolve {
+            return Err(Rejection::UnknownType);
+        } else if action_details.amount.is_some() {
+            return Err(Rejection::InvalidAmount);
+        }
+
+        // If transaction is not found - ignore!
+        let transaction = self
+            .transaction_mgr
+            .get_transaction(action_details.tx, action_details.client)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::TxNotFound)?;
+        self.check_open_dispute(action_details.tx)?;
+
+        // Only the disputed portion is released
+        let amount = transaction.disputed;
+        let holder = self.holder_of(action_details.tx, action_details.client)?;
+        self.client_state_mgr.apply_resolve(holder, amount)?;
+        self.transaction_mgr
+            .set_disputed(action_details.tx, 0.)
+            .map_err(|e| self.store_failure(e))?;
+        self.transaction_mgr
+            .set_dispute_state(action_details.tx, DisputeState::Resolved)
+            .map_err(|e| self.store_failure(e))?;
+        self.dispute_deadlines.close(action_details.tx);
+
+        Ok(())
+    }
+
+    fn apply_chargeback(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Chargeback {
+            return Err(Rejection::UnknownType);
+        } else if action_details.amount.is_some() {
+            return Err(Rejection::InvalidAmount);
+        }
+
+        // If transaction is not found - ignore!
+        let transaction = self
+            .transaction_mgr
+            .get_transaction(action_details.tx, action_details.client)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::TxNotFound)?;
+        self.check_open_dispute(action_details.tx)?;
+
+        // Only the disputed portion is charged back
+        let amount = transaction.disputed;
+        let holder = self.holder_of(action_details.tx, action_details.client)?;
+        match holder == action_details.client {
+            true => self
+                .client_state_mgr
+                .apply_chargeback(action_details.client, amount)?,
+            false => self.client_state_mgr.apply_transfer_chargeback(
+                action_details.client,
+                holder,
+                amount,
+            )?,
+        }
+        self.transaction_mgr
+            .set_disputed(action_details.tx, 0.)
+            .map_err(|e| self.store_failure(e))?;
+        self.transaction_mgr
+            .set_dispute_state(action_details.tx, DisputeState::ChargedBack)
+            .map_err(|e| self.store_failure(e))?;
+        self.dispute_deadlines.close(action_details.tx);
+
+        // Partially charged back transaction keeps its fee
+        if amount < transaction.amount {
+            return Ok(());
+        }
+
+        // Transaction is reversed - the fee charged for it should be refunded as well
+        if transaction.fee > 0.
+            && !transaction.fee_refunded
+            && self
+                .client_state_mgr
+                .refund_fee(action_details.client, transaction.fee)
+                .is_ok()
+        {
+            self.transaction_mgr
+                .mark_fee_refunded(action_details.tx)
+                .map_err(|e| self.store_failure(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Undo not disputed (or resolved) deposit/withdrawal - the account isn't locked
+    fn apply_reversal(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Reversal {
+            return Err(Rejection::UnknownType);
+        } else if action_details.amount.is_some() {
+            return Err(Rejection::InvalidAmount);
+        }
+
+        let transaction = self
+            .transaction_mgr
+            .get_transaction(action_details.tx, action_details.client)
+            .map_err(|e| self.store_failure(e))?
+            .ok_or(Rejection::TxNotFound)?;
+        match transaction.dispute {
+            DisputeState::Open => return Err(Rejection::AlreadyDisputed),
+            DisputeState::ChargedBack => return Err(Rejection::AlreadyChargedBack),
+            DisputeState::Reversed => return Err(Rejection::AlreadyReversed),
+            _ => {}
+        }
+        self.check_not_held(action_details.tx, action_details.client)?;
+
+        let fee = match transaction.fee_refunded {
+            true => 0.,
+            false => transaction.fee,
+        };
+        self.client_state_mgr.apply_reversal(
+            action_details.client,
+            transaction.transaction_type,
+            transaction.amount,
+            fee,
+        )?;
+        if fee > 0. {
+            self.transaction_mgr
+                .mark_fee_refunded(action_details.tx)
+                .map_err(|e| self.store_failure(e))?;
+        }
+
+        self.transaction_mgr
+            .set_dispute_state(action_details.tx, DisputeState::Reversed)
+            .map_err(|e| self.store_failure(e))?;
+
+        Ok(())
+    }
+
+    /// Change status of the account - operator and reason are mandatory
+    /// tx of the admin action is only recorded, it's not checked against transactions
+    fn apply_admin(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        let status = match action_details.transaction_type {
+            TransactionType::Unlock => AccountStatus::Active,
+            TransactionType::Lock => AccountStatus::Locked,
+            TransactionType::Close => AccountStatus::Closed,
+            _ => return Err(Rejection::UnknownType),
+        };
+        if action_details.amount.is_some() {
+            return Err(Rejection::InvalidAmount);
+        }
+        check_operator(&action_details)?;
+
+        self.client_state_mgr
+            .set_status(action_details.client, status)
+    }
+
+    /// Review decision on the flagged transaction - held funds are released or taken out
+    /// Declined transaction is undone with its fee and marked as reversed
+    /// Declined withdrawal doesn't count in the withdrawal window anymore
+    fn apply_review(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        let approved = match action_details.transaction_type {
+            TransactionType::Approve => true,
+            TransactionType::Decline => false,
+            _ => return Err(Rejection::UnknownType),
+        };
+        if action_details.amount.is_some() {
+            return Err(Rejection::InvalidAmount);
+        }
+        check_operator(&action_details)?;
+
+        let review = self
+            .transaction_mgr
+            .get_review(action_details.tx, action_details.client)
+            .cloned()
+            .ok_or(Rejection::NotUnderReview)?;
+        match (approved, review.transaction_type) {
+            (true, _) if !review.held => {}
+            (true, TransactionType::Deposit) => self
+                .client_state_mgr
+                .apply_release(review.client, review.amount)?,
+            (true, TransactionType::Transfer) => {
+                let destination = self.holder_of(review.tx, review.client)?;
+                self.client_state_mgr
+                    .apply_release(destination, review.amount)?
+            }
+            (true, _) => self
+                .client_state_mgr
+                .apply_settle(review.client, review.amount)?,
+            (false, transaction_type) => {
+                let transaction = self
+                    .transaction_mgr
+                    .get_transaction(review.tx, review.client)
+                    .map_err(|e| self.store_failure(e))?
+                    .ok_or(Rejection::TxNotFound)?;
+                let fee = match transaction.fee_refunded {
+                    true => 0.,
+                    false => transaction.fee,
+                };
+                match transaction_type {
+                    TransactionType::Transfer => {
+                        let destination = self.holder_of(review.tx, review.client)?;
+                        self.client_state_mgr.apply_transfer_decline(
+                            review.client,
+                            destination,
+                            transaction.amount,
+                            fee,
+                            review.held,
+                        )?
+                    }
+                    _ => self.client_state_mgr.apply_decline(
+                        review.client,
+                        transaction_type,
+                        transaction.amount,
+                        fee,
+                        review.held,
+                    )?,
+                }
+                if fee > 0. {
+                    self.transaction_mgr
+                        .mark_fee_refunded(review.tx)
+                        .map_err(|e| self.store_failure(e))?;
+                }
+                if transaction_type == TransactionType::Withdrawal {
+                    self.withdrawal_windows.remove(review.client, review.row);
+                }
+                self.transaction_mgr
+                    .set_dispute_state(review.tx, DisputeState::Reversed)
+                    .map_err(|e| self.store_failure(e))?;
+            }
+        }
+        self.transaction_mgr.remove_review(review.tx);
+
+        Ok(())
+    }
+
+    /// Accrual marker - client and tx of the marker are not used
+    fn apply_accrue(&mut self, action_details: TransactionDetails) -> Result<(), Rejection> {
+        if action_details.transaction_type != TransactionType::Accrue {
+            return Err(Rejection::UnknownType);
+        } else if action_details.amount.is_some() {
+            return Err(Rejection::InvalidAmount);
+        }
+
+        self.accrue_interest().map(|_| ())
+    }
+
+    /// Accrue interest on available funds of the clients based on the configured rates
+    /// Locked and closed accounts are skipped
+    /// Each accrual is posted as interest transaction with id from the reserved range
+    /// Returns number of credited clients
+    pub fn accrue_interest(&mut self) -> Result<u32, Rejection> {
+        let mut states = self
+            .client_state_mgr
+            .get_states()
+            .map_err(|e| self.store_failure(e))?;
+        states.sort_by_key(|d| d.client);
+
+        let mut credited = 0;
+        for state in states.into_iter().filter(|d| !d.is_locked()) {
+            let rate = self
+                .config
+                .interest
+                .rate_of(state.client, &self.config.tiers);
+            let amount = self.config.amounts.round(state.available * rate / 100.);
+            if amount <= 0. {
+                continue;
+            }
+
+            let details = TransactionDetails {
+                transaction_type: TransactionType::Interest,
+                client: state.client,
+                tx: self.next_accrual_tx()?,
+                amount: Some(amount),
+                ..Default::default()
+            };
+            self.keep_before_image(details.client, details.tx)
+                .map_err(|e| self.store_failure(e))?;
+            self.client_state_mgr
+                .apply_deposit(details.client, amount)?;
+            if !self
+                .transaction_mgr
+                .insert_new_transaction(details.clone(), self.row)
+                .map_err(|e| self.store_failure(e))?
+            {
+                return Err(Rejection::DuplicateTx);
+            }
+
+            self.record_generated(details)?;
+            credited += 1;
+        }
+
+        Ok(credited)
+    }
+
+    /// Next free tx id of the reserved range
+    fn next_accrual_tx(&mut self) -> Result<u32, Rejection> {
+        let mut tx = self
+            .next_accrual_tx
+            .unwrap_or(self.config.interest.first_tx);
+        while self
+            .transaction_mgr
+            .transaction_exist(tx)
+            .map_err(|e| self.store_failure(e))?
+        {
+            // Reserved range is exhausted
+            tx = tx.checked_add(1).ok_or(Rejection::ReservedTx)?;
+        }
+
+        self.next_accrual_tx = Some(tx.saturating_add(1));
+        Ok(tx)
+    }
+
+    /// Record the generated transaction as the applied one - in history, audit log and the batch
+    fn record_generated(&mut self, details: TransactionDetails) -> Result<(), Rejection> {
+        let result = Ok(());
+        let transaction_type: &str = details.transaction_type.into();
+        log::info!(
+            row = self.row,
+            client = details.client,
+            tx = details.tx,
+            transaction_type = transaction_type,
+            outcome = "applied",
+            delta_available:% = details.amount.unwrap_or_default();
+            "transaction generated"
         );
-        action.amount = Some(7.);
+
+        if let Some(history) = self.history.as_mut() {
+            history.record(&details, self.row, &result);
+        }
+        if let Err(e) = self.append_audit(self.row, &details, &result) {
+            return Err(self.store_failure(e));
+        }
+        if let Some(undo) = self.undo.as_mut() {
+            undo.applied.push((self.row, details));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::audit::AuditLog;
+    use crate::config::EngineConfig;
+    use crate::csv_processor::TransactionLoader;
+    use crate::disputes::DisputeState;
+    use crate::metrics::MetricsRegistry;
+    use crate::rejection::Rejection;
+    use crate::rules::{Activity, FraudRule, Verdict};
+    use crate::{
+        AccountStatus, ClientState, ClientsStatesMgr, TransactionDetails, TransactionMgr,
+        TransactionType, TransactionsProcessor,
+    };
+    use float_cmp::approx_eq;
+    use std::cell::RefCell;
+
+    thread_local! {
+        /// Log records of the test thread - level, message and outcome
+        static LOG_RECORDS: RefCell<Vec<(log::Level, String, String)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Logger keeping the records of each test thread separately
+    struct TestLogger;
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let outcome = record
+                .key_values()
+                .get(log::kv::Key::from_str("outcome"))
+                .map(|d| d.to_string())
+                .unwrap_or_default();
+            LOG_RECORDS.with(|d| {
+                d.borrow_mut()
+                    .push((record.level(), record.args().to_string(), outcome))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static TEST_LOGGER: TestLogger = TestLogger;
+
+    /// Capture log records of the test thread
+    fn capture_logs() {
+        // Logger may be already set by other test
+        let _ = log::set_logger(&TEST_LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+        LOG_RECORDS.with(|d| d.borrow_mut().clear());
+    }
+
+    pub struct TransactionTestLoader {
+        data: Vec<TransactionDetails>,
+        curr_idx: usize,
+    }
+
+    impl TransactionLoader for TransactionTestLoader {
+        fn next_transaction(&mut self) -> Option<TransactionDetails> {
+            match self.data.get(self.curr_idx) {
+                Some(data) => {
+                    self.curr_idx += 1;
+                    Some(data.clone())
+                }
+                None => None,
+            }
+        }
+    }
+
+    /// Action of the test - optional fields are left empty
+    pub(crate) fn tx(
+        transaction_type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<f32>,
+    ) -> TransactionDetails {
+        TransactionDetails {
+            transaction_type,
+            client,
+            tx,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    /// Processor of the test on the new in-memory managers
+    /// Managers are leaked to outlive the processor - it's fine for the test run
+    fn processor(config: EngineConfig) -> TransactionsProcessor<'static, TransactionTestLoader> {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+        TransactionsProcessor::new(
+            Box::leak(Box::new(ClientsStatesMgr::new())),
+            Box::leak(Box::new(TransactionMgr::new())),
+            loader,
+        )
+        .with_config(config)
+    }
+
+    #[test]
+    pub fn test_deposit() {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+
+        let mut client_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+
+        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader);
+
+        let mut action = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: None,
+            ..Default::default()
+        };
+
         assert!(
-            !mgr.apply_withdrawal(action.clone()),
-            "Tx id is not unique!"
+            mgr.apply_deposit(action.clone()).is_err(),
+            "Should be failed as amount is not provided!"
         );
-        action.tx = 4;
-        assert!(mgr.apply_withdrawal(action.clone()));
+        assert!(mgr.client_state_mgr.get_states().unwrap().is_empty());
+        assert!(!mgr.transaction_mgr.transaction_exist(1).unwrap());
+
+        action.amount = Some(13.);
+        assert!(mgr.apply_deposit(action.clone()).is_ok());
 
-        let clients = mgr.client_state_mgr.get_states();
+        let clients = mgr.client_state_mgr.get_states().unwrap();
         assert_eq!(clients.len(), 1);
         assert_eq!(clients[0].client, 2);
-        assert!(approx_eq!(f32, clients[0].available, 2.5, ulps = 4));
+        assert!(approx_eq!(f32, clients[0].available, 13., ulps = 4));
         assert!(approx_eq!(f32, clients[0].held, 0., ulps = 4));
-        assert!(approx_eq!(f32, clients[0].total, 2.5, ulps = 4));
-        assert!(!clients[0].locked);
-        assert!(!mgr.apply_withdrawal(deposit), "Type mismatch");
+        assert!(approx_eq!(f32, clients[0].total, 13., ulps = 4));
+        assert!(!clients[0].is_locked());
 
-        action.amount = Some(1.);
+        let transaction = mgr.transaction_mgr.get_transaction(1, 2).unwrap();
+        assert!(transaction.is_some());
+        assert_eq!(
+            transaction.unwrap().transaction_type,
+            TransactionType::Deposit
+        );
+        assert!(approx_eq!(f32, transaction.unwrap().amount, 13., ulps = 4));
+
+        action.amount = Some(23.);
         assert!(
-            !mgr.apply_withdrawal(action.clone()),
-            "Tx id is not unique."
+            mgr.apply_deposit(action.clone()).is_err(),
+            "Transaction ID is not unique!"
         );
 
         action.tx = 3;
-        assert!(mgr.apply_withdrawal(action.clone()));
-        let clients = mgr.client_state_mgr.get_states();
+        assert!(
+            mgr.apply_deposit(action.clone()).is_ok(),
+            "Transaction ID is unique!"
+        );
+
+        let clients = mgr.client_state_mgr.get_states().unwrap();
         assert_eq!(clients.len(), 1);
         assert_eq!(clients[0].client, 2);
-        assert!(approx_eq!(f32, clients[0].available, 1.5, ulps = 4));
+        assert!(approx_eq!(f32, clients[0].available, 36., ulps = 4));
         assert!(approx_eq!(f32, clients[0].held, 0., ulps = 4));
-        assert!(approx_eq!(f32, clients[0].total, 1.5, ulps = 4));
-        assert!(!clients[0].locked);
+        assert!(approx_eq!(f32, clients[0].total, 36., ulps = 4));
+        assert!(!clients[0].is_locked());
 
-        action.amount = Some(3.);
-        action.tx = 4;
+        action.client = 4;
         assert!(
-            !mgr.apply_withdrawal(action.clone()),
-            "Tx amount more than available!"
+            mgr.apply_deposit(action.clone()).is_err(),
+            "Transaction ID is not unique!"
+        );
+        assert_eq!(mgr.client_state_mgr.get_states().unwrap().len(), 1);
+
+        action.tx = 5;
+        assert!(
+            mgr.apply_deposit(action.clone()).is_ok(),
+            "Transaction ID is unique!"
         );
+
+        let clients = mgr.client_state_mgr.get_states().unwrap();
+        assert_eq!(clients.len(), 2);
+    }
+
+    #[test]
+    pub fn test_withdraw() {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+
+        let mut client_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+
+        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader);
+
+        let mut action = TransactionDetails {
+            transaction_type: TransactionType::Withdrawal,
+            client: 2,
+            tx: 1,
+            amount: None,
+            ..Default::default()
+        };
+
+        assert!(
+            mgr.apply_withdrawal(action.clone()).is_err(),
+            "Should be failed as amount is not provided!"
+        );
+        assert!(mgr.client_state_mgr.get_states().unwrap().is_empty());
+        assert!(!mgr.transaction_mgr.transaction_exist(1).unwrap());
+
+        action.amount = Some(13.);
+        assert!(
+            mgr.apply_withdrawal(action.clone()).is_err(),
+            "Total can't be negative: 0-13."
+        );
+
+        let mut deposit = action.clone();
+        deposit.transaction_type = TransactionType::Deposit;
+        deposit.amount = Some(9.5);
+        assert!(mgr.apply_deposit(deposit.clone()).is_ok()); // Amount == 9.5
+
+        assert!(
+            mgr.apply_withdrawal(action.clone()).is_err(),
+            "Tx amount more than available!"
+        );
+        action.amount = Some(7.);
+        assert!(
+            mgr.apply_withdrawal(action.clone()).is_err(),
+            "Tx id is not unique!"
+        );
+        action.tx = 4;
+        assert!(mgr.apply_withdrawal(action.clone()).is_ok());
+
+        let clients = mgr.client_state_mgr.get_states().unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client, 2);
+        assert!(approx_eq!(f32, clients[0].available, 2.5, ulps = 4));
+        assert!(approx_eq!(f32, clients[0].held, 0., ulps = 4));
+        assert!(approx_eq!(f32, clients[0].total, 2.5, ulps = 4));
+        assert!(!clients[0].is_locked());
+        assert!(mgr.apply_withdrawal(deposit).is_err(), "Type mismatch");
+
+        action.amount = Some(1.);
+        assert!(
+            mgr.apply_withdrawal(action.clone()).is_err(),
+            "Tx id is not unique."
+        );
+
+        action.tx = 3;
+        assert!(mgr.apply_withdrawal(action.clone()).is_ok());
+        let clients = mgr.client_state_mgr.get_states().unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client, 2);
+        assert!(approx_eq!(f32, clients[0].available, 1.5, ulps = 4));
+        assert!(approx_eq!(f32, clients[0].held, 0., ulps = 4));
+        assert!(approx_eq!(f32, clients[0].total, 1.5, ulps = 4));
+        assert!(!clients[0].is_locked());
+
+        action.amount = Some(3.);
+        action.tx = 4;
+        assert!(
+            mgr.apply_withdrawal(action.clone()).is_err(),
+            "Tx amount more than available!"
+        );
+    }
+
+    #[test]
+    pub fn test_fees() {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+
+        let config = EngineConfig::from_toml(
+            r#"
+            [tiers]
+            gold = [3]
+
+            [[fees]]
+            transaction_type = "deposit"
+            flat = 1.0
+
+            [[fees]]
+            transaction_type = "withdrawal"
+            percent = 10.0
+
+            [[fees]]
+            transaction_type = "withdrawal"
+            tier = "gold"
+            "#,
+        )
+        .unwrap();
+
+        let mut client_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+
+        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader)
+            .with_config(config);
+
+        let mut action = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(0.5),
+            ..Default::default()
+        };
+        assert!(
+            mgr.apply_deposit(action.clone()).is_err(),
+            "Fee is more than the deposit!"
+        );
+        assert!(mgr.client_state_mgr.get_states().unwrap().is_empty());
+
+        action.amount = Some(21.);
+        assert!(mgr.apply_deposit(action.clone()).is_ok());
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 20., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 20., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1., ulps = 4));
+        assert!(approx_eq!(
+            f32,
+            mgr.transaction_mgr.get_fee(1, 2).unwrap().unwrap().amount,
+            1.,
+            ulps = 4
+        ));
+
+        let mut withdrawal = action.clone();
+        withdrawal.transaction_type = TransactionType::Withdrawal;
+        withdrawal.tx = 2;
+        withdrawal.amount = Some(19.);
+        assert!(
+            mgr.apply_withdrawal(withdrawal.clone()).is_err(),
+            "Amount with fee is more than available!"
+        );
+        withdrawal.amount = Some(10.);
+        assert!(mgr.apply_withdrawal(withdrawal.clone()).is_ok());
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 9., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 9., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 2., ulps = 4));
+
+        // Gold tier has no withdrawal fee, but still pays for the deposit
+        action.client = 3;
+        action.tx = 3;
+        action.amount = Some(10.);
+        assert!(mgr.apply_deposit(action.clone()).is_ok());
+        withdrawal.client = 3;
+        withdrawal.tx = 4;
+        withdrawal.amount = Some(9.);
+        assert!(mgr.apply_withdrawal(withdrawal).is_ok());
+        let c = mgr.client_state_mgr.get_state(3).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 0., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1., ulps = 4));
+        assert!(mgr.transaction_mgr.get_fee(4, 3).unwrap().is_none());
+
+        // Chargeback of the deposit refunds the fee
+        let mut dispute = action.clone();
+        dispute.client = 2;
+        dispute.tx = 1;
+        dispute.amount = None;
+        dispute.transaction_type = TransactionType::Deposit;
+        action.client = 2;
+        action.tx = 5;
+        action.amount = Some(13.);
+        assert!(mgr.apply_deposit(action).is_ok());
+        dispute.transaction_type = TransactionType::Dispute;
+        assert!(mgr.apply_dispute(dispute.clone()).is_ok());
+        dispute.transaction_type = TransactionType::Chargeback;
+        assert!(mgr.apply_chargeback(dispute).is_ok());
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(c.is_locked());
+        assert!(approx_eq!(f32, c.available, 1., ulps = 4));
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 1., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 2., ulps = 4));
+        assert!(mgr.transaction_mgr.get_fee(1, 2).unwrap().unwrap().refunded);
+    }
+
+    #[test]
+    pub fn test_limits() {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+
+        let config = EngineConfig::from_toml(
+            r#"
+            [limits.default]
+            max_withdrawal = 10.0
+            max_window_withdrawal = 15.0
+            window_rows = 4
+            max_balance = 100.0
+
+            [limits.clients.3]
+            max_balance = 1000.0
+            "#,
+        )
+        .unwrap();
+
+        let mut client_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+
+        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader)
+            .with_config(config);
+
+        let mut action = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(101.),
+            ..Default::default()
+        };
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::MaxBalanceExceeded)
+        );
+        action.amount = Some(100.);
+        assert!(mgr.process_transaction(action.clone()).is_ok());
+
+        action.client = 3;
+        action.tx = 2;
+        action.amount = Some(500.);
+        assert!(
+            mgr.process_transaction(action.clone()).is_ok(),
+            "Client specific limit"
+        );
+
+        action.transaction_type = TransactionType::Withdrawal;
+        action.client = 2;
+        action.tx = 3;
+        action.amount = Some(11.);
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::MaxWithdrawalExceeded)
+        );
+        action.amount = Some(10.);
+        assert!(mgr.process_transaction(action.clone()).is_ok()); // Row 5
+
+        action.tx = 4;
+        action.amount = Some(6.);
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::WindowWithdrawalExceeded)
+        );
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 90., ulps = 4));
+
+        action.amount = Some(5.);
+        assert!(mgr.process_transaction(action.clone()).is_ok()); // Row 7
+
+        action.tx = 5;
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::WindowWithdrawalExceeded)
+        );
+        assert!(
+            mgr.process_transaction(action.clone()).is_ok(),
+            "Row 5 is out of the window"
+        );
+
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 80., ulps = 4));
+    }
+
+    #[test]
+    pub fn test_ordering() {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+
+        let config = EngineConfig::from_toml("[ordering]\nmode = \"reject\"").unwrap();
+
+        let mut client_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+
+        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader)
+            .with_config(config)
+            .with_as_of(Some(3000));
+
+        let mut action = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(10.),
+            timestamp: Some(2000),
+            ..Default::default()
+        };
+        assert!(mgr.process_transaction(action.clone()).is_ok());
+
+        action.tx = 2;
+        action.timestamp = Some(1999);
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::OutOfOrder)
+        );
+        action.timestamp = None;
+        assert!(
+            mgr.process_transaction(action.clone()).is_ok(),
+            "No timestamp - not checked"
+        );
+
+        action.tx = 3;
+        action.timestamp = Some(3001);
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::AfterAsOf)
+        );
+        action.timestamp = Some(3000);
+        action.transaction_type = TransactionType::Withdrawal;
+        action.amount = Some(100.);
+        assert_eq!(
+            mgr.process_transaction(action.clone()),
+            Err(Rejection::InsufficientFunds)
+        );
+        action.tx = 4;
+        action.transaction_type = TransactionType::Deposit;
+        action.amount = Some(10.);
+        action.timestamp = Some(2500);
+        assert!(
+            mgr.process_transaction(action.clone()).is_ok(),
+            "Rejected action doesn't move the point in time"
+        );
+        action.tx = 3;
+        action.timestamp = Some(3000);
+        assert!(mgr.process_transaction(action.clone()).is_ok());
+        assert_eq!(
+            mgr.transaction_mgr
+                .get_transaction(3, 2)
+                .unwrap()
+                .unwrap()
+                .timestamp(),
+            Some(3000),
+            "Timestamp is kept in the history"
+        );
+
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.total, 40., ulps = 4));
+    }
+
+    #[test]
+    pub fn test_disputes() {
+        let loader = TransactionTestLoader {
+            data: vec![],
+            curr_idx: 0,
+        };
+
+        let config = EngineConfig::from_toml(
+            r#"
+            [disputes]
+            window_rows = 2
+            auto_resolve_rows = 2
+            "#,
+        )
+        .unwrap();
+
+        let mut client_mgr = ClientsStatesMgr::new();
+        let mut transaction_mgr = TransactionMgr::new();
+
+        let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader)
+            .with_config(config);
+
+        let mut action = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(10.),
+            ..Default::default()
+        };
+        assert!(mgr.process_transaction(action.clone()).is_ok()); // Row 1
+        action.tx = 2;
+        assert!(mgr.process_transaction(action.clone()).is_ok()); // Row 2
+
+        let mut dispute = action.clone();
+        dispute.amount = None;
+        dispute.transaction_type = TransactionType::Dispute;
+        assert!(mgr.process_transaction(dispute.clone()).is_ok()); // Row 3
+
+        dispute.tx = 1;
+        assert_eq!(
+            mgr.process_transaction(dispute.clone()),
+            Err(Rejection::DisputeExpired),
+            "Tx 1 is older than 2 rows"
+        ); // Row 4
+
+        dispute.tx = 2;
+        assert_eq!(
+            mgr.process_transaction(dispute.clone()),
+            Err(Rejection::AlreadyDisputed)
+        ); // Row 5
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.held, 10., ulps = 4));
+
+        action.tx = 3;
+        assert!(mgr.process_transaction(action.clone()).is_ok()); // Row 6 - auto-resolve
+        assert_eq!(
+            mgr.transaction_mgr.get_dispute_state(2).unwrap(),
+            Some(DisputeState::Resolved)
+        );
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+        assert!(approx_eq!(f32, c.available, 30., ulps = 4));
+
+        dispute.transaction_type = TransactionType::Resolve;
+        assert_eq!(
+            mgr.process_transaction(dispute.clone()),
+            Err(Rejection::NotDisputed),
+            "Already resolved automatically"
+        ); // Row 7
+
+        dispute.transaction_type = TransactionType::Dispute;
+        dispute.tx = 3;
+        assert!(mgr.process_transaction(dispute.clone()).is_ok()); // Row 8
+        dispute.transaction_type = TransactionType::Chargeback;
+        assert!(mgr.process_transaction(dispute.clone()).is_ok()); // Row 9
+        assert_eq!(
+            mgr.process_transaction(dispute.clone()),
+            Err(Rejection::AlreadyChargedBack)
+        ); // Row 10
+        dispute.transaction_type = TransactionType::Dispute;
+        assert_eq!(
+            mgr.process_transaction(dispute.clone()),
+            Err(Rejection::AlreadyChargedBack)
+        ); // Row 11
+
+        // Charged back dispute is not resolved automatically
+        action.tx = 4;
+        assert!(mgr.process_transaction(action.clone()).is_ok());
+        let c = mgr.client_state_mgr.get_state(2).unwrap().unwrap();
+        assert!(c.is_locked());
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 30., ulps = 4));
+    }
+
+    #[test]
+    pub fn test_auto_resolve_at_end() {
+        let config = EngineConfig::from_toml("[disputes]\nauto_resolve_millis = 1000").unwrap();
+        let deposit = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(10.),
+            timestamp: Some(1000),
+            ..Default::default()
+        };
+        let mut dispute = deposit.clone();
+        dispute.transaction_type = TransactionType::Dispute;
+        dispute.amount = None;
+        dispute.timestamp = Some(2000);
+
+        for (as_of, expected) in [
+            (None, DisputeState::Open),
+            (Some(3000), DisputeState::Open),
+            (Some(3001), DisputeState::Resolved),
+        ] {
+            let loader = TransactionTestLoader {
+                data: vec![deposit.clone(), dispute.clone()],
+                curr_idx: 0,
+            };
+            let mut client_mgr = ClientsStatesMgr::new();
+            let mut transaction_mgr = TransactionMgr::new();
+            let mut mgr = TransactionsProcessor::new(&mut client_mgr, &mut transaction_mgr, loader)
+                .with_config(config.clone())
+                .with_as_of(as_of);
+
+            let report = mgr.apply_transaction_actions().unwrap();
+            assert_eq!(report.rejected(), 0);
+            assert_eq!(
+                mgr.transaction_mgr.get_dispute_state(1).unwrap(),
+                Some(expected),
+                "Overdue at the as-of point in time {:?}",
+                as_of
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_atomic_batch() {
+        let metrics = MetricsRegistry::default();
+        let mut mgr = processor(EngineConfig::default()).with_metrics(metrics.clone());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(10.)))
+            .is_ok());
+
+        // Withdrawal is rejected as the funds are held - the whole batch is rolled back
+        let report = mgr
+            .apply_batch(vec![
+                tx(TransactionType::Deposit, 2, 2, Some(5.)),
+                tx(TransactionType::Dispute, 1, 1, None),
+                tx(TransactionType::Withdrawal, 1, 3, Some(3.)),
+                tx(TransactionType::Deposit, 1, 4, Some(1.)),
+            ])
+            .unwrap();
+        assert_eq!(report.rows, 4);
+        assert_eq!(report.applied, 0);
+        assert_eq!(
+            report.rejections.get(&Rejection::InsufficientFunds),
+            Some(&1)
+        );
+        assert_eq!(
+            report.rejections.get(&Rejection::BatchRolledBack),
+            Some(&3),
+            "Applied and not processed actions"
+        );
+
+        // Rolled back rows aren't counted as applied
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.rows_read, 4);
+        assert_eq!(snapshot.applied.get("deposit"), Some(&1));
+        assert_eq!(snapshot.applied.get("dispute"), None);
+        assert_eq!(snapshot.rejections.get("batch_rolled_back"), Some(&2));
+        assert_eq!(snapshot.rejections.get("insufficient_funds"), Some(&1));
+
+        assert!(mgr.client_state_mgr.get_state(2).unwrap().is_none());
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 10., ulps = 4));
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+        assert!(!mgr.transaction_mgr.transaction_exist(2).unwrap());
+        assert_eq!(
+            mgr.transaction_mgr.get_dispute_state(1).unwrap(),
+            Some(DisputeState::NotDisputed)
+        );
+
+        // Rolled back transactions can be posted again
+        let report = mgr
+            .apply_batch(vec![
+                tx(TransactionType::Deposit, 2, 2, Some(5.)),
+                tx(TransactionType::Withdrawal, 1, 3, Some(3.)),
+            ])
+            .unwrap();
+        assert_eq!(report.applied, 2);
+        assert_eq!(report.rejected(), 0);
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 7., ulps = 4));
+        assert!(mgr.transaction_mgr.transaction_exist(2).unwrap());
+        assert_eq!(metrics.snapshot().applied.get("deposit"), Some(&2));
+    }
+
+    #[test]
+    pub fn test_action_logs() {
+        capture_logs();
+        let mut mgr = processor(EngineConfig::default());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(10.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 1, 2, Some(20.)))
+            .is_err());
+
+        let records = LOG_RECORDS.with(|d| d.borrow().clone());
+        assert_eq!(
+            records,
+            vec![
+                (
+                    log::Level::Info,
+                    "action applied".to_string(),
+                    "applied".to_string()
+                ),
+                (
+                    log::Level::Warn,
+                    "action rejected".to_string(),
+                    "insufficient_funds".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_reversal() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[fees]]
+            transaction_type = "deposit"
+            flat = 1.0
+
+            [[fees]]
+            transaction_type = "withdrawal"
+            flat = 0.5
+            "#,
+        )
+        .unwrap();
+        let mut mgr = processor(config);
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(10.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 1, 2, Some(4.)))
+            .is_ok());
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 4.5, ulps = 4));
+
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Reversal, 1, 2, Some(4.))),
+            Err(Rejection::InvalidAmount)
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Reversal, 2, 2, None)),
+            Err(Rejection::TxNotFound)
+        );
+
+        // Withdrawal is returned with its fee
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Reversal, 1, 2, None))
+            .is_ok());
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 9., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 9., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1., ulps = 4));
+        assert!(!c.is_locked(), "Reversal doesn't lock the account");
+        assert_eq!(
+            mgr.transaction_mgr.get_dispute_state(2).unwrap(),
+            Some(DisputeState::Reversed)
+        );
+
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Reversal, 1, 2, None)),
+            Err(Rejection::AlreadyReversed)
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Dispute, 1, 2, None)),
+            Err(Rejection::AlreadyReversed)
+        );
+
+        // Disputed deposit can't be reversed
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 5, Some(2.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 1, None))
+            .is_ok());
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Reversal, 1, 1, None)),
+            Err(Rejection::AlreadyDisputed)
+        );
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Resolve, 1, 1, None))
+            .is_ok());
+
+        // Deposit is taken back - its fee is refunded
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Reversal, 1, 1, None))
+            .is_ok());
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 1., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 1., ulps = 4));
+        assert!(approx_eq!(f32, c.fees, 1., ulps = 4));
+
+        // Deposit which is already spent can't be taken back
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 3, Some(20.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 1, 4, Some(10.)))
+            .is_ok());
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Reversal, 1, 3, None)),
+            Err(Rejection::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    pub fn test_partial_disputes() {
+        let mut mgr = processor(EngineConfig::default());
+
+        let held = |mgr: &TransactionsProcessor<TransactionTestLoader>| {
+            let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+            (c.available, c.held)
+        };
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(10.)))
+            .is_ok());
+
+        for amount in [-1., f32::NAN, f32::INFINITY] {
+            assert_eq!(
+                mgr.process_transaction(tx(TransactionType::Dispute, 1, 1, Some(amount))),
+                Err(Rejection::InvalidAmount)
+            );
+        }
+        assert_eq!(held(&mgr), (10., 0.));
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 1, Some(4.)))
+            .is_ok());
+        assert_eq!(held(&mgr), (6., 4.));
+
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Dispute, 1, 1, Some(7.))),
+            Err(Rejection::ExceedsUndisputed),
+            "Only 6 is not disputed"
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Dispute, 1, 1, None)),
+            Err(Rejection::AlreadyDisputed)
+        );
+
+        // Open dispute is extended by the partial one
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 1, Some(6.)))
+            .is_ok());
+        assert_eq!(held(&mgr), (0., 10.));
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Resolve, 1, 1, None))
+            .is_ok());
+        assert_eq!(held(&mgr), (10., 0.));
+
+        // Only the disputed portion is charged back
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 1, Some(3.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Chargeback, 1, 1, None))
+            .is_ok());
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(c.is_locked());
+        assert!(approx_eq!(f32, c.total, 7., ulps = 4));
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Dispute, 1, 1, Some(1.))),
+            Err(Rejection::AlreadyChargedBack)
+        );
+    }
+
+    #[test]
+    pub fn test_admin_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit_path = dir.path().join("audit.jsonl");
+        let mut mgr =
+            processor(EngineConfig::default()).with_audit(AuditLog::open(&audit_path).unwrap());
+
+        let admin = |transaction_type, operator: &str, reason: &str| TransactionDetails {
+            operator: Some(operator.to_string()),
+            reason: Some(reason.to_string()),
+            ..tx(transaction_type, 1, 100, None)
+        };
+        let status = |mgr: &TransactionsProcessor<'_, TransactionTestLoader>| {
+            mgr.client_state_mgr.get_state(1).unwrap().unwrap().status
+        };
+
+        assert_eq!(
+            mgr.process_transaction(admin(TransactionType::Lock, "ops-1", "fraud check")),
+            Err(Rejection::UnknownClient)
+        );
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(10.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 2, Some(5.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 1, None))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Chargeback, 1, 1, None))
+            .is_ok());
+        assert_eq!(status(&mgr), AccountStatus::Locked);
+
+        // Operator and reason are mandatory
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Unlock, 1, 100, None)),
+            Err(Rejection::MissingOperatorOrReason)
+        );
+        assert_eq!(
+            mgr.process_transaction(admin(TransactionType::Unlock, "ops-1", " ")),
+            Err(Rejection::MissingOperatorOrReason)
+        );
+        assert_eq!(
+            mgr.process_transaction(TransactionDetails {
+                amount: Some(1.),
+                ..admin(TransactionType::Unlock, "ops-1", "verified")
+            }),
+            Err(Rejection::InvalidAmount)
+        );
+
+        assert!(mgr
+            .process_transaction(admin(TransactionType::Unlock, "ops-1", "verified"))
+            .is_ok());
+        assert_eq!(status(&mgr), AccountStatus::Active);
+        assert_eq!(
+            mgr.process_transaction(admin(TransactionType::Unlock, "ops-1", "verified")),
+            Err(Rejection::StatusUnchanged)
+        );
+        assert!(mgr
+            .process_transaction(admin(TransactionType::Lock, "ops-2", "court order"))
+            .is_ok());
+        assert_eq!(status(&mgr), AccountStatus::Locked);
+
+        // Closed account accepts only disputes of earlier transactions
+        assert!(mgr
+            .process_transaction(admin(TransactionType::Close, "ops-2", "client request"))
+            .is_ok());
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Deposit, 1, 3, Some(1.))),
+            Err(Rejection::AccountClosed)
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Withdrawal, 1, 4, Some(1.))),
+            Err(Rejection::AccountClosed)
+        );
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 2, None))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Chargeback, 1, 2, None))
+            .is_ok());
+        assert_eq!(status(&mgr), AccountStatus::Closed);
+        assert_eq!(
+            mgr.process_transaction(admin(TransactionType::Unlock, "ops-1", "reopen")),
+            Err(Rejection::AccountClosed)
+        );
+
+        // Admin actions are recorded in the audit log with operator and reason
+        drop(mgr.take_audit());
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert!(audit.contains(
+            r#""transaction_type":"close","client":1,"tx":100,"amount":null,"timestamp":null,"operator":"ops-2","reason":"client request""#
+        ));
+        assert!(audit.contains(r#""outcome":"missing_operator_or_reason""#));
+    }
+
+    #[test]
+    pub fn test_transfer() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[fees]]
+            transaction_type = "transfer"
+            flat = 0.5
+            "#,
+        )
+        .unwrap();
+        let mut mgr = processor(config);
+        let transfer = |tx, amount, destination| TransactionDetails {
+            transaction_type: TransactionType::Transfer,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            destination,
+            ..Default::default()
+        };
+        let state = |mgr: &TransactionsProcessor<'_, TransactionTestLoader>, client| {
+            mgr.client_state_mgr.get_state(client).unwrap().unwrap()
+        };
+
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(10.)))
+            .is_ok());
+        assert_eq!(
+            mgr.process_transaction(transfer(2, 4., Some(2))),
+            Err(Rejection::UnknownClient),
+            "Destination should exist"
+        );
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 2, 3, Some(1.)))
+            .is_ok());
+        assert_eq!(
+            mgr.process_transaction(transfer(2, 4., None)),
+            Err(Rejection::InvalidDestination)
+        );
+        assert_eq!(
+            mgr.process_transaction(transfer(2, 4., Some(1))),
+            Err(Rejection::InvalidDestination)
+        );
+        assert_eq!(
+            mgr.process_transaction(transfer(2, 9.8, Some(2))),
+            Err(Rejection::InsufficientFunds),
+            "Fee should be covered as well"
+        );
+
+        assert!(mgr.process_transaction(transfer(2, 4., Some(2))).is_ok());
+        let (c1, c2) = (state(&mgr, 1), state(&mgr, 2));
+        assert!(approx_eq!(f32, c1.available, 5.5, ulps = 4));
+        assert!(approx_eq!(f32, c1.fees, 0.5, ulps = 4));
+        assert!(approx_eq!(f32, c2.available, 5., ulps = 4));
+        assert!(approx_eq!(f32, c2.total, 5., ulps = 4));
+        assert_eq!(
+            mgr.process_transaction(transfer(2, 1., Some(2))),
+            Err(Rejection::DuplicateTx)
+        );
+
+        // Dispute holds the funds on the receiving side
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Dispute, 2, 2, None)),
+            Err(Rejection::TxNotFound),
+            "Transfer is disputed by the source client"
+        );
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 2, None))
+            .is_ok());
+        let (c1, c2) = (state(&mgr, 1), state(&mgr, 2));
+        assert!(approx_eq!(f32, c1.available, 5.5, ulps = 4));
+        assert!(approx_eq!(f32, c1.held, 0., ulps = 4));
+        assert!(approx_eq!(f32, c2.available, 1., ulps = 4));
+        assert!(approx_eq!(f32, c2.held, 4., ulps = 4));
+
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Resolve, 1, 2, None))
+            .is_ok());
+        assert!(approx_eq!(f32, state(&mgr, 2).available, 5., ulps = 4));
+
+        // Chargeback returns the funds to the source and locks the destination
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 2, None))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Chargeback, 1, 2, None))
+            .is_ok());
+        let (c1, c2) = (state(&mgr, 1), state(&mgr, 2));
+        assert!(approx_eq!(f32, c1.available, 10., ulps = 4));
+        assert!(approx_eq!(f32, c1.fees, 0., ulps = 4));
+        assert!(!c1.is_locked());
+        assert!(approx_eq!(f32, c2.total, 1., ulps = 4));
+        assert!(approx_eq!(f32, c2.held, 0., ulps = 4));
+        assert!(c2.is_locked());
+
+        // Rolled back transfer restores both clients
+        let report = mgr
+            .apply_batch(vec![
+                transfer(4, 3., Some(2)),
+                tx(TransactionType::Withdrawal, 2, 5, Some(10.)),
+            ])
+            .unwrap();
+        assert_eq!(report.applied, 0);
+        assert!(approx_eq!(f32, state(&mgr, 1).available, 10., ulps = 4));
+        assert!(approx_eq!(f32, state(&mgr, 2).available, 1., ulps = 4));
+        assert!(!mgr.transaction_mgr.transaction_exist(4).unwrap());
+        assert_eq!(mgr.transaction_mgr.get_destination(4).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_accrual() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [tiers]
+            gold = [2]
+
+            [interest]
+            first_tx = 1000
+
+            [[interest.rates]]
+            percent = 1.0
+
+            [[interest.rates]]
+            tier = "gold"
+            percent = 2.0
+            "#,
+        )
+        .unwrap();
+        let mut mgr = processor(config).with_history();
+        let state = |mgr: &TransactionsProcessor<'_, TransactionTestLoader>, client| {
+            mgr.client_state_mgr.get_state(client).unwrap().unwrap()
+        };
+
+        for (client, id) in [(1, 1), (2, 2), (3, 3)] {
+            assert!(mgr
+                .process_transaction(tx(TransactionType::Deposit, client, id, Some(100.)))
+                .is_ok());
+        }
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 3, 3, None))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Chargeback, 3, 3, None))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Dispute, 1, 1, Some(50.)))
+            .is_ok());
+
+        // Input transactions can't use the reserved range
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Deposit, 1, 1000, Some(1.))),
+            Err(Rejection::ReservedTx)
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Interest, 1, 4, Some(1.))),
+            Err(Rejection::UnknownType)
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Accrue, 0, 0, Some(1.))),
+            Err(Rejection::InvalidAmount)
+        );
+
+        // Interest is accrued on available funds only, the locked account is skipped
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Accrue, 0, 0, None))
+            .is_ok());
+        assert!(approx_eq!(f32, state(&mgr, 1).available, 50.5, ulps = 4));
+        assert!(approx_eq!(f32, state(&mgr, 1).total, 100.5, ulps = 4));
+        assert!(approx_eq!(f32, state(&mgr, 2).available, 102., ulps = 4));
+        assert!(approx_eq!(f32, state(&mgr, 3).total, 0., ulps = 4));
+
+        let accrual = mgr
+            .transaction_mgr
+            .get_transaction(1000, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(accrual.transaction_type, TransactionType::Interest);
+        assert!(approx_eq!(f32, accrual.amount, 0.5, ulps = 4));
+        assert!(mgr
+            .transaction_mgr
+            .get_transaction(1001, 2)
+            .unwrap()
+            .is_some());
+        assert!(!mgr.transaction_mgr.transaction_exist(1002).unwrap());
+
+        // Next accrual continues the reserved range
+        assert_eq!(mgr.accrue_interest(), Ok(2));
+        assert!(mgr
+            .transaction_mgr
+            .get_transaction(1002, 1)
+            .unwrap()
+            .is_some());
+        assert!(mgr
+            .transaction_mgr
+            .get_transaction(1003, 2)
+            .unwrap()
+            .is_some());
+
+        // Generated transactions can't be referred by the input
+        for transaction_type in [
+            TransactionType::Dispute,
+            TransactionType::Reversal,
+            TransactionType::Approve,
+        ] {
+            assert_eq!(
+                mgr.process_transaction(tx(transaction_type, 1, 1000, None)),
+                Err(Rejection::ReservedTx)
+            );
+        }
+
+        let history = mgr.take_history().unwrap();
+        assert!(history
+            .of(1)
+            .iter()
+            .any(|d| d.tx == 1000 && d.transaction_type == TransactionType::Interest));
+    }
+
+    /// Custom rule denying all actions of the client
+    struct BlockedClientRule(u16);
+
+    impl FraudRule for BlockedClientRule {
+        fn name(&self) -> &str {
+            "blocked_client"
+        }
+
+        fn check(
+            &self,
+            action_details: &TransactionDetails,
+            _state: Option<&ClientState>,
+            _activity: &[&Activity],
+            _row: u64,
+        ) -> Verdict {
+            match action_details.client == self.0 {
+                true => Verdict::Deny,
+                false => Verdict::Allow,
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_fraud_rules() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[rules]]
+            rule = "velocity"
+            max_withdrawals = 2
+            window_rows = 10
+            verdict = "deny"
+
+            [[rules]]
+            rule = "large_amount"
+            max_percent = 50
+            min_amount = 100
+            verdict = "flag"
+            "#,
+        )
+        .unwrap();
+        let mut mgr = processor(config).with_rule(Box::new(BlockedClientRule(9)));
+
+        // Flagged deposit is applied
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(1000.)))
+            .is_ok());
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Deposit, 9, 2, Some(10.))),
+            Err(Rejection::RuleDenied)
+        );
+        assert!(mgr.client_state_mgr.get_state(9).unwrap().is_none());
+
+        for id in 3..5 {
+            assert!(mgr
+                .process_transaction(tx(TransactionType::Withdrawal, 1, id, Some(1.)))
+                .is_ok());
+        }
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Withdrawal, 1, 5, Some(1.))),
+            Err(Rejection::RuleDenied)
+        );
+        let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+        assert!(approx_eq!(f32, c.available, 998., ulps = 4));
+        assert!(!mgr.transaction_mgr.transaction_exist(5).unwrap());
+
+        // Rejected withdrawals are not counted - the window moves on
+        for id in 6..15 {
+            assert!(mgr
+                .process_transaction(tx(TransactionType::Deposit, 2, id, Some(1.)))
+                .is_ok());
+        }
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 1, 15, Some(1.)))
+            .is_ok());
+
+        // Transfers are checked as well
+        let mut transfer = tx(TransactionType::Transfer, 1, 16, Some(600.));
+        transfer.destination = Some(2);
+        assert!(mgr.process_transaction(transfer).is_ok());
+        assert!(
+            mgr.transaction_mgr.get_review(16, 1).is_some(),
+            "Large transfer is flagged"
+        );
+    }
+
+    #[test]
+    pub fn test_review_queue() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[rules]]
+            rule = "large_amount"
+            max_percent = 50
+            min_amount = 100
+            verdict = "flag"
+
+            [[rules]]
+            rule = "large_amount"
+            max_percent = 50
+            min_amount = 500
+            verdict = "hold"
+            "#,
+        )
+        .unwrap();
+        let mut mgr = processor(config);
+        let review = |transaction_type, id| TransactionDetails {
+            operator: Some("alice".to_string()),
+            reason: Some("checked".to_string()),
+            ..tx(transaction_type, 1, id, None)
+        };
+        let balance = |mgr: &TransactionsProcessor<_>| {
+            let c = mgr.client_state_mgr.get_state(1).unwrap().unwrap();
+            (c.available, c.pending, c.total)
+        };
+        let assert_balance = |mgr: &TransactionsProcessor<_>, expected: (f32, f32, f32)| {
+            let (available, pending, total) = balance(mgr);
+            assert!(approx_eq!(f32, available, expected.0, ulps = 4));
+            assert!(approx_eq!(f32, pending, expected.1, ulps = 4));
+            assert!(approx_eq!(f32, total, expected.2, ulps = 4));
+        };
+
+        // Flagged deposit is applied, held one is moved to pending funds
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 1, Some(50.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 2, Some(200.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 3, Some(1000.)))
+            .is_ok());
+        assert_balance(&mgr, (250., 1000., 1250.));
+
+        let reviews = mgr.transaction_mgr.reviews();
+        assert_eq!(reviews.len(), 2);
+        assert!(!reviews[0].held);
+        assert!(reviews[1].held);
+        assert_eq!(reviews[1].rules, "large_amount;large_amount");
+
+        // Held transaction can't be disputed until it's reviewed
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Dispute, 1, 3, None)),
+            Err(Rejection::UnderReview)
+        );
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Approve, 1, 3, None)),
+            Err(Rejection::MissingOperatorOrReason)
+        );
+        assert!(mgr
+            .process_transaction(review(TransactionType::Approve, 3))
+            .is_ok());
+        assert_balance(&mgr, (1250., 0., 1250.));
+        assert_eq!(
+            mgr.process_transaction(review(TransactionType::Approve, 3)),
+            Err(Rejection::NotUnderReview)
+        );
+        assert!(mgr
+            .process_transaction(review(TransactionType::Approve, 2))
+            .is_ok());
+        assert_balance(&mgr, (1250., 0., 1250.));
+
+        // Declined withdrawal is returned, declined deposit is taken back
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 1, 4, Some(700.)))
+            .is_ok());
+        assert_balance(&mgr, (550., 700., 1250.));
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 4))
+            .is_ok());
+        assert_balance(&mgr, (1250., 0., 1250.));
+        assert_eq!(
+            mgr.transaction_mgr.get_dispute_state(4).unwrap(),
+            Some(DisputeState::Reversed)
+        );
+
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 1, 5, Some(5000.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 5))
+            .is_ok());
+        assert_balance(&mgr, (1250., 0., 1250.));
+        assert!(mgr.transaction_mgr.reviews().is_empty());
+
+        // Rolled back batch doesn't leave its reviews
+        let report = mgr
+            .apply_batch(vec![
+                tx(TransactionType::Deposit, 1, 6, Some(3000.)),
+                tx(TransactionType::Withdrawal, 2, 7, Some(1.)),
+            ])
+            .unwrap();
+        assert_eq!(report.applied, 0);
+        assert_balance(&mgr, (1250., 0., 1250.));
+        assert!(mgr.transaction_mgr.reviews().is_empty());
+    }
+
+    #[test]
+    pub fn test_review_decline() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [disputes]
+            insufficient_funds = "hold"
+
+            [[fees]]
+            transaction_type = "deposit"
+            flat = 1.0
+
+            [[fees]]
+            transaction_type = "withdrawal"
+            flat = 0.5
+
+            [limits.clients.2]
+            max_window_withdrawal = 200.0
+
+            [[rules]]
+            rule = "large_amount"
+            max_percent = 50
+            min_amount = 100
+            verdict = "flag"
+
+            [[rules]]
+            rule = "large_amount"
+            max_percent = 50
+            min_amount = 500
+            verdict = "hold"
+            "#,
+        )
+        .unwrap();
+        let mut mgr = processor(config);
+        let review = |transaction_type, client, id| TransactionDetails {
+            operator: Some("alice".to_string()),
+            reason: Some("checked".to_string()),
+            ..tx(transaction_type, client, id, None)
+        };
+        let assert_balance =
+            |mgr: &TransactionsProcessor<_>, client, expected: (f32, f32, f32, f32)| {
+                let c = mgr.client_state_mgr.get_state(client).unwrap().unwrap();
+                assert!(approx_eq!(f32, c.available, expected.0, ulps = 4));
+                assert!(approx_eq!(f32, c.pending, expected.1, ulps = 4));
+                assert!(approx_eq!(f32, c.total, expected.2, ulps = 4));
+                assert!(approx_eq!(f32, c.fees, expected.3, ulps = 4));
+            };
+
+        // Held deposit is credited to pending funds even if available ones are overdrawn
+        for action in [
+            tx(TransactionType::Deposit, 1, 1, Some(100.)),
+            tx(TransactionType::Withdrawal, 1, 2, Some(98.5)),
+            tx(TransactionType::Dispute, 1, 1, None),
+            tx(TransactionType::Deposit, 1, 3, Some(600.)),
+        ] {
+            assert!(mgr.process_transaction(action).is_ok());
+        }
+        assert_balance(&mgr, 1, (-100., 599., 599., 2.5));
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Deposit, 1, 3, Some(600.))),
+            Err(Rejection::DuplicateTx)
+        );
+        assert_balance(&mgr, 1, (-100., 599., 599., 2.5));
+
+        // Declined held deposit is taken back with its fee
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 1, 3))
+            .is_ok());
+        assert_balance(&mgr, 1, (-100., 0., 0., 1.5));
+
+        // Declined flagged withdrawal is returned with its fee and leaves the window
+        for id in 10..13 {
+            assert!(mgr
+                .process_transaction(tx(TransactionType::Deposit, 2, id, Some(90.)))
+                .is_ok());
+        }
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 2, 13, Some(150.)))
+            .is_ok());
+        assert_balance(&mgr, 2, (116.5, 0., 116.5, 3.5));
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Withdrawal, 2, 14, Some(60.))),
+            Err(Rejection::WindowWithdrawalExceeded)
+        );
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 2, 13))
+            .is_ok());
+        assert_balance(&mgr, 2, (267., 0., 267., 3.));
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 2, 14, Some(60.)))
+            .is_ok());
+        assert_balance(&mgr, 2, (206.5, 0., 206.5, 3.5));
+
+        // Declined flagged deposit is taken back with its fee
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 2, 15, Some(200.)))
+            .is_ok());
+        assert_balance(&mgr, 2, (405.5, 0., 405.5, 4.5));
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 2, 15))
+            .is_ok());
+        assert_balance(&mgr, 2, (206.5, 0., 206.5, 3.5));
+        assert_eq!(
+            mgr.process_transaction(tx(TransactionType::Reversal, 2, 15, None)),
+            Err(Rejection::AlreadyReversed)
+        );
+
+        // Declined held withdrawal is released with its fee
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Deposit, 3, 20, Some(2000.)))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(review(TransactionType::Approve, 3, 20))
+            .is_ok());
+        assert!(mgr
+            .process_transaction(tx(TransactionType::Withdrawal, 3, 21, Some(1500.)))
+            .is_ok());
+        assert_balance(&mgr, 3, (498.5, 1500., 1998.5, 1.5));
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 3, 21))
+            .is_ok());
+        assert_balance(&mgr, 3, (1999., 0., 1999., 1.));
+
+        // Held transfer is credited to pending funds of the destination until it's approved
+        let transfer = |client, id, amount, destination| TransactionDetails {
+            destination: Some(destination),
+            ..tx(TransactionType::Transfer, client, id, Some(amount))
+        };
+        assert!(mgr.process_transaction(transfer(3, 30, 1500., 2)).is_ok());
+        assert_balance(&mgr, 3, (499., 0., 499., 1.));
+        assert_balance(&mgr, 2, (206.5, 1500., 1706.5, 3.5));
+        assert!(mgr
+            .process_transaction(review(TransactionType::Approve, 3, 30))
+            .is_ok());
+        assert_balance(&mgr, 2, (1706.5, 0., 1706.5, 3.5));
+
+        // Declined held transfer is moved back
+        assert!(mgr.process_transaction(transfer(2, 31, 1000., 3)).is_ok());
+        assert_balance(&mgr, 3, (499., 1000., 1499., 1.));
+        assert!(mgr
+            .process_transaction(review(TransactionType::Decline, 2, 31))
+            .is_ok());
+        assert_balance(&mgr, 2, (1706.5, 0., 1706.5, 3.5));
+        assert_balance(&mgr, 3, (499., 0., 499., 1.));
+    }
+
+    #[test]
+    pub fn test_policies() {
+        let process = |config: &str, actions: Vec<TransactionDetails>| {
+            let mut mgr = processor(EngineConfig::from_toml(config).unwrap());
+            let results: Vec<_> = actions
+                .into_iter()
+                .map(|d| mgr.process_transaction(d))
+                .collect();
+            (results, mgr.client_state_mgr.get_state(1).unwrap().unwrap())
+        };
+        let locked_account = || {
+            vec![
+                tx(TransactionType::Deposit, 1, 1, Some(10.)),
+                tx(TransactionType::Deposit, 1, 2, Some(5.)),
+                tx(TransactionType::Dispute, 1, 1, None),
+                tx(TransactionType::Chargeback, 1, 1, None),
+                tx(TransactionType::Deposit, 1, 3, Some(1.)),
+                tx(TransactionType::Dispute, 1, 2, None),
+            ]
+        };
+
+        // Locked account accepts all actions by default
+        let (results, c) = process("", locked_account());
+        assert!(results.iter().all(|d| d.is_ok()));
+        assert!(approx_eq!(f32, c.held, 5., ulps = 4));
+
+        let (results, c) = process("[accounts]\nlocked = \"reject_funds\"", locked_account());
+        assert_eq!(results[4], Err(Rejection::AccountLocked));
+        assert!(results[5].is_ok());
+        assert!(approx_eq!(f32, c.total, 5., ulps = 4));
+
+        let (results, c) = process("[accounts]\nlocked = \"reject_all\"", locked_account());
+        assert_eq!(results[4], Err(Rejection::AccountLocked));
+        assert_eq!(results[5], Err(Rejection::AccountLocked));
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+
+        // Amounts and fees are rounded, zero amount is accepted
+        let (results, c) = process(
+            "[amounts]\ndecimals = 2\nallow_zero = true\n[[fees]]\ntransaction_type = \"withdrawal\"\npercent = 1.5",
+            vec![
+                tx(TransactionType::Deposit, 1, 1, Some(10.126)),
+                tx(TransactionType::Deposit, 1, 2, Some(0.)),
+                tx(TransactionType::Withdrawal, 1, 3, Some(1.)),
+                tx(TransactionType::Deposit, 1, 4, Some(-1.)),
+            ],
+        );
+        assert_eq!(results[..3], [Ok(()), Ok(()), Ok(())]);
+        assert_eq!(results[3], Err(Rejection::InvalidAmount));
+        assert!(approx_eq!(f32, c.fees, 0.02, ulps = 4));
+        assert!(approx_eq!(f32, c.available, 9.11, ulps = 4));
+
+        let (results, _) = process(
+            "",
+            vec![
+                tx(TransactionType::Deposit, 1, 1, Some(1.)),
+                tx(TransactionType::Deposit, 1, 2, Some(0.)),
+            ],
+        );
+        assert_eq!(results[1], Err(Rejection::InvalidAmount));
+
+        // Dispute of already withdrawn deposit
+        let overdrawn = || {
+            vec![
+                tx(TransactionType::Deposit, 1, 1, Some(10.)),
+                tx(TransactionType::Withdrawal, 1, 2, Some(8.)),
+                tx(TransactionType::Dispute, 1, 1, None),
+            ]
+        };
+        let (results, c) = process("", overdrawn());
+        assert_eq!(results[2], Err(Rejection::InsufficientFunds));
+        assert!(approx_eq!(f32, c.held, 0., ulps = 4));
+
+        let (results, c) = process("[disputes]\ninsufficient_funds = \"hold\"", overdrawn());
+        assert!(results[2].is_ok());
+        assert!(approx_eq!(f32, c.available, -8., ulps = 4));
+        assert!(approx_eq!(f32, c.held, 10., ulps = 4));
+        assert!(approx_eq!(f32, c.total, 2., ulps = 4));
     }
 }
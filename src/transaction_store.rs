@@ -0,0 +1,626 @@
+use crate::disputes::DisputeState;
+use crate::transaction_details::{TransactionDetails, TransactionType};
+use anyhow::anyhow;
+use lru::LruCache;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::size_of;
+use std::num::NonZeroUsize;
+
+/// Number of records per page of the compact store
+const PAGE_SIZE: usize = 1024;
+
+/// Size of serialized record - kept packed by the compact and on-disk stores
+pub const RECORD_SIZE: usize = 35;
+
+/// Packed record of the compact store page
+type PackedRecord = [u8; RECORD_SIZE];
+
+/// Empty slot of the compact store page - stored records have non-zero type code
+const EMPTY_SLOT: PackedRecord = [0; RECORD_SIZE];
+
+/// Storage of transaction records - i.e. in memory, on disk, etc.
+pub trait TransactionStore {
+    /// Get record by transaction id
+    fn get(&self, tx: u32) -> Result<Option<TransactionRecord>, anyhow::Error>;
+    /// Insert new record. Returns false if record with the id is present
+    fn insert(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error>;
+    /// Update existing record. Returns false if there is no record with the id
+    fn update(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error>;
+    /// Remove the record - i.e. on rollback. Returns false if there is no record with the id
+    fn remove(&mut self, tx: u32) -> Result<bool, anyhow::Error>;
+
+    /// Whether record with the id is present
+    fn contains(&self, tx: u32) -> Result<bool, anyhow::Error> {
+        Ok(self.get(tx)?.is_some())
+    }
+}
+
+/// Compact transaction record - keeps only details needed for disputes and fee refunds
+/// Stores keep it packed to RECORD_SIZE bytes - see to_bytes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TransactionRecord {
+    /// Processing row of the transaction
+    pub row: u64,
+    /// Timestamp in epoch millis - i64::MIN if not provided
+    timestamp: i64,
+    /// Amount of the transaction
+    pub amount: f32,
+    /// Amount held by the open dispute - it may be less than the transaction amount
+    pub disputed: f32,
+    /// Fee charged for the transaction from its client - zero if not charged
+    pub fee: f32,
+    /// Client id
+    pub client: u16,
+    /// Destination client of the transfer - funds of the transfer are held on its side
+    pub destination: Option<u16>,
+    /// Transaction type - deposit, withdrawal, transfer or interest. Unknown marks empty slot
+    pub transaction_type: TransactionType,
+    /// Dispute state of the transaction
+    pub dispute: DisputeState,
+    /// Whether the fee is refunded, i.e. in case of chargeback
+    pub fee_refunded: bool,
+}
+
+impl TransactionRecord {
+    /// Create record from transaction details processed on the row
+    pub fn new(transaction: &TransactionDetails, row: u64) -> Self {
+        Self {
+            row,
+            timestamp: transaction.timestamp.unwrap_or(i64::MIN),
+            amount: transaction.amount.unwrap_or_default(),
+            disputed: 0.,
+            fee: 0.,
+            client: transaction.client,
+            destination: transaction.destination,
+            transaction_type: transaction.transaction_type,
+            dispute: DisputeState::NotDisputed,
+            fee_refunded: false,
+        }
+    }
+
+    /// Timestamp of the transaction, if provided
+    pub fn timestamp(&self) -> Option<i64> {
+        Some(self.timestamp).filter(|d| *d != i64::MIN)
+    }
+
+    /// Serialize record to fixed-size bytes
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0_u8; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.row.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.amount.to_le_bytes());
+        bytes[20..22].copy_from_slice(&self.client.to_le_bytes());
+        bytes[22] = type_to_code(self.transaction_type);
+        bytes[23] = dispute_to_code(self.dispute);
+        bytes[24..28].copy_from_slice(&self.disputed.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.fee.to_le_bytes());
+        bytes[32..34].copy_from_slice(&self.destination.unwrap_or_default().to_le_bytes());
+        bytes[34] = (self.destination.is_some() as u8) | (self.fee_refunded as u8) << 1;
+        bytes
+    }
+
+    /// Deserialize record from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let bytes: [u8; RECORD_SIZE] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Invalid record size: {}", bytes.len()))?;
+
+        Ok(Self {
+            row: u64::from_le_bytes(bytes[0..8].try_into()?),
+            timestamp: i64::from_le_bytes(bytes[8..16].try_into()?),
+            amount: f32::from_le_bytes(bytes[16..20].try_into()?),
+            client: u16::from_le_bytes(bytes[20..22].try_into()?),
+            transaction_type: type_from_code(bytes[22])?,
+            dispute: dispute_from_code(bytes[23])?,
+            disputed: f32::from_le_bytes(bytes[24..28].try_into()?),
+            fee: f32::from_le_bytes(bytes[28..32].try_into()?),
+            destination: Some(u16::from_le_bytes(bytes[32..34].try_into()?))
+                .filter(|_| bytes[34] & 1 != 0),
+            fee_refunded: bytes[34] & 2 != 0,
+        })
+    }
+}
+
+impl fmt::Display for TransactionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transaction_type: &str = self.transaction_type.into();
+        write!(
+            f,
+            "{} client {} amount {} row {}",
+            transaction_type, self.client, self.amount, self.row
+        )?;
+        if let Some(timestamp) = self.timestamp() {
+            write!(f, " timestamp {}", timestamp)?;
+        }
+        write!(f, " dispute {:?}", self.dispute)
+    }
+}
+
+fn type_to_code(transaction_type: TransactionType) -> u8 {
+    match transaction_type {
+        TransactionType::Deposit => 1,
+        TransactionType::Withdrawal => 2,
+        TransactionType::Transfer => 3,
+        TransactionType::Interest => 4,
+        _ => 0,
+    }
+}
+
+fn type_from_code(code: u8) -> Result<TransactionType, anyhow::Error> {
+    match code {
+        1 => Ok(TransactionType::Deposit),
+        2 => Ok(TransactionType::Withdrawal),
+        3 => Ok(TransactionType::Transfer),
+        4 => Ok(TransactionType::Interest),
+        _ => Err(anyhow!("Invalid transaction type code: {}", code)),
+    }
+}
+
+fn dispute_to_code(dispute: DisputeState) -> u8 {
+    match dispute {
+        DisputeState::NotDisputed => 0,
+        DisputeState::Open => 1,
+        DisputeState::Resolved => 2,
+        DisputeState::ChargedBack => 3,
+        DisputeState::Reversed => 4,
+    }
+}
+
+fn dispute_from_code(code: u8) -> Result<DisputeState, anyhow::Error> {
+    match code {
+        0 => Ok(DisputeState::NotDisputed),
+        1 => Ok(DisputeState::Open),
+        2 => Ok(DisputeState::Resolved),
+        3 => Ok(DisputeState::ChargedBack),
+        4 => Ok(DisputeState::Reversed),
+        _ => Err(anyhow!("Invalid dispute state code: {}", code)),
+    }
+}
+
+/// Plain in-memory store - transaction id to record mapping
+#[derive(Default)]
+pub struct HashMapTransactionStore {
+    id_to_record: HashMap<u32, TransactionRecord>,
+}
+
+impl TransactionStore for HashMapTransactionStore {
+    fn get(&self, tx: u32) -> Result<Option<TransactionRecord>, anyhow::Error> {
+        Ok(self.id_to_record.get(&tx).copied())
+    }
+
+    fn insert(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        match self.id_to_record.entry(tx) {
+            std::collections::hash_map::Entry::Occupied(_) => Ok(false),
+            std::collections::hash_map::Entry::Vacant(v) => {
+                v.insert(record);
+                Ok(true)
+            }
+        }
+    }
+
+    fn update(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        match self.id_to_record.get_mut(&tx) {
+            Some(d) => {
+                *d = record;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove(&mut self, tx: u32) -> Result<bool, anyhow::Error> {
+        Ok(self.id_to_record.remove(&tx).is_some())
+    }
+}
+
+/// Memory efficient transaction store for dense tx ids - packed records are kept in fixed-size pages keyed by tx id.
+/// Memory use is 35 bytes per transaction if tx ids are dense,
+/// for sparse ids - up to one page (35 KiB) per transaction, so the hash map store fits them better
+#[derive(Default)]
+pub struct CompactTransactionStore {
+    /// Page index (tx / PAGE_SIZE) to page records mapping
+    pages: HashMap<u32, Box<[PackedRecord]>>,
+    /// Number of stored records
+    len: usize,
+}
+
+impl CompactTransactionStore {
+    /// Get record by transaction id
+    pub fn get(&self, tx: u32) -> Option<TransactionRecord> {
+        let (page, slot) = Self::position(tx);
+        self.pages
+            .get(&page)
+            .map(|d| &d[slot])
+            .filter(|d| **d != EMPTY_SLOT)
+            // Stored slots are packed by the store itself - always valid
+            .and_then(|d| TransactionRecord::from_bytes(d).ok())
+    }
+
+    /// Insert new record. Returns false if record with the id is present
+    pub fn insert(&mut self, tx: u32, record: TransactionRecord) -> bool {
+        let (page, slot) = Self::position(tx);
+        let page = self
+            .pages
+            .entry(page)
+            .or_insert_with(|| vec![EMPTY_SLOT; PAGE_SIZE].into_boxed_slice());
+
+        if page[slot] != EMPTY_SLOT {
+            return false;
+        }
+
+        page[slot] = record.to_bytes();
+        self.len += 1;
+        true
+    }
+
+    /// Update existing record. Returns false if there is no record with the id
+    pub fn update(&mut self, tx: u32, record: TransactionRecord) -> bool {
+        let (page, slot) = Self::position(tx);
+        match self.pages.get_mut(&page).filter(|d| d[slot] != EMPTY_SLOT) {
+            Some(page) => {
+                page[slot] = record.to_bytes();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the record. Returns false if there is no record with the id
+    pub fn remove(&mut self, tx: u32) -> bool {
+        let (page, slot) = Self::position(tx);
+        match self.pages.get_mut(&page).filter(|d| d[slot] != EMPTY_SLOT) {
+            Some(page) => {
+                page[slot] = EMPTY_SLOT;
+                self.len -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether record with the id is present
+    pub fn contains(&self, tx: u32) -> bool {
+        self.get(tx).is_some()
+    }
+
+    /// Number of stored records
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Approximate memory use in bytes - pages and page index
+    pub fn memory_usage(&self) -> usize {
+        let page_bytes = PAGE_SIZE * size_of::<PackedRecord>();
+        let index_bytes = size_of::<(u32, Box<[PackedRecord]>)>() + 1;
+
+        self.pages.capacity() * index_bytes + self.pages.len() * page_bytes
+    }
+
+    /// Page index and slot in the page of the transaction
+    fn position(tx: u32) -> (u32, usize) {
+        (tx / PAGE_SIZE as u32, tx as usize % PAGE_SIZE)
+    }
+}
+
+impl TransactionStore for CompactTransactionStore {
+    fn get(&self, tx: u32) -> Result<Option<TransactionRecord>, anyhow::Error> {
+        Ok(CompactTransactionStore::get(self, tx))
+    }
+
+    fn insert(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        Ok(CompactTransactionStore::insert(self, tx, record))
+    }
+
+    fn update(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        Ok(CompactTransactionStore::update(self, tx, record))
+    }
+
+    fn remove(&mut self, tx: u32) -> Result<bool, anyhow::Error> {
+        Ok(CompactTransactionStore::remove(self, tx))
+    }
+}
+
+/// On-disk store based on embedded key-value database - for histories larger than RAM
+/// Clones share the same database
+#[derive(Clone)]
+pub struct DiskTransactionStore {
+    db: sled::Db,
+}
+
+impl DiskTransactionStore {
+    /// Open (or create) the store in the provided directory
+    pub fn open(path: &str) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            db: sled::open(path).map_err(|e| anyhow!(e))?,
+        })
+    }
+
+    /// Create store removed on drop - i.e. for tests
+    pub fn temporary() -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            db: sled::Config::new()
+                .temporary(true)
+                .open()
+                .map_err(|e| anyhow!(e))?,
+        })
+    }
+
+    /// Create temporary copy of the store in the provided directory - the store isn't changed
+    /// Copy is empty if there is no store in the directory
+    pub fn temporary_copy(path: &str) -> Result<Self, anyhow::Error> {
+        let copy = Self::temporary()?;
+        if std::path::Path::new(path).exists() {
+            let db = sled::open(path).map_err(|e| anyhow!(e))?;
+            for entry in db.iter() {
+                let (tx, record) = entry?;
+                copy.db.insert(tx, record)?;
+            }
+        }
+
+        Ok(copy)
+    }
+}
+
+impl TransactionStore for DiskTransactionStore {
+    fn get(&self, tx: u32) -> Result<Option<TransactionRecord>, anyhow::Error> {
+        match self.db.get(tx.to_be_bytes())? {
+            Some(bytes) => Ok(Some(TransactionRecord::from_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        let inserted = self.db.compare_and_swap(
+            tx.to_be_bytes(),
+            None as Option<&[u8]>,
+            Some(&record.to_bytes()[..]),
+        )?;
+        Ok(inserted.is_ok())
+    }
+
+    fn update(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        if !self.db.contains_key(tx.to_be_bytes())? {
+            return Ok(false);
+        }
+
+        self.db.insert(tx.to_be_bytes(), &record.to_bytes()[..])?;
+        Ok(true)
+    }
+
+    fn remove(&mut self, tx: u32) -> Result<bool, anyhow::Error> {
+        Ok(self.db.remove(tx.to_be_bytes())?.is_some())
+    }
+}
+
+/// LRU cache in front of another store - i.e. on-disk one
+/// Writes go through to the underlying store
+pub struct LruCachedStore<S: TransactionStore> {
+    store: S,
+    cache: RefCell<LruCache<u32, TransactionRecord>>,
+}
+
+impl<S: TransactionStore> LruCachedStore<S> {
+    /// Create cached store keeping up to capacity records in memory
+    pub fn new(store: S, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            store,
+            cache: RefCell::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl<S: TransactionStore> TransactionStore for LruCachedStore<S> {
+    fn get(&self, tx: u32) -> Result<Option<TransactionRecord>, anyhow::Error> {
+        if let Some(record) = self.cache.borrow_mut().get(&tx) {
+            return Ok(Some(*record));
+        }
+
+        let record = self.store.get(tx)?;
+        if let Some(record) = record {
+            self.cache.borrow_mut().put(tx, record);
+        }
+        Ok(record)
+    }
+
+    fn insert(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        let inserted = self.store.insert(tx, record)?;
+        if inserted {
+            self.cache.get_mut().put(tx, record);
+        }
+        Ok(inserted)
+    }
+
+    fn update(&mut self, tx: u32, record: TransactionRecord) -> Result<bool, anyhow::Error> {
+        let updated = self.store.update(tx, record)?;
+        if updated {
+            self.cache.get_mut().put(tx, record);
+        }
+        Ok(updated)
+    }
+
+    fn remove(&mut self, tx: u32) -> Result<bool, anyhow::Error> {
+        self.cache.get_mut().pop(&tx);
+        self.store.remove(tx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::disputes::DisputeState;
+    use crate::transaction_store::{
+        CompactTransactionStore, DiskTransactionStore, HashMapTransactionStore, LruCachedStore,
+        TransactionRecord, TransactionStore, RECORD_SIZE,
+    };
+    use crate::{TransactionDetails, TransactionType};
+
+    #[test]
+    pub fn test_compact_store() {
+        let mut store = CompactTransactionStore::default();
+        assert!(store.is_empty());
+        assert!(store.get(0).is_none());
+
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Withdrawal,
+            client: 3,
+            tx: 0,
+            amount: Some(1.5),
+            timestamp: Some(1000),
+            ..Default::default()
+        };
+        let mut record = TransactionRecord::new(&tx, 7);
+        assert_eq!(record.timestamp(), Some(1000));
+        assert!(store.insert(0, record));
+        assert!(!store.insert(0, record), "Record is present!");
+        assert!(store.insert(u32::MAX, record));
+        assert_eq!(store.len(), 2);
+        assert!(!store.contains(1), "Same page, but empty slot");
+
+        let stored = store.get(0).unwrap();
+        assert_eq!(stored, record);
+        assert_eq!(stored.row, 7);
+        assert_eq!(stored.client, 3);
+        assert_eq!(stored.transaction_type, TransactionType::Withdrawal);
+        assert_eq!(stored.dispute, DisputeState::NotDisputed);
+
+        record.dispute = DisputeState::Open;
+        assert!(store.update(u32::MAX, record));
+        assert!(!store.update(1, record), "No record to update!");
+        assert_eq!(store.get(u32::MAX).unwrap().dispute, DisputeState::Open);
+        assert_eq!(store.get(0).unwrap().dispute, DisputeState::NotDisputed);
+
+        let tx = TransactionDetails {
+            timestamp: None,
+            ..tx
+        };
+        assert!(TransactionRecord::new(&tx, 1).timestamp().is_none());
+        assert_eq!(store.memory_usage() / 1024 / RECORD_SIZE, 2, "Two pages");
+    }
+
+    /// Common checks of the store implementations
+    fn check_store<S: TransactionStore>(mut store: S) {
+        assert!(store.get(1).unwrap().is_none());
+
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(3.5),
+            timestamp: Some(-5),
+            ..Default::default()
+        };
+        let mut record = TransactionRecord::new(&tx, 4);
+        assert!(store.insert(1, record).unwrap());
+        assert!(!store.insert(1, record).unwrap(), "Record is present!");
+        assert!(store.contains(1).unwrap());
+        assert!(!store.contains(2).unwrap());
+        assert_eq!(store.get(1).unwrap(), Some(record));
+
+        record.dispute = DisputeState::ChargedBack;
+        assert!(store.update(1, record).unwrap());
+        assert!(!store.update(2, record).unwrap(), "No record to update!");
+        assert_eq!(store.get(1).unwrap(), Some(record));
+
+        assert!(store.remove(1).unwrap());
+        assert!(!store.remove(1).unwrap(), "Record is removed!");
+        assert!(store.get(1).unwrap().is_none());
+        assert!(store.insert(1, record).unwrap());
+    }
+
+    #[test]
+    pub fn test_stores() {
+        check_store(HashMapTransactionStore::default());
+        check_store(CompactTransactionStore::default());
+        check_store(DiskTransactionStore::temporary().unwrap());
+        check_store(LruCachedStore::new(HashMapTransactionStore::default(), 1));
+        check_store(LruCachedStore::new(
+            DiskTransactionStore::temporary().unwrap(),
+            10,
+        ));
+    }
+
+    #[test]
+    pub fn test_disk_store_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tx_history");
+        let path = path.to_str().unwrap();
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(2.),
+            ..Default::default()
+        };
+        let record = TransactionRecord::new(&tx, 1);
+
+        // Missing store isn't created
+        assert!(DiskTransactionStore::temporary_copy(path)
+            .unwrap()
+            .get(1)
+            .unwrap()
+            .is_none());
+        assert!(!std::path::Path::new(path).exists());
+
+        let mut store = DiskTransactionStore::open(path).unwrap();
+        assert!(store.insert(1, record).unwrap());
+        drop(store);
+
+        let mut copy = DiskTransactionStore::temporary_copy(path).unwrap();
+        assert_eq!(copy.get(1).unwrap(), Some(record));
+        assert!(copy.remove(1).unwrap());
+        drop(copy);
+        let store = DiskTransactionStore::open(path).unwrap();
+        assert_eq!(store.get(1).unwrap(), Some(record), "Store isn't changed");
+    }
+
+    #[test]
+    pub fn test_record_bytes() {
+        let tx = TransactionDetails {
+            transaction_type: TransactionType::Withdrawal,
+            client: 7,
+            tx: 1,
+            amount: Some(12.25),
+            ..Default::default()
+        };
+        let mut record = TransactionRecord::new(&tx, u64::MAX);
+        record.dispute = DisputeState::Open;
+        record.disputed = 2.5;
+        record.fee = 0.5;
+        record.fee_refunded = true;
+
+        let bytes = record.to_bytes();
+        assert_eq!(TransactionRecord::from_bytes(&bytes).unwrap(), record);
+        assert!(TransactionRecord::from_bytes(&bytes[1..]).is_err());
+
+        // Destination 0 is kept apart from the missing one
+        let transfer = TransactionDetails {
+            transaction_type: TransactionType::Transfer,
+            destination: Some(0),
+            ..tx
+        };
+        let transfer = TransactionRecord::new(&transfer, 2);
+        assert_eq!(
+            TransactionRecord::from_bytes(&transfer.to_bytes()).unwrap(),
+            transfer
+        );
+        assert_eq!(transfer.destination, Some(0));
+        assert!(TransactionRecord::from_bytes(&bytes)
+            .unwrap()
+            .destination
+            .is_none());
+
+        let mut bytes = bytes;
+        bytes[22] = 0;
+        assert!(
+            TransactionRecord::from_bytes(&bytes).is_err(),
+            "Empty record type"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::csv_processor::ParseError;
+use crate::report::ProcessingReport;
+use crate::ClientState;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Lock status change of the client account
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LockChange {
+    pub client: u16,
+    /// Lock status after processing - new clients are considered as unlocked before
+    pub locked: bool,
+}
+
+/// Result of the dry-run of the file - nothing is posted
+#[derive(Default, Debug)]
+pub struct ValidationReport {
+    /// Rows which can't be parsed
+    pub parse_errors: Vec<ParseError>,
+    /// Applied and rejected actions
+    pub processing: ProcessingReport,
+    /// Clients whose lock status would change - sorted by client id
+    pub lock_changes: Vec<LockChange>,
+}
+
+impl ValidationReport {
+    /// Create report based on clients states before and after the processing
+    pub fn new(
+        parse_errors: Vec<ParseError>,
+        processing: ProcessingReport,
+        before: &[ClientState],
+        after: &[ClientState],
+    ) -> Self {
+        let locked_before: HashMap<u16, bool> =
+            before.iter().map(|d| (d.client, d.is_locked())).collect();
+
+        let mut lock_changes: Vec<LockChange> = after
+            .iter()
+            .filter(|d| locked_before.get(&d.client).copied().unwrap_or(false) != d.is_locked())
+            .map(|d| LockChange {
+                client: d.client,
+                locked: d.is_locked(),
+            })
+            .collect();
+        lock_changes.sort_by_key(|d| d.client);
+
+        Self {
+            parse_errors,
+            processing,
+            lock_changes,
+        }
+    }
+
+    /// Whether the file can be posted without parse errors and rejections
+    pub fn is_clean(&self) -> bool {
+        self.parse_errors.is_empty() && self.processing.rejected() == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows: {}", self.processing.rows)?;
+        writeln!(f, "applied: {}", self.processing.applied)?;
+
+        writeln!(f, "parse errors: {}", self.parse_errors.len())?;
+        for e in &self.parse_errors {
+            writeln!(f, "  line {}: {}", e.line, e.message)?;
+        }
+
+        writeln!(f, "rejections: {}", self.processing.rejected())?;
+        for (reason, count) in &self.processing.rejections {
+            writeln!(f, "  {}: {}", reason, count)?;
+        }
+
+        writeln!(f, "lock status changes: {}", self.lock_changes.len())?;
+        for d in &self.lock_changes {
+            let status = if d.locked { "locked" } else { "unlocked" };
+            writeln!(f, "  client {}: {}", d.client, status)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::csv_processor::ParseError;
+    use crate::rejection::Rejection;
+    use crate::report::ProcessingReport;
+    use crate::validation::{LockChange, ValidationReport};
+    use crate::{AccountStatus, ClientState};
+
+    fn state(client: u16, locked: bool) -> ClientState {
+        ClientState {
+            client,
+            status: match locked {
+                true => AccountStatus::Locked,
+                false => AccountStatus::Active,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_validation_report() {
+        let mut processing = ProcessingReport::default();
+        processing.record(&Ok(()));
+        processing.record(&Err(Rejection::TxNotFound));
+
+        let before = [state(1, false), state(2, true), state(3, false)];
+        let after = [
+            state(1, true),
+            state(2, false),
+            state(3, false),
+            state(4, true),
+            state(5, false),
+        ];
+        let parse_errors = vec![ParseError {
+            line: 3,
+            message: "invalid digit".to_string(),
+        }];
+        let report = ValidationReport::new(parse_errors, processing, &before, &after);
+
+        assert!(!report.is_clean());
+        assert_eq!(
+            report.lock_changes,
+            vec![
+                LockChange {
+                    client: 1,
+                    locked: true
+                },
+                LockChange {
+                    client: 2,
+                    locked: false
+                },
+                LockChange {
+                    client: 4,
+                    locked: true
+                },
+            ]
+        );
+
+        let text = report.to_string();
+        assert!(text.contains("line 3: invalid digit"));
+        assert!(text.contains("tx_not_found: 1"));
+        assert!(text.contains("client 2: unlocked"));
+        assert!(
+            !text.contains("client 5"),
+            "New unlocked client isn't a change!"
+        );
+    }
+}